@@ -0,0 +1,40 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use vtk::{Frame, Tlv, TlvKey};
+
+/// A VRP answer as terminals send it: operation, amount, product and a full banking slip.
+fn receipt_tlv() -> Tlv {
+    let slip: String = (0..250).map(|i| if i % 32 == 31 {'\n'} else {(b'A' + (i % 26) as u8) as char}).collect();
+    let mut tlv = Tlv::new();
+    tlv.set_str(TlvKey::MsgName, "VRP");
+    tlv.set_bin(TlvKey::OperationNum, &[0x00, 0x2A]);
+    tlv.set_bin(TlvKey::AmountInMinorCurrencyUnit, &[0x01, 0xF4]);
+    tlv.set_bin(TlvKey::ProductId, &[0x07]);
+    tlv.set_str(TlvKey::ProductName, "Espresso doppio");
+    tlv.set_str(TlvKey::BankingReceipt, &slip);
+    tlv
+}
+
+fn tlv(c: &mut Criterion) {
+    let tlv = receipt_tlv();
+    let raw = tlv.clone().serialize().unwrap();
+    let mut group = c.benchmark_group("tlv");
+    group.throughput(Throughput::Bytes(raw.len() as u64));
+    group.bench_function("serialize", |b| b.iter(|| black_box(tlv.clone()).serialize()));
+    group.bench_function("deserialize", |b| b.iter(|| Tlv::deserialize(black_box(&raw))));
+    group.finish();
+}
+
+fn frame(c: &mut Criterion) {
+    let frame = Frame::new("VRP", receipt_tlv());
+    let raw = frame.encode().unwrap();
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Bytes(raw.len() as u64));
+    group.bench_function("encode", |b| b.iter(|| black_box(&frame).encode()));
+    group.bench_function("decode", |b| b.iter(|| Frame::decode(black_box(&raw)).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, tlv, frame);
+criterion_main!(benches);
@@ -0,0 +1,272 @@
+use std::{io::{Error, Write}, net::TcpStream, sync::{mpsc::{self, RecvTimeoutError, Sender}, Mutex, TryLockError}, time::Duration};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use vtk::{Authorize, Event, Payment, Product, Tlv, Vtk, VtkError};
+
+use crate::{http::{self, Request}, reload::Source, supervisor, webhook::Webhook};
+
+/// One terminal the daemon drives, addressed as `/terminals/{name}/...`.
+pub struct Terminal {
+    pub name: String,
+    pub vtk: Mutex<Vtk>,
+    pub source: Source,
+}
+
+/// An event stream client and the terminal it follows, `None` for all of them.
+pub struct Listener {
+    pub terminal: Option<String>,
+    pub tx: Sender<String>,
+}
+
+pub struct Bridge {
+    pub terminals: Vec<Terminal>,
+    pub listeners: Mutex<Vec<Listener>>,
+    pub webhook: Option<Webhook>,
+    /// Asked with `X-Operator-Token` before calls that settle sales or take a terminal out of service; `None` lets
+    /// them all through.
+    pub authorize: Option<Authorize>,
+}
+
+impl Bridge {
+    fn terminal(&self, name: &str) -> Option<&Terminal> {
+        self.terminals.iter().find(|t| t.name == name)
+    }
+
+    /// Sends `event` from `terminal` to the listeners following it.
+    pub fn publish(&self, terminal: &str, event: &Event) {
+        let mut data = event_json(event);
+        data["terminal"] = Value::from(terminal);
+        let data = data.to_string();
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.retain(|l| l.terminal.as_ref().is_some_and(|t| t != terminal) || l.tx.send(data.clone()).is_ok());
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Qr {
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct Sell {
+    amount: u64,
+    product_id: Option<u32>,
+    product_name: Option<String>,
+    #[serde(default = "default_sale_timeout")]
+    timeout_secs: u64,
+}
+
+fn default_sale_timeout() -> u64 {
+    60
+}
+
+#[derive(Deserialize)]
+struct Finalize {
+    operation: u32,
+    amount: u64,
+}
+
+#[derive(Deserialize)]
+struct Abort {
+    operation: u32,
+}
+
+pub fn event_json(event: &Event) -> Value {
+    match event {
+        Event::Degraded {silent_for} => json!({"event": "degraded", "silent_for_ms": silent_for.as_millis() as u64}),
+        Event::Recovered => json!({"event": "recovered"}),
+        Event::Anomaly(a) => json!({"event": "anomaly", "detail": a.to_string()}),
+        Event::OperatingHours {open} => json!({"event": "operating_hours", "open": open}),
+        Event::ClockSkew {skew_ms} => json!({"event": "clock_skew", "skew_ms": skew_ms}),
+        Event::TerminalSilent {interval, silent_for} =>
+            json!({"event": "terminal_silent", "interval_ms": interval.as_millis() as u64, "silent_for_ms": silent_for.as_millis() as u64}),
+        Event::CircuitOpen {failures, cool_down} => json!({"event": "circuit_open", "failures": failures, "cool_down_ms": cool_down.as_millis() as u64}),
+        Event::CircuitClosed => json!({"event": "circuit_closed"}),
+    }
+}
+
+fn payment_json(p: &Payment) -> Value {
+    json!({"operation": p.operation, "amount": p.amount, "approved": p.approved, "response": p.response.to_json()})
+}
+
+fn parse<'a, T: Deserialize<'a>>(req: &'a Request) -> Result<T, (u16, Value)> {
+    serde_json::from_slice(&req.body).map_err(|e| (400, json!({"error": e.to_string()})))
+}
+
+/// A driver error as a 502, with `VtkError::code` when the driver gave one, or as a 503 with `retry_after_ms`
+/// when the driver will take the request again later.
+fn failed(e: Error) -> (u16, Value) {
+    let code = VtkError::of(&e).map(VtkError::code);
+    match VtkError::of(&e).and_then(VtkError::retry_after) {
+        Some(after) => (503, json!({"error": e.to_string(), "code": code, "retry_after_ms": after.as_millis() as u64})),
+        None => (502, json!({"error": e.to_string(), "code": code})),
+    }
+}
+
+fn terminal(res: Result<Tlv, Error>) -> Result<Value, (u16, Value)> {
+    res.map(|tlv| json!({"response": tlv.to_json()})).map_err(failed)
+}
+
+/// 403 unless `Bridge::authorize` lets `req` run `operation`.
+fn authorized(bridge: &Bridge, req: &Request, operation: &str) -> Result<(), (u16, Value)> {
+    match &bridge.authorize {
+        Some(authorize) if !authorize(operation, req.operator.as_deref()) =>
+            Err((403, json!({"error": format!("{} needs operator authorization", operation)}))),
+        _ => Ok(()),
+    }
+}
+
+/// Reports a settled sale to the webhook, if one is configured.
+fn notify(bridge: &Bridge, terminal: &Terminal, event: &str, res: &Result<Tlv, Error>, mut payload: Value) {
+    if let (Some(hook), Ok(tlv)) = (&bridge.webhook, res) {
+        payload["response"] = tlv.to_json();
+        payload["terminal"] = Value::from(terminal.name.as_str());
+        hook.fire(event, payload);
+    }
+}
+
+fn status(terminal: &Terminal) -> Value {
+    match terminal.vtk.try_lock() {
+        Ok(vtk) => {
+            let c = vtk.counters();
+            json!({
+                "name": terminal.name,
+                "busy": false,
+                "host": vtk.config().host,
+                "port": vtk.config().port,
+                "connected": vtk.is_connected(),
+                "degraded": vtk.is_degraded(),
+                "suspect": vtk.is_suspect(),
+                "frames_sent": c.frames_sent,
+                "frames_received": c.frames_received,
+                "errors": c.errors,
+                "round_trip_ms": vtk.last_round_trip().map(|d| d.as_millis() as u64),
+                "circuit_open_ms": vtk.circuit_open().map(|d| d.as_millis() as u64),
+            })
+        },
+        Err(TryLockError::WouldBlock) => json!({"name": terminal.name, "busy": true}),
+        Err(TryLockError::Poisoned(_)) => json!({"name": terminal.name, "busy": false, "error": "driver poisoned"}),
+    }
+}
+
+/// Liveness, or with `ready` whether sales can go through: a busy driver is ready, a degraded terminal is not.
+fn health<'a>(terminals: impl IntoIterator<Item = &'a Terminal>, ready: bool) -> Result<Value, (u16, Value)> {
+    for terminal in terminals {
+        if !supervisor::healthy(terminal) {
+            return Err((503, json!({"ok": false, "terminal": terminal.name, "error": "driver poisoned"})));
+        }
+        if !ready {continue;}
+        if let Ok(vtk) = terminal.vtk.try_lock() {
+            if vtk.is_degraded() {
+                return Err((503, json!({"ok": false, "terminal": terminal.name, "error": "terminal not answering"})));
+            }
+            if vtk.circuit_open().is_some() {
+                return Err((503, json!({"ok": false, "terminal": terminal.name, "error": "circuit open"})));
+            }
+        }
+    }
+    Ok(json!({"ok": true}))
+}
+
+/// The terminal a request is for and the rest of its path: `/terminals/{name}/sell` or, with a single
+/// terminal, plain `/sell`.
+fn target<'a>(bridge: &'a Bridge, path: &'a str) -> Result<(&'a Terminal, &'a str), (u16, Value)> {
+    let Some(rest) = path.strip_prefix("/terminals/") else {
+        return match &bridge.terminals[..] {
+            [only] => Ok((only, path)),
+            _ => Err((404, json!({"error": format!("several terminals, use /terminals/{{name}}{}", path)}))),
+        };
+    };
+    let (name, path) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+    let terminal = bridge.terminal(name).ok_or_else(|| (404, json!({"error": format!("no terminal named {}", name)})))?;
+    Ok((terminal, path))
+}
+
+fn route(req: &Request, bridge: &Bridge) -> Result<Value, (u16, Value)> {
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/terminals") => return Ok(Value::from(bridge.terminals.iter().map(status).collect::<Vec<_>>())),
+        ("GET", "/healthz") => return health(&bridge.terminals, false),
+        ("GET", "/readyz") => return health(&bridge.terminals, true),
+        (_, "/terminals" | "/healthz" | "/readyz") => return Err((405, json!({"error": "method not allowed"}))),
+        _ => (),
+    }
+    let (term, path) = target(bridge, &req.path)?;
+    let lock = || term.vtk.lock().map_err(|_| (503, json!({"error": "driver poisoned"})));
+    match (req.method.as_str(), path) {
+        ("GET", "/status") => Ok(status(term)),
+        ("GET", "/healthz") => health([term], false),
+        ("GET", "/readyz") => health([term], true),
+        ("POST", "/idle") => terminal(lock()?.idle(None)),
+        ("POST", "/disable") => {
+            authorized(bridge, req, "disable")?;
+            terminal(lock()?.disable())
+        },
+        ("POST", "/qr") => {
+            let qr: Qr = parse(req)?;
+            terminal(lock()?.show_qr(&qr.data))
+        },
+        ("POST", "/sell") => {
+            let sell: Sell = parse(req)?;
+            let product = Product {id: sell.product_id, name: sell.product_name};
+            lock()?.request_payment(sell.amount, Some(&product), Duration::from_secs(sell.timeout_secs))
+                .map(|p| payment_json(&p))
+                .map_err(failed)
+        },
+        ("POST", "/finalize") => {
+            let fin: Finalize = parse(req)?;
+            let res = lock()?.finalize(fin.operation, fin.amount);
+            notify(bridge, term, "sale.finalized", &res, json!({"operation": fin.operation, "amount": fin.amount}));
+            terminal(res)
+        },
+        ("POST", "/abort") => {
+            let abr: Abort = parse(req)?;
+            let res = lock()?.abort(abr.operation);
+            notify(bridge, term, "sale.aborted", &res, json!({"operation": abr.operation}));
+            terminal(res)
+        },
+        (_, "/status" | "/healthz" | "/readyz" | "/idle" | "/disable" | "/qr" | "/sell" | "/finalize" | "/abort" | "/events") => Err((405, json!({"error": "method not allowed"}))),
+        _ => Err((404, json!({"error": "not found"}))),
+    }
+}
+
+/// Streams driver events as server-sent events until the client goes away.
+fn events(mut stream: &TcpStream, bridge: &Bridge, terminal: Option<String>) -> Result<(), Error> {
+    let (tx, rx) = mpsc::channel();
+    if let Ok(mut listeners) = bridge.listeners.lock() {
+        listeners.push(Listener {terminal, tx});
+    }
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n")?;
+    loop {
+        match rx.recv_timeout(Duration::from_secs(15)) {
+            Ok(data) => write!(stream, "data: {}\n\n", data)?,
+            Err(RecvTimeoutError::Timeout) => write!(stream, ": keepalive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+pub fn handle(stream: TcpStream, bridge: &Bridge) {
+    let req = match http::read_request(&stream) {
+        Ok(req) => req,
+        Err(e) => {
+            _ = http::respond(&stream, 400, &json!({"error": e.to_string()}));
+            return;
+        },
+    };
+    if req.method == "GET" && req.path == "/events" {
+        _ = events(&stream, bridge, None);
+        return;
+    }
+    if let (Ok((terminal, "/events")), "GET") = (target(bridge, &req.path), req.method.as_str()) {
+        _ = events(&stream, bridge, Some(terminal.name.clone()));
+        return;
+    }
+    let (status, body) = match route(&req, bridge) {
+        Ok(body) => (200, body),
+        Err(err) => err,
+    };
+    _ = http::respond(&stream, status, &body);
+}
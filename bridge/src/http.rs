@@ -0,0 +1,61 @@
+use std::{io::{BufRead, BufReader, Error, ErrorKind, Read, Write}, net::TcpStream, time::Duration};
+
+use serde_json::Value;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// `X-Operator-Token`, an operator's PIN or token for management calls.
+    pub operator: Option<String>,
+    pub body: Vec<u8>,
+}
+
+const MAX_BODY: usize = 64 * 1024;
+/// How long any one read of a request may wait, so a client that connects and goes quiet does not hold a thread.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn read_request(stream: &TcpStream) -> Result<Request, Error> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(Error::new(ErrorKind::InvalidData, "malformed request line"));
+    };
+    let (method, path) = (String::from(method), String::from(path));
+    let mut len = 0;
+    let mut operator = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {break;}
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                len = value.trim().parse().map_err(|_| Error::new(ErrorKind::InvalidData, "bad content-length"))?;
+            } else if name.eq_ignore_ascii_case("x-operator-token") {
+                operator = Some(String::from(value.trim()));
+            }
+        }
+    }
+    if len > MAX_BODY {
+        return Err(Error::new(ErrorKind::InvalidData, "body too large"));
+    }
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+    Ok(Request {method, path, operator, body})
+}
+
+pub fn respond(mut stream: &TcpStream, status: u16, body: &Value) -> Result<(), Error> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    let body = body.to_string();
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, reason, body.len(), body)
+}
@@ -0,0 +1,144 @@
+mod api;
+mod http;
+mod reload;
+#[cfg(windows)]
+mod service;
+mod supervisor;
+mod webhook;
+
+use std::{env, io::{Error, ErrorKind}, net::TcpListener, path::PathBuf, process, sync::{Arc, Mutex}, thread};
+
+use vtk::{operator_token, Vtk};
+
+use crate::{api::{Bridge, Terminal}, reload::Source, webhook::Webhook};
+
+const USAGE: &str = "usage: vtk-bridged [--config FILE] [--host HOST] [--port PORT] [--terminal NAME=HOST:PORT]...
+                   [--listen ADDR] [--webhook URL] [--service]
+
+Owns the terminal connection and serves a JSON API on ADDR (default 127.0.0.1:8080):
+    GET  /status    driver state and counters
+    POST /idle      POST /disable
+    POST /qr        {\"data\": \"...\"}
+    POST /sell      {\"amount\": 100, \"product_id\": 1, \"product_name\": \"Tea\", \"timeout_secs\": 60}
+    POST /finalize  {\"operation\": 1, \"amount\": 100}
+    POST /abort     {\"operation\": 1}
+    GET  /events    server-sent driver events
+    GET  /healthz   200 while the daemon works, for the supervisor
+    GET  /readyz    200 while the terminal answers
+Failed terminal calls answer 502 with {\"error\": \"...\", \"code\": N}; N, null for plain I/O errors,
+stays the same across releases.
+Calls the driver turned away only for now (a pending answer, an open circuit) answer 503 with
+\"retry_after_ms\" added, so the machine can ask the customer to wait.
+
+With --terminal, one daemon drives several terminals that share the rest of the configuration;
+the calls above are then made as /terminals/NAME/sell and so on, and GET /terminals lists them.
+Events carry the terminal's name. A single terminal is named default.
+
+With --webhook, every finalized or aborted sale is POSTed to URL as JSON;
+set VTK_WEBHOOK_SECRET to sign the body with HMAC-SHA256 in X-Vtk-Signature.
+
+Set VTK_OPERATOR_TOKEN, e.g. to an operator PIN, to refuse POST /disable
+with 403 unless the request carries it in X-Operator-Token.
+
+Under systemd use Type=notify; WatchdogSec= is honoured. SIGINT and SIGTERM disable
+the terminal before exiting. SIGHUP, or saving the config file, reloads the
+configuration once the exchange in flight is over. On Windows, --service runs under the service control manager.";
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn main() {
+    #[cfg(windows)]
+    if env::args().any(|a| a == "--service") {
+        if let Err(e) = service::run() {
+            eprintln!("vtk-bridged: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    if let Err(e) = run(env::args().skip(1).collect()) {
+        eprintln!("vtk-bridged: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), Error> {
+    let (bridge, listener) = setup(args)?;
+    supervisor::start(&bridge)?;
+    serve(listener, bridge);
+    Ok(())
+}
+
+/// `NAME=HOST:PORT`.
+fn terminal_arg(arg: &str) -> Option<(String, String, u16)> {
+    let (name, addr) = arg.split_once('=')?;
+    let (host, port) = addr.rsplit_once(':')?;
+    Some((String::from(name), String::from(host), port.parse().ok()?)).filter(|t| !t.0.is_empty() && !t.0.contains('/'))
+}
+
+/// Parses the options, creates the drivers and binds the API socket.
+fn setup(args: Vec<String>) -> Result<(Arc<Bridge>, TcpListener), Error> {
+    let mut args = args.into_iter();
+    let mut path = None;
+    let mut host = None;
+    let mut port = None;
+    let mut listen = String::from("127.0.0.1:8080");
+    let mut webhook = None;
+    let mut named = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => path = Some(PathBuf::from(args.next().unwrap_or_else(|| usage()))),
+            "--host" => host = Some(args.next().unwrap_or_else(|| usage())),
+            "--port" => port = Some(args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| usage())),
+            "--listen" => listen = args.next().unwrap_or_else(|| usage()),
+            "--webhook" => webhook = Some(args.next().unwrap_or_else(|| usage())),
+            "--terminal" => named.push(args.next().as_deref().and_then(terminal_arg).unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    let sources = if named.is_empty() {
+        vec![(String::from("default"), Source {path: path.clone(), host, port})]
+    } else {
+        if host.is_some() || port.is_some() {usage();}
+        named.into_iter().map(|(name, host, port)| (name, Source {path: path.clone(), host: Some(host), port: Some(port)})).collect()
+    };
+    let mut terminals = Vec::new();
+    let mut subscriptions = Vec::new();
+    for (name, source) in sources {
+        let config = source.load()?;
+        if config.host.is_empty() {
+            usage();
+        }
+        if terminals.iter().any(|t: &Terminal| t.name == name) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("terminal {} given twice", name)));
+        }
+        let mut vtk = Vtk::from_config(config);
+        subscriptions.push((name.clone(), vtk.subscribe()));
+        terminals.push(Terminal {name, vtk: Mutex::new(vtk), source});
+    }
+    let webhook = webhook.map(|url| Webhook {url, secret: env::var("VTK_WEBHOOK_SECRET").ok().map(String::into_bytes)});
+    let authorize = env::var("VTK_OPERATOR_TOKEN").ok().filter(|t| !t.is_empty()).map(operator_token);
+    let bridge = Arc::new(Bridge {terminals, listeners: Mutex::new(Vec::new()), webhook, authorize});
+    for (name, events) in subscriptions {
+        let bridge = bridge.clone();
+        thread::spawn(move || {
+            for event in events {
+                bridge.publish(&name, &event);
+            }
+        });
+    }
+    reload::watch(&bridge, path);
+    let listener = TcpListener::bind(&listen)?;
+    eprintln!("vtk-bridged: listening on {}", listen);
+    Ok((bridge, listener))
+}
+
+fn serve(listener: TcpListener, bridge: Arc<Bridge>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {continue;};
+        let bridge = bridge.clone();
+        thread::spawn(move || api::handle(stream, &bridge));
+    }
+}
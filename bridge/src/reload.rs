@@ -0,0 +1,85 @@
+//! Re-reading the configuration on SIGHUP or when the file changes.
+
+use std::{fs, io::Error, path::{Path, PathBuf}, sync::Arc, thread, time::{Duration, SystemTime}};
+
+use vtk::Config;
+
+use crate::api::{Bridge, Terminal};
+
+/// How often the config file is checked for changes.
+const POLL: Duration = Duration::from_secs(2);
+
+/// Where a terminal's configuration comes from: the file, then `VTK_*` variables, then the command line.
+#[derive(Clone)]
+pub struct Source {
+    pub path: Option<PathBuf>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl Source {
+    pub fn load(&self) -> Result<Config, Error> {
+        let mut config = match &self.path {
+            Some(path) => Config::from_toml(path)?,
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        if let Some(host) = &self.host {
+            config.host.clone_from(host);
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        Ok(config)
+    }
+}
+
+/// Waits for the exchange in flight, a customer paying included, then swaps the configuration in.
+/// A file that does not parse leaves the running configuration alone.
+fn reload_terminal(terminal: &Terminal) {
+    let name = &terminal.name;
+    let config = match terminal.source.load() {
+        Ok(config) if config.host.is_empty() => {
+            eprintln!("vtk-bridged: {}: reload: no terminal host, keeping the running configuration", name);
+            return;
+        },
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("vtk-bridged: {}: reload: {}, keeping the running configuration", name, e);
+            return;
+        },
+    };
+    let Ok(mut vtk) = terminal.vtk.lock() else {return;};
+    if *vtk.config() == config {return;}
+    let moved = vtk.reconfigure(config);
+    eprintln!("vtk-bridged: {}: configuration reloaded{}", name, if moved {", reconnecting to the terminal"} else {""});
+}
+
+/// Reloads every terminal, each as soon as its own exchange in flight is over.
+pub fn reload(bridge: &Bridge) {
+    thread::scope(|s| for terminal in &bridge.terminals {
+        s.spawn(move || reload_terminal(terminal));
+    });
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reloads whenever the config file `path` changes; SIGHUP is handled by `supervisor`.
+pub fn watch(bridge: &Arc<Bridge>, path: Option<PathBuf>) {
+    if let Some(path) = path {
+        let bridge = bridge.clone();
+        thread::spawn(move || {
+            let mut seen = modified(&path);
+            loop {
+                thread::sleep(POLL);
+                let now = modified(&path);
+                if now.is_some() && now != seen {
+                    seen = now;
+                    reload(&bridge);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,66 @@
+//! Running as a Windows service, e.g. after
+//! `sc create vtk-bridged binPath= "C:\vtk\vtk-bridged.exe --service --config C:\vtk\vtk.toml" start= auto`.
+
+use std::{env, ffi::OsString, io::Error, sync::mpsc, thread, time::Duration};
+
+use windows_service::{
+    define_windows_service,
+    service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType},
+    service_control_handler::{self, ServiceControlHandlerResult},
+    service_dispatcher,
+};
+
+use crate::{serve, setup, supervisor};
+
+const NAME: &str = "vtk-bridged";
+
+define_windows_service!(ffi_main, service_main);
+
+/// Hands the process to the service control manager; returns once the service has stopped.
+pub fn run() -> Result<(), Error> {
+    service_dispatcher::start(NAME, ffi_main).map_err(Error::other)
+}
+
+fn status(state: ServiceState, accept: ServiceControlAccept, exit: u32) -> ServiceStatus {
+    ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: accept,
+        exit_code: ServiceExitCode::Win32(exit),
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(5),
+        process_id: None,
+    }
+}
+
+/// Options come from the image path given to `sc create`, not from the start parameters.
+fn service_main(_start_args: Vec<OsString>) {
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let handler = move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            _ = stop_tx.send(());
+            ServiceControlHandlerResult::NoError
+        },
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let Ok(handle) = service_control_handler::register(NAME, handler) else {return;};
+    let args: Vec<String> = env::args().skip(1).filter(|a| a != "--service").collect();
+    let (bridge, listener) = match setup(args) {
+        Ok(ready) => ready,
+        Err(e) => {
+            eprintln!("vtk-bridged: {}", e);
+            _ = handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty(), 1));
+            return;
+        },
+    };
+    _ = handle.set_service_status(status(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN, 0));
+    {
+        let bridge = bridge.clone();
+        thread::spawn(move || serve(listener, bridge));
+    }
+    _ = stop_rx.recv();
+    _ = handle.set_service_status(status(ServiceState::StopPending, ServiceControlAccept::empty(), 0));
+    supervisor::stop(&bridge);
+    _ = handle.set_service_status(status(ServiceState::Stopped, ServiceControlAccept::empty(), 0));
+}
@@ -0,0 +1,106 @@
+//! Running under a supervisor: systemd readiness and watchdog notifications, a clean stop on SIGINT/SIGTERM and
+//! a reload on SIGHUP.
+
+use std::{env, io::Error, process, sync::Arc, thread, time::Duration};
+
+use crate::{api::{Bridge, Terminal}, reload};
+
+/// How long the final DIS may take; an exchange already in flight finishes first.
+const SHUTDOWN: Duration = Duration::from_secs(3);
+
+/// Sends `state` to systemd when started with `Type=notify`; a no-op elsewhere.
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send(&path, state) {
+            eprintln!("vtk-bridged: sd_notify: {}", e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, state: &str) -> Result<(), Error> {
+    use std::os::unix::{ffi::OsStrExt, net::UnixDatagram};
+
+    let socket = UnixDatagram::unbound()?;
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+        socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?;
+        return Ok(());
+    }
+    socket.send_to(state.as_bytes(), path)?;
+    Ok(())
+}
+
+/// `WatchdogSec=` of the unit, when systemd expects this process to ping it.
+fn watchdog() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {return None;}
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec)).filter(|d| !d.is_zero())
+}
+
+/// A poisoned driver lock is the one failure a restart fixes; a silent terminal is reported by `/readyz` instead.
+pub fn healthy(terminal: &Terminal) -> bool {
+    !terminal.vtk.is_poisoned()
+}
+
+#[cfg(unix)]
+fn handle_signals(bridge: &Arc<Bridge>) -> Result<(), Error> {
+    use signal_hook::{consts::{SIGHUP, SIGINT, SIGTERM}, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    let bridge = bridge.clone();
+    thread::spawn(move || for signal in signals.forever() {
+        if signal == SIGHUP {
+            reload::reload(&bridge);
+        } else {
+            stop(&bridge);
+            process::exit(0);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn handle_signals(bridge: &Arc<Bridge>) -> Result<(), Error> {
+    let bridge = bridge.clone();
+    ctrlc::set_handler(move || {
+        stop(&bridge);
+        process::exit(0);
+    }).map_err(Error::other)
+}
+
+/// Stops cleanly on signals, reports readiness and keeps the systemd watchdog fed while healthy.
+pub fn start(bridge: &Arc<Bridge>) -> Result<(), Error> {
+    handle_signals(bridge)?;
+    notify("READY=1");
+    if let Some(timeout) = watchdog() {
+        let bridge = bridge.clone();
+        thread::spawn(move || while bridge.terminals.iter().all(healthy) {
+            notify("WATCHDOG=1");
+            thread::sleep(timeout / 2);
+        });
+    }
+    Ok(())
+}
+
+/// Disables the terminals so they do not keep taking cards while nobody is listening; in parallel, so a bank of
+/// machines stops within one `SHUTDOWN`.
+pub fn stop(bridge: &Bridge) {
+    notify("STOPPING=1");
+    eprintln!("vtk-bridged: stopping, disabling the terminals");
+    thread::scope(|s| for terminal in &bridge.terminals {
+        s.spawn(move || {
+            let Ok(mut vtk) = terminal.vtk.lock() else {return;};
+            if let Err(e) = vtk.shutdown(SHUTDOWN) {
+                eprintln!("vtk-bridged: {}: shutdown: {}", terminal.name, e);
+            }
+        });
+    });
+}
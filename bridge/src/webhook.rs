@@ -0,0 +1,42 @@
+use std::{thread, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+/// Posts sale outcomes to a URL; with a secret the body is signed in `X-Vtk-Signature: sha256=<hex>`.
+#[derive(Clone)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: Option<Vec<u8>>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+pub fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(body);
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Webhook {
+    /// Fires in the background so a slow receiver never holds up the terminal; failures are only logged.
+    pub fn fire(&self, event: &str, mut payload: Value) {
+        payload["event"] = Value::from(event);
+        payload["ts"] = Value::from(now_ms());
+        let body = payload.to_string();
+        let hook = self.clone();
+        thread::spawn(move || {
+            let agent = ureq::Agent::config_builder().timeout_global(Some(Duration::from_secs(10))).build().new_agent();
+            let mut req = agent.post(&hook.url).header("Content-Type", "application/json");
+            if let Some(secret) = &hook.secret {
+                req = req.header("X-Vtk-Signature", format!("sha256={}", signature(secret, body.as_bytes())));
+            }
+            if let Err(e) = req.send(&body) {
+                eprintln!("vtk-bridged: webhook {}: {}", hook.url, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,23 @@
+use std::{fs::File, io::{BufReader, Error, ErrorKind}, path::Path};
+
+use serde_json::json;
+use vtk::trace::{self, Recorder};
+
+use crate::{open_recorder, usage};
+
+/// Rewrites a recording in the format `out`'s extension asks for; refuses to append to an existing file.
+pub fn run(args: &[String], json: bool) -> Result<(), Error> {
+    let [input, out] = args else {usage()};
+    if Path::new(out).exists() {
+        return Err(Error::new(ErrorKind::AlreadyExists, format!("{} exists", out)));
+    }
+    let entries = trace::read_recording(BufReader::new(File::open(input)?))?;
+    let mut recorder: Box<dyn Recorder> = open_recorder(out, None)?;
+    trace::write_recording(recorder.as_mut(), &entries)?;
+    if json {
+        println!("{}", json!({"out": out, "frames": entries.len()}));
+    } else {
+        eprintln!("{} frames", entries.len());
+    }
+    Ok(())
+}
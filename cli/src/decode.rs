@@ -0,0 +1,104 @@
+use std::{io::{Error, ErrorKind}, process};
+
+use serde_json::{json, Value};
+use vtk::{logging::is_sensitive, schema::{self, Direction}, Frame, TagDiff, TlvKey};
+
+pub fn run(args: &[String], json: bool) -> Result<(), Error> {
+    let raw = parse_hex(&args.join(""))?;
+    let mut rest = &raw[..];
+    while !rest.is_empty() {
+        let (frame, used) = Frame::decode(rest)?;
+        print_frame(&frame, &rest[..used], json);
+        rest = &rest[used..];
+    }
+    Ok(())
+}
+
+/// Prints how the `actual` frame's tags differ from the `expected` one's.
+pub fn diff(args: &[String], json: bool) -> Result<(), Error> {
+    let [expected, actual] = args else {crate::usage()};
+    let (expected, _) = Frame::decode(&parse_hex(expected)?)?;
+    let (actual, _) = Frame::decode(&parse_hex(actual)?)?;
+    let diff = expected.tlv.diff(&actual.tlv);
+    if json {
+        let changes: Vec<Value> = diff.iter().map(|d| match d {
+            TagDiff::Added(key, v) => json!({"change": "added", "name": format!("{:?}", key), "hex": masked_hex(*key, v)}),
+            TagDiff::Removed(key, v) => json!({"change": "removed", "name": format!("{:?}", key), "hex": masked_hex(*key, v)}),
+            TagDiff::Changed {key, from, to} =>
+                json!({"change": "changed", "name": format!("{:?}", key), "from": masked_hex(*key, from), "to": masked_hex(*key, to)}),
+        }).collect();
+        println!("{}", Value::from(changes));
+    } else if diff.is_empty() {
+        println!("same tags and values");
+    } else {
+        for d in &diff {
+            println!("{}", d);
+        }
+    }
+    if !diff.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Accepts hex with optional spaces, colons, dashes or `0x` prefixes, as copied from captures.
+pub fn parse_hex(s: &str) -> Result<Vec<u8>, Error> {
+    let digits: String = s.replace("0x", "").replace("0X", "").chars()
+        .filter(|c| !(c.is_whitespace() || *c == ':' || *c == '-'))
+        .collect();
+    if !digits.len().is_multiple_of(2) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(Error::new(ErrorKind::InvalidInput, "expected an even number of hex digits"));
+    }
+    Ok((0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or_default())
+        .collect())
+}
+
+pub fn hex(v: &[u8]) -> String {
+    v.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// `hex`, or `<redacted N bytes>` for receipts and other sensitive tags, as the driver's logs show them.
+fn masked_hex(key: TlvKey, v: &[u8]) -> String {
+    if is_sensitive(key) {
+        return format!("<redacted {} bytes>", v.len());
+    }
+    hex(v)
+}
+
+/// Captures do not say which way a frame went, so it is held to whichever schema it fits best.
+fn violations(frame: &Frame) -> Vec<String> {
+    [Direction::ToTerminal, Direction::FromTerminal].into_iter()
+        .map(|d| schema::check(&frame.msg_name, d, &frame.tlv))
+        .min_by_key(Vec::len)
+        .unwrap_or_default()
+        .iter().map(ToString::to_string).collect()
+}
+
+fn print_frame(frame: &Frame, raw: &[u8], json: bool) {
+    let mut out = frame.to_json();
+    for tag in out["tags"].as_array_mut().into_iter().flatten() {
+        let key = TlvKey::from_u8(tag["tag"].as_u64().unwrap_or_default() as u8);
+        if let Some(v) = frame.tlv.get_bin(key).filter(|_| is_sensitive(key)) {
+            tag["hex"] = Value::from(masked_hex(key, v));
+            tag["value"] = Value::Null;
+        }
+    }
+    if json {
+        out["length"] = Value::from(raw.len());
+        out["violations"] = Value::from(violations(frame));
+        println!("{}", out);
+        return;
+    }
+    println!("{} ({} bytes)", frame.msg_name, raw.len());
+    for tag in out["tags"].as_array_mut().map(std::mem::take).unwrap_or_default() {
+        let (num, name, hex) = (tag["tag"].as_u64().unwrap_or_default(), tag["name"].as_str().unwrap_or_default(), tag["hex"].as_str().unwrap_or_default());
+        match &tag["value"] {
+            Value::Null => println!("  0x{:02X} {:<26} [{}]", num, name, hex),
+            value => println!("  0x{:02X} {:<26} {} [{}]", num, name, value, hex),
+        }
+    }
+    for violation in violations(frame) {
+        println!("  ! {}", violation);
+    }
+}
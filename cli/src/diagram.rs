@@ -0,0 +1,22 @@
+use std::{fs::File, io::{BufReader, Error}};
+
+use serde_json::json;
+use vtk::trace;
+
+use crate::usage;
+
+pub fn run(args: &[String], json: bool) -> Result<(), Error> {
+    let (plantuml, path) = match args {
+        [flag, path] if flag == "--plantuml" => (true, path),
+        [path] => (false, path),
+        _ => usage(),
+    };
+    let entries = trace::read_recording(BufReader::new(File::open(path)?))?;
+    let text = if plantuml {trace::plantuml(&entries)} else {trace::mermaid(&entries)};
+    if json {
+        println!("{}", json!({"format": if plantuml {"plantuml"} else {"mermaid"}, "frames": entries.len(), "diagram": text}));
+    } else {
+        print!("{}", text);
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+mod convert;
+mod decode;
+mod diagram;
+mod monitor;
+mod output;
+mod prometheus;
+mod repl;
+mod scenario;
+mod signal;
+
+use std::{env, io::Error, process, time::Instant};
+
+use vtk::{trace::{BinaryRecorder, JsonlRecorder, Recorder, RecordingFormat, RotatingRecorder}, AmountFormat, Config, Rotate, Vtk, VtkError};
+
+const USAGE: &str = "usage: vtk-cli [--config FILE] [--host HOST] [--port PORT] [--record FILE [--record-max-mb N]
+               [--record-keep N] [--record-gzip]] [--json] <command>
+
+commands:
+    idle                       put the terminal into the idle screen
+    disable                    disable card acceptance
+    qr <data>                  show a QR code on the idle screen
+    monitor [--interval SECS] [--prometheus ADDR]
+                               probe the terminal periodically and export metrics
+    repl                       craft and send frames interactively
+    decode <hex>...            decode frames from a hex dump, no terminal needed
+    diff <expected> <actual>   tags that differ between two hex frames, exit 1 if any
+    diagram [--plantuml] <recording>
+                               sequence diagram of a --record file, Mermaid by default
+    convert <recording> <out>  rewrite a recording as JSONL, or as binary for a .vtkrec <out>
+    run <scenario.yaml|json>   run scripted exchanges with assertions
+
+--record writes JSONL, or the compact binary format when FILE ends in .vtkrec; with --record-max-mb the file
+rotates at that size, keeping the --record-keep latest parts, gzipped with --record-gzip.
+
+decode and diff mask receipts and other sensitive tags, as the driver's logs do.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    if let Err(e) = run(args) {
+        if json {
+            println!("{}", serde_json::json!({"error": e.to_string(), "code": VtkError::of(&e).map(VtkError::code)}));
+        } else {
+            eprintln!("vtk-cli: {}", e);
+        }
+        process::exit(1);
+    }
+}
+
+/// The binary format for `.vtkrec` files, JSONL otherwise.
+fn open_recorder(path: &str, rotate: Option<Rotate>) -> Result<Box<dyn Recorder>, Error> {
+    let format = RecordingFormat::for_path(path);
+    Ok(match (rotate, format) {
+        (Some(rotate), _) => Box::new(RotatingRecorder::open(path, format, rotate)?),
+        (None, RecordingFormat::Binary) => Box::new(BinaryRecorder::open(path)?),
+        (None, RecordingFormat::Jsonl) => Box::new(JsonlRecorder::open(path)?),
+    })
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn run(args: Vec<String>) -> Result<(), Error> {
+    let json = args.iter().any(|a| a == "--json");
+    let mut args = args.into_iter().filter(|a| a != "--json");
+    let mut config_path = None;
+    let mut host = None;
+    let mut port = None;
+    let mut record = None;
+    let mut rotate = Rotate::default();
+    let command = loop {
+        match args.next().as_deref() {
+            Some("--config") => config_path = Some(args.next().unwrap_or_else(|| usage())),
+            Some("--host") => host = Some(args.next().unwrap_or_else(|| usage())),
+            Some("--record") => record = Some(args.next().unwrap_or_else(|| usage())),
+            Some("--record-max-mb") => rotate.max_bytes = Some(args.next().and_then(|n| n.parse::<u64>().ok()).unwrap_or_else(|| usage()) << 20),
+            Some("--record-keep") => rotate.keep = Some(args.next().and_then(|n| n.parse().ok()).unwrap_or_else(|| usage())),
+            Some("--record-gzip") => rotate.gzip = true,
+            Some("--port") => port = Some(args.next().and_then(|p| p.parse().ok()).unwrap_or_else(|| usage())),
+            Some("-h") | Some("--help") | None => usage(),
+            Some(cmd) => break String::from(cmd),
+        }
+    };
+    let rest: Vec<String> = args.collect();
+    if command == "decode" {
+        return decode::run(&rest, json);
+    }
+    if command == "diff" {
+        return decode::diff(&rest, json);
+    }
+    if command == "diagram" {
+        return diagram::run(&rest, json);
+    }
+    if command == "convert" {
+        return convert::run(&rest, json);
+    }
+    let mut config = match config_path {
+        Some(path) => Config::from_toml(path)?,
+        None => Config::default(),
+    };
+    config.apply_env()?;
+    if let Some(host) = host {
+        config.host = host;
+    }
+    if let Some(port) = port {
+        config.port = port;
+    }
+    if config.host.is_empty() {
+        usage();
+    }
+    let recorder = match record {
+        Some(path) => Some(open_recorder(&path, Some(rotate).filter(|r| *r != Rotate::default()))?),
+        None => None,
+    };
+    match command.as_str() {
+        "monitor" => return monitor::run(config, recorder, &rest, json),
+        "repl" => return repl::run(config, recorder, json),
+        "run" => return scenario::run(config, recorder, &rest, json),
+        _ => (),
+    }
+    let amounts = AmountFormat::new(config.amount_limits.exponent);
+    let mut vtk = Vtk::from_config(config);
+    vtk.set_recorder(recorder);
+    let start = Instant::now();
+    let res = match command.as_str() {
+        "idle" => vtk.idle(None),
+        "disable" => vtk.disable(),
+        "qr" => match rest.first() {
+            Some(data) => vtk.show_qr(data),
+            None => usage(),
+        },
+        _ => usage(),
+    };
+    output::report(json, &command, &res, start.elapsed(), &amounts);
+    if res.is_err() {
+        process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use std::{io::Error, sync::{Arc, Mutex}, time::{Duration, Instant}};
+
+use serde_json::json;
+use vtk::{trace::Recorder, AmountFormat, Config, Event, Vtk};
+
+use crate::{output, prometheus::{self, Metrics}, signal, usage};
+
+pub fn run(mut config: Config, recorder: Option<Box<dyn Recorder>>, args: &[String], json: bool) -> Result<(), Error> {
+    let amounts = AmountFormat::new(config.amount_limits.exponent);
+    let mut interval = Duration::from_secs(10);
+    let mut exporter = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => interval = Duration::from_secs(args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| usage())),
+            "--prometheus" => exporter = Some(args.next().unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+    config.keepalive = Some(interval);
+    config.watchdog.get_or_insert(interval * 3);
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    if let Some(addr) = exporter {
+        prometheus::serve(addr, metrics.clone())?;
+    }
+    signal::install()?;
+    let mut vtk = Vtk::from_config(config);
+    vtk.set_recorder(recorder);
+    let events = vtk.subscribe();
+    while !signal::stopped() {
+        let start = Instant::now();
+        let res = vtk.idle(None);
+        let rtt = start.elapsed();
+        {
+            let mut m = metrics.lock().unwrap();
+            m.exchanges += 1;
+            m.up = res.is_ok();
+            match &res {
+                Ok(_) => m.round_trip = vtk.last_round_trip(),
+                Err(_) => m.failures += 1,
+            }
+            output::report(json, "monitor", &res, rtt, &amounts);
+            for event in events.try_iter() {
+                match event {
+                    Event::Degraded {..} => m.degraded_events += 1,
+                    Event::Recovered => m.recovered_events += 1,
+                    Event::Anomaly(_) => m.anomaly_events += 1,
+                    Event::OperatingHours {..} | Event::ClockSkew {..} | Event::TerminalSilent {..} | Event::CircuitOpen {..} | Event::CircuitClosed => (),
+                }
+                if json {
+                    println!("{}", json!({"event": format!("{:?}", event)}));
+                } else {
+                    println!("event {:?}", event);
+                }
+            }
+        }
+        signal::sleep(interval.saturating_sub(start.elapsed()));
+    }
+    signal::wind_down(&mut vtk)
+}
@@ -0,0 +1,31 @@
+use std::{io::Error, time::Duration};
+
+use serde_json::json;
+use vtk::{AmountFormat, Tlv, TlvKey, VtkError};
+
+/// Prints the outcome of one command, either as text, with the amount in major units, or as a single JSON object.
+pub fn report(json: bool, command: &str, res: &Result<Tlv, Error>, elapsed: Duration, amounts: &AmountFormat) {
+    if json {
+        let out = match res {
+            Ok(tlv) => json!({"command": command, "ok": true, "elapsed_ms": elapsed.as_millis() as u64, "response": tlv.to_json()}),
+            Err(e) => json!({"command": command, "ok": false, "elapsed_ms": elapsed.as_millis() as u64, "error": e.to_string(),
+                "code": VtkError::of(e).map(VtkError::code)}),
+        };
+        println!("{}", out);
+        return;
+    }
+    match res {
+        Ok(tlv) => {
+            println!("{} ok in {}ms", command, elapsed.as_millis());
+            for tag in tlv.to_json().as_array().into_iter().flatten() {
+                let name = tag["name"].as_str().unwrap_or_default();
+                let value = if tag["value"].is_null() {&tag["hex"]} else {&tag["value"]};
+                match tlv.get_uint(TlvKey::AmountInMinorCurrencyUnit).filter(|_| name == "AmountInMinorCurrencyUnit") {
+                    Some(amount) => println!("  {} = {}", name, amounts.format(amount)),
+                    None => println!("  {} = {}", name, value),
+                }
+            }
+        },
+        Err(e) => println!("{} failed after {}ms: {}", command, elapsed.as_millis(), e),
+    }
+}
@@ -0,0 +1,60 @@
+use std::{fmt::Write as _, io::{BufRead, BufReader, Error, Write}, net::TcpListener, sync::{Arc, Mutex}, thread, time::Duration};
+
+#[derive(Default)]
+pub struct Metrics {
+    pub up: bool,
+    pub round_trip: Option<Duration>,
+    pub exchanges: u64,
+    pub failures: u64,
+    pub degraded_events: u64,
+    pub recovered_events: u64,
+    pub anomaly_events: u64,
+}
+
+impl Metrics {
+    fn render(&self) -> String {
+        let mut out = String::new();
+        _ = writeln!(out, "# HELP vtk_terminal_up Whether the last probe reached the terminal.");
+        _ = writeln!(out, "# TYPE vtk_terminal_up gauge");
+        _ = writeln!(out, "vtk_terminal_up {}", self.up as u8);
+        if let Some(rtt) = self.round_trip {
+            _ = writeln!(out, "# HELP vtk_round_trip_seconds Request to answer time of the last successful probe, connecting excluded.");
+            _ = writeln!(out, "# TYPE vtk_round_trip_seconds gauge");
+            _ = writeln!(out, "vtk_round_trip_seconds {}", rtt.as_secs_f64());
+        }
+        _ = writeln!(out, "# HELP vtk_exchanges_total Probes sent to the terminal.");
+        _ = writeln!(out, "# TYPE vtk_exchanges_total counter");
+        _ = writeln!(out, "vtk_exchanges_total {}", self.exchanges);
+        _ = writeln!(out, "# HELP vtk_exchange_failures_total Probes that got no valid answer.");
+        _ = writeln!(out, "# TYPE vtk_exchange_failures_total counter");
+        _ = writeln!(out, "vtk_exchange_failures_total {}", self.failures);
+        _ = writeln!(out, "# HELP vtk_events_total Driver events by kind.");
+        _ = writeln!(out, "# TYPE vtk_events_total counter");
+        _ = writeln!(out, "vtk_events_total{{event=\"degraded\"}} {}", self.degraded_events);
+        _ = writeln!(out, "vtk_events_total{{event=\"recovered\"}} {}", self.recovered_events);
+        _ = writeln!(out, "vtk_events_total{{event=\"anomaly\"}} {}", self.anomaly_events);
+        out
+    }
+}
+
+/// Serves the metrics in Prometheus text format on every request path.
+pub fn serve(addr: &str, metrics: Arc<Mutex<Metrics>>) -> Result<(), Error> {
+    let addr = match addr.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{}", port),
+        None => String::from(addr),
+    };
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {continue;};
+            let mut reader = BufReader::new(&mut stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).map(|n| n > 2).unwrap_or(false) {
+                line.clear();
+            }
+            let body = metrics.lock().unwrap().render();
+            _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::{io::{self, BufRead, Error, ErrorKind, Write}, time::{Duration, Instant}};
+
+use vtk::{trace::Recorder, AmountFormat, Config, Tlv, TlvKey, Vtk};
+
+use crate::{decode::{hex, parse_hex}, output};
+
+const HELP: &str = "commands:
+    set <tag> <text>     set a tag to a UTF-8 string
+    hex <tag> <hex>      set a tag to raw bytes
+    num <tag> <n>        set a tag to a big-endian number
+    unset <tag>          remove a tag from the pending request
+    show                 print the pending request
+    clear                drop all pending tags
+    send <MSG>           send the pending request as MSG and print the answer
+    recv [ms]            wait for an unsolicited frame
+    raw                  hex of the last frames sent and received
+    disconnect           close the socket
+    tags                 list known tags
+    quit";
+
+pub fn run(config: Config, recorder: Option<Box<dyn Recorder>>, json: bool) -> Result<(), Error> {
+    let amounts = AmountFormat::new(config.amount_limits.exponent);
+    let mut vtk = Vtk::from_config(config);
+    vtk.set_recorder(recorder);
+    let mut pending = Tlv::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("vtk> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next() else {break;};
+        let line = line?;
+        let mut words = line.trim().splitn(3, ' ');
+        let res = match (words.next().unwrap_or(""), words.next(), words.next()) {
+            ("", _, _) => Ok(()),
+            ("quit", _, _) | ("exit", _, _) => break,
+            ("help", _, _) => {
+                println!("{}", HELP);
+                Ok(())
+            },
+            ("tags", _, _) => {
+                for k in TlvKey::ALL {
+                    println!("  0x{:02X} {:?}", k.as_u8(), k);
+                }
+                Ok(())
+            },
+            ("set", Some(tag), Some(text)) => tag.parse().map(|k| pending.set_str(k, text)),
+            ("hex", Some(tag), Some(hex)) => tag.parse().and_then(|k| parse_hex(hex).map(|v| pending.set_bin(k, &v))),
+            ("num", Some(tag), Some(n)) => tag.parse().and_then(|k| {
+                let n = n.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("not a number: {}", n)))?;
+                pending.set_u64(k, n);
+                Ok(())
+            }),
+            ("unset", Some(tag), None) => tag.parse().map(|k| {pending.remove(k);}),
+            ("show", _, _) => {
+                println!("{}", pending.to_json());
+                Ok(())
+            },
+            ("clear", _, _) => {
+                pending = Tlv::new();
+                Ok(())
+            },
+            ("send", Some(msg), None) => {
+                let start = Instant::now();
+                let res = vtk.exchange(msg, pending.clone(), vtk.config().read_timeout);
+                output::report(json, msg, &res, start.elapsed(), &amounts);
+                Ok(())
+            },
+            ("recv", ms, None) => {
+                let ms = ms.and_then(|ms| ms.parse().ok()).unwrap_or(vtk.config().read_timeout.as_millis() as u64);
+                let start = Instant::now();
+                let res = vtk.receive(Duration::from_millis(ms));
+                output::report(json, "recv", &res, start.elapsed(), &amounts);
+                Ok(())
+            },
+            ("raw", None, None) => {
+                let last = vtk.last_frames();
+                println!("sent     {}\nreceived {}", hex(&last.sent), hex(&last.received));
+                Ok(())
+            },
+            ("disconnect", None, None) => {
+                vtk.disconnect();
+                Ok(())
+            },
+            _ => Err(Error::new(ErrorKind::InvalidInput, "unknown command, try help")),
+        };
+        if let Err(e) = res {
+            println!("error: {}", e);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::{collections::BTreeMap, fs, io::{Error, ErrorKind}, path::Path, process, thread, time::Duration};
+
+use serde::Deserialize;
+use serde_json::json;
+use vtk::{conformance::{self, Check}, trace::Recorder, Config, Frame, Tlv, TlvKey, Vtk};
+
+use crate::decode::parse_hex;
+
+/// A scripted acceptance run, loaded from YAML or JSON.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Scenario {
+    #[serde(default)]
+    name: String,
+    steps: Vec<Step>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct Step {
+    name: Option<String>,
+    send: Option<String>,
+    tags: BTreeMap<String, String>,
+    hex_tags: BTreeMap<String, String>,
+    answer: Option<String>,
+    expect: BTreeMap<String, String>,
+    /// Hex of the whole answer frame the terminal must send back, tag order aside.
+    expect_frame: Option<String>,
+    require: Vec<String>,
+    timeout_ms: Option<u64>,
+    sleep_ms: Option<u64>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn load(path: &Path) -> Result<Scenario, Error> {
+    let text = fs::read_to_string(path)?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e))),
+        _ => serde_yaml::from_str(&text).map_err(|e| invalid(format!("{}: {}", path.display(), e))),
+    }
+}
+
+fn check(i: usize, step: &Step, msg_name: &str) -> Result<Check, Error> {
+    let mut request = Tlv::new();
+    for (tag, text) in &step.tags {
+        request.set_str(tag.parse()?, text);
+    }
+    for (tag, hex) in &step.hex_tags {
+        request.set_bin(tag.parse()?, &parse_hex(hex)?);
+    }
+    let name = step.name.clone().unwrap_or_else(|| format!("step {}", i + 1));
+    let mut check = Check::new(&name, msg_name, request);
+    if let Some(answer) = &step.answer {
+        check = check.answered_by(answer);
+    }
+    for tag in &step.require {
+        check = check.expect(tag.parse::<TlvKey>()?);
+    }
+    for (tag, value) in &step.expect {
+        check = check.expect_value(tag.parse()?, value.as_bytes());
+    }
+    if let Some(hex) = &step.expect_frame {
+        let (frame, _) = Frame::decode(&parse_hex(hex)?)?;
+        check = check.expect_frame(frame.tlv);
+    }
+    Ok(check)
+}
+
+pub fn run(config: Config, recorder: Option<Box<dyn Recorder>>, args: &[String], json: bool) -> Result<(), Error> {
+    let [path] = args else {crate::usage()};
+    let scenario = load(Path::new(path))?;
+    let timeout = config.read_timeout;
+    let mut vtk = Vtk::from_config(config);
+    vtk.set_recorder(recorder);
+    let mut failed = 0;
+    if !json {
+        println!("scenario {}", scenario.name);
+    }
+    for (i, step) in scenario.steps.iter().enumerate() {
+        if let Some(ms) = step.sleep_ms {
+            thread::sleep(Duration::from_millis(ms));
+        }
+        let Some(msg_name) = &step.send else {continue;};
+        let check = check(i, step, msg_name)?;
+        let timeout = step.timeout_ms.map_or(timeout, Duration::from_millis);
+        let report = conformance::run(&mut vtk, &[check], timeout);
+        for r in &report.results {
+            if !r.passed {
+                failed += 1;
+            }
+            if json {
+                println!("{}", json!({"step": r.name, "msg_name": r.msg_name, "passed": r.passed, "detail": r.detail, "elapsed_ms": r.elapsed.as_millis() as u64}));
+            } else {
+                println!("{:<4} {:<24} {} {:>5}ms {}", r.msg_name, r.name, if r.passed {"PASS"} else {"FAIL"}, r.elapsed.as_millis(), r.detail);
+            }
+        }
+    }
+    if !json {
+        println!("{} failed", failed);
+    }
+    if failed > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
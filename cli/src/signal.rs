@@ -0,0 +1,36 @@
+//! SIGINT and SIGTERM for the long-running commands, so the terminal is disabled rather than left taking cards.
+
+use std::{io::Error, sync::atomic::{AtomicBool, Ordering}, thread, time::{Duration, Instant}};
+
+use vtk::Vtk;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// How long the final DIS may take before the process exits anyway.
+const SHUTDOWN: Duration = Duration::from_secs(3);
+
+pub fn install() -> Result<(), Error> {
+    ctrlc::set_handler(|| STOP.store(true, Ordering::SeqCst)).map_err(Error::other)
+}
+
+pub fn stopped() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
+/// Sleeps for `d`, waking early on a signal.
+pub fn sleep(d: Duration) {
+    let until = Instant::now() + d;
+    while !stopped() {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {break;}
+        thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+/// Disables the terminal, closes the socket and closes the recording.
+pub fn wind_down(vtk: &mut Vtk) -> Result<(), Error> {
+    eprintln!("vtk-cli: stopping, disabling the terminal");
+    let res = vtk.shutdown(SHUTDOWN);
+    vtk.set_recorder(None);
+    res
+}
@@ -0,0 +1,77 @@
+//! Amounts in minor currency units as text for people, e.g. in messages and on screens.
+
+#[cfg(feature = "decimal")]
+use std::io::{Error, ErrorKind};
+
+#[cfg(feature = "decimal")]
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "decimal")]
+use crate::config::AmountLimits;
+
+/// How `format` writes minor units: `exponent` digits after `decimal`, and `grouping` between thousands of the
+/// major part when set, e.g. `1 250,50` for 125050 with `','` and `Some(' ')`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct AmountFormat {
+    pub exponent: u8,
+    pub decimal: char,
+    pub grouping: Option<char>,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        Self {exponent: 2, decimal: '.', grouping: None}
+    }
+}
+
+impl AmountFormat {
+    pub fn new(exponent: u8) -> Self {
+        Self {exponent, ..Self::default()}
+    }
+
+    /// `amount` in major units, e.g. `125.50` for 12550 with exponent 2.
+    pub fn format(&self, amount: u64) -> String {
+        let exponent = self.exponent as usize;
+        let digits = format!("{:0>width$}", amount, width = exponent + 1);
+        let (int, frac) = digits.split_at(digits.len() - exponent);
+        let mut out = String::with_capacity(digits.len() * 2);
+        for (i, c) in int.chars().enumerate() {
+            if let Some(sep) = self.grouping.filter(|_| i > 0 && (int.len() - i).is_multiple_of(3)) {
+                out.push(sep);
+            }
+            out.push(c);
+        }
+        if exponent > 0 {
+            out.push(self.decimal);
+            out.push_str(frac);
+        }
+        out
+    }
+
+    /// `amount` as a major-unit `Decimal`, e.g. `125.50` for 12550 with exponent 2, for totals and discounts
+    /// without float math.
+    #[cfg(feature = "decimal")]
+    pub fn to_decimal(&self, amount: u64) -> Decimal {
+        Decimal::from_i128_with_scale(amount as i128, self.exponent as u32)
+    }
+
+    /// The minor units of the major-unit `value`, for `sell`. Refuses a negative value, one with digits past
+    /// `exponent`, e.g. `1.005` for cents, and one outside `limits`.
+    #[cfg(feature = "decimal")]
+    pub fn from_decimal(&self, value: Decimal, limits: &AmountLimits) -> Result<u64, Error> {
+        if value.is_sign_negative() && !value.is_zero() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("negative amount {}", value)));
+        }
+        let minor = 10u64.checked_pow(self.exponent as u32).and_then(|unit| value.checked_mul(Decimal::from(unit)))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("amount {} is too large", value)))?;
+        if !minor.fract().is_zero() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("amount {} has more than {} decimals", value, self.exponent)));
+        }
+        let amount = minor.to_u64().ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("amount {} is too large", value)))?;
+        limits.check(amount)?;
+        Ok(amount)
+    }
+}
@@ -0,0 +1,65 @@
+//! Append-only trail of exchanges and sale outcomes, kept apart from the debug log.
+
+use std::{fmt::Write as _, io::{Error, ErrorKind, Write}, path::Path, time::{Duration, UNIX_EPOCH}};
+
+use crate::{record::SaleOutcome, rotate::{Rotate, RotatingFile}};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEntry {
+    /// One request and what came back: the answer's MsgName, or the error kind when nothing usable did.
+    Exchange {
+        request: String,
+        operation: Option<u32>,
+        amount: Option<u64>,
+        answer: Result<String, ErrorKind>,
+    },
+    Outcome {operation: u32, outcome: SaleOutcome},
+}
+
+/// Receives an entry after every exchange and every settled sale; `at_ms` is Unix milliseconds.
+pub trait AuditLog: Send {
+    fn append(&mut self, at_ms: u64, entry: &AuditEntry) -> Result<(), Error>;
+}
+
+/// One line per entry, synced as it is written:
+/// `<at_ms> exchange VRP op=3 amount=150 -> VRP`, `<at_ms> exchange FIN op=3 amount=150 !TimedOut`
+/// or `<at_ms> outcome op=3 finalized`. Daily rotation goes by `at_ms`, so follows the driver's `Clock`.
+pub struct FileAuditLog {
+    out: RotatingFile,
+}
+
+impl FileAuditLog {
+    pub fn open<P: AsRef<Path>>(path: P, rotate: Rotate) -> Result<Self, Error> {
+        Ok(Self {out: RotatingFile::open(path, rotate)?})
+    }
+}
+
+fn opt<T: ToString>(v: Option<T>) -> String {
+    v.map_or_else(|| String::from("-"), |v| v.to_string())
+}
+
+/// The line for `entry`, newline included.
+fn format_line(at_ms: u64, entry: &AuditEntry) -> String {
+    let mut line = at_ms.to_string();
+    match entry {
+        AuditEntry::Exchange {request, operation, amount, answer} => {
+            _ = write!(line, " exchange {} op={} amount={}", request, opt(*operation), opt(*amount));
+            match answer {
+                Ok(msg) => _ = write!(line, " -> {}", if msg.is_empty() {"???"} else {msg}),
+                Err(kind) => _ = write!(line, " !{:?}", kind),
+            }
+        },
+        AuditEntry::Outcome {operation, outcome} => _ = write!(line, " outcome op={} {}", operation, outcome.as_str()),
+    }
+    line.push('\n');
+    line
+}
+
+impl AuditLog for FileAuditLog {
+    fn append(&mut self, at_ms: u64, entry: &AuditEntry) -> Result<(), Error> {
+        self.out.rotate_if_due_at(UNIX_EPOCH + Duration::from_millis(at_ms))?;
+        self.out.write_all(format_line(at_ms, entry).as_bytes())?;
+        self.out.flush()?;
+        self.out.sync()
+    }
+}
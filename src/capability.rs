@@ -0,0 +1,46 @@
+//! What the connected terminal reports about itself.
+//!
+//! Vendotek does not publish a matrix of which firmware supports which optional tags, so nothing here
+//! guesses from the version: a capability is known once the terminal has shown it.
+
+use std::collections::BTreeSet;
+
+use crate::vtk::{Tlv, TlvKey};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// SysInfo from the latest answer that carried it.
+    pub sys_info: Option<String>,
+    /// The first dotted number in SysInfo, e.g. `[2, 14, 1]` for `VTK-Pro fw 2.14.1`.
+    pub firmware: Option<Vec<u32>>,
+    /// Tags seen in the terminal's answers.
+    pub tags: BTreeSet<u8>,
+}
+
+/// The first `N.N[.N...]` in `text`.
+fn version(text: &str) -> Option<Vec<u32>> {
+    text.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .map(|w| w.trim_matches('.'))
+        .filter(|w| w.contains('.'))
+        .find_map(|w| w.split('.').map(|n| n.parse().ok()).collect())
+}
+
+impl Capabilities {
+    pub(crate) fn learn(&mut self, tlv: &Tlv) {
+        self.tags.extend(tlv.data().keys().map(|k| k.as_u8()));
+        if let Some(info) = tlv.get_bin(TlvKey::SysInfo) {
+            let info = String::from_utf8_lossy(info).trim().to_owned();
+            self.firmware = version(&info);
+            self.sys_info = Some(info);
+        }
+    }
+
+    pub fn reports(&self, key: TlvKey) -> bool {
+        self.tags.contains(&key.as_u8())
+    }
+
+    /// Whether the firmware is at least `min`, e.g. `&[2, 10]`; `None` until SysInfo gave a version.
+    pub fn firmware_at_least(&self, min: &[u32]) -> Option<bool> {
+        self.firmware.as_ref().map(|v| v[..] >= *min)
+    }
+}
@@ -0,0 +1,77 @@
+use std::{collections::HashMap, io::Error};
+#[cfg(feature = "toml")]
+use std::{fs, io::ErrorKind, path::Path};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::charset::Charset;
+
+/// Display texts by language code, e.g. QR captions or product names.
+///
+/// ```toml
+/// default = "en"
+/// [charsets]
+/// ru = "utf-8"
+/// [messages.en]
+/// scan = "Scan to pay {amount}"
+/// [messages.ru]
+/// scan = "Отсканируйте, чтобы оплатить {amount}"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Catalog {
+    /// Language used when neither the requested one nor its base language has the message.
+    pub default: String,
+    /// Encoding of the terminal display per language; UTF-8 when unset.
+    pub charsets: HashMap<String, String>,
+    pub messages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalog {
+    pub fn new(default: &str) -> Self {
+        Self {default: String::from(default), ..Default::default()}
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(s: &str) -> Result<Self, Error> {
+        toml::from_str(s).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn insert(&mut self, lang: &str, key: &str, text: &str) {
+        self.messages.entry(String::from(lang)).or_default().insert(String::from(key), String::from(text));
+    }
+
+    /// `pt-BR`, then `pt`, then the default language.
+    fn fallbacks<'a>(&'a self, lang: &'a str) -> impl Iterator<Item = &'a str> {
+        let base = lang.split(['-', '_']).next().filter(|b| *b != lang);
+        [Some(lang), base, Some(self.default.as_str())].into_iter().flatten()
+    }
+
+    pub fn get(&self, lang: &str, key: &str) -> Option<&str> {
+        self.fallbacks(lang).find_map(|l| self.messages.get(l)?.get(key)).map(String::as_str)
+    }
+
+    /// Like `get`, with `{name}` placeholders replaced from `args`.
+    pub fn format(&self, lang: &str, key: &str, args: &[(&str, &str)]) -> Option<String> {
+        let mut text = String::from(self.get(lang, key)?);
+        for (name, value) in args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        Some(text)
+    }
+
+    pub fn charset(&self, lang: &str) -> &str {
+        self.fallbacks(lang).find_map(|l| self.charsets.get(l)).map(String::as_str).unwrap_or("utf-8")
+    }
+
+    /// Encodes `text` for a display configured for `lang`, ready for `Tlv::set_bin`.
+    pub fn encode(&self, lang: &str, text: &str) -> Result<Vec<u8>, Error> {
+        Ok(self.charset(lang).parse::<Charset>()?.encode(text))
+    }
+}
@@ -0,0 +1,101 @@
+use std::{borrow::Cow, fmt, io::{Error, ErrorKind}, str::FromStr};
+
+/// Text encoding of string tags such as product names and receipts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Charset {
+    #[default]
+    Utf8,
+    /// Non-ASCII characters are sent as `?`.
+    Ascii,
+    /// Any WHATWG encoding label, e.g. `windows-1251` or `ibm866`.
+    #[cfg(feature = "encoding")]
+    Other(&'static encoding_rs::Encoding),
+}
+
+impl Charset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Ascii => "ascii",
+            #[cfg(feature = "encoding")]
+            Charset::Other(enc) => enc.name(),
+        }
+    }
+
+    /// Characters the charset cannot represent become `?`.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            Charset::Utf8 => text.as_bytes().to_vec(),
+            Charset::Ascii => text.chars().map(|c| if c.is_ascii() {c as u8} else {b'?'}).collect(),
+            #[cfg(feature = "encoding")]
+            Charset::Other(enc) => encode_with(enc, text),
+        }
+    }
+
+    /// Invalid sequences become U+FFFD rather than failing the whole value.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        match self {
+            Charset::Utf8 | Charset::Ascii => String::from_utf8_lossy(bytes),
+            #[cfg(feature = "encoding")]
+            Charset::Other(enc) => enc.decode_without_bom_handling(bytes).0,
+        }
+    }
+}
+
+#[cfg(feature = "encoding")]
+fn encode_with(enc: &'static encoding_rs::Encoding, text: &str) -> Vec<u8> {
+    use encoding_rs::EncoderResult;
+
+    let mut encoder = enc.new_encoder();
+    let mut out = Vec::with_capacity(text.len());
+    let mut buf = [0u8; 256];
+    let mut rest = text;
+    loop {
+        let (res, read, written) = encoder.encode_from_utf8_without_replacement(rest, &mut buf, true);
+        out.extend_from_slice(&buf[..written]);
+        rest = &rest[read..];
+        match res {
+            EncoderResult::InputEmpty => return out,
+            EncoderResult::OutputFull => (),
+            EncoderResult::Unmappable(_) => out.push(b'?'),
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Charset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Charset::Utf8),
+            "ascii" | "us-ascii" => Ok(Charset::Ascii),
+            #[cfg(feature = "encoding")]
+            label => encoding_rs::Encoding::for_label(label.as_bytes())
+                .map(Charset::Other)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unknown charset {}", s))),
+            #[cfg(not(feature = "encoding"))]
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unsupported charset {} (needs the encoding feature)", s))),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Charset {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Charset {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let label = String::deserialize(d)?;
+        label.parse().map_err(serde::de::Error::custom)
+    }
+}
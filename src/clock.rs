@@ -0,0 +1,113 @@
+//! Where the driver gets the time, so keepalive, backoff and timeout logic can run on a `ManualClock` in tests,
+//! and calendar arithmetic for the few places that need dates without a time zone library.
+
+use std::{sync::{Arc, Mutex, PoisonError}, thread, time::{Duration, Instant, SystemTime}};
+
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+    /// Wall-clock time, for timestamps.
+    fn system_now(&self) -> SystemTime;
+    fn sleep(&self, d: Duration);
+}
+
+/// The OS clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, d: Duration) {
+        thread::sleep(d);
+    }
+}
+
+/// Time that only moves when told to; `sleep` moves it instead of waiting. Clones share the time.
+///
+/// Socket timeouts are the OS's, so reads and outgoing connects still wait in real time; a `Role::Server` driver's
+/// wait for the terminal to connect does not.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    start: (Instant, SystemTime),
+    offset: Arc<Mutex<Duration>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self {start: (Instant::now(), SystemTime::now()), offset: Arc::default()}
+    }
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, d: Duration) {
+        *self.offset.lock().unwrap_or_else(PoisonError::into_inner) += d;
+    }
+
+    /// How far the clock has moved since it was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start.0 + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.start.1 + self.elapsed()
+    }
+
+    fn sleep(&self, d: Duration) {
+        self.advance(d);
+    }
+}
+
+/// Days since the Unix epoch for a proleptic Gregorian date; Howard Hinnant's `days_from_civil`.
+pub(crate) fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 {y - 1} else {y};
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (m as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The inverse of `days_from_civil`.
+pub(crate) fn civil_from_days(day: i64) -> (i64, u32, u32) {
+    let z = day + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 {mp + 3} else {mp - 9} as u32;
+    (yoe + era * 400 + (m <= 2) as i64, m, d)
+}
+
+/// LocalTime as the terminal sends it, `YYYYMMDDhhmmss`, in Unix milliseconds as if it were UTC.
+/// Separators are skipped, so `2024-05-01 12:30:00` reads too.
+pub(crate) fn parse_local_time(raw: &[u8]) -> Option<i64> {
+    let digits: Vec<u32> = raw.iter().filter(|b| b.is_ascii_digit()).map(|b| (b - b'0') as u32).collect();
+    let [y0, y1, y2, y3, mo0, mo1, d0, d1, h0, h1, mi0, mi1, s0, s1] = digits[..] else {return None;};
+    let y = y0 * 1000 + y1 * 100 + y2 * 10 + y3;
+    let (mo, d) = (mo0 * 10 + mo1, d0 * 10 + d1);
+    let (h, mi, s) = (h0 * 10 + h1, mi0 * 10 + mi1, s0 * 10 + s1);
+    if !(1..=12).contains(&mo) || !(1..=31).contains(&d) || h > 23 || mi > 59 || s > 60 {
+        return None;
+    }
+    let secs = days_from_civil(y as i64, mo, d) * 86_400 + (h * 3600 + mi * 60 + s) as i64;
+    Some(secs * 1000)
+}
@@ -0,0 +1,82 @@
+//! Wire primitives shared by `Tlv`, `Frame` and `FixedTlv`; all but `push_len`, which only `Tlv` needs, use only `core`.
+
+pub const PROTOCOL_ID: [u8; 2] = [0x96, 0xFB];
+
+/// Why a body does not split cleanly into tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Broken {
+    Truncated {tag: u8, declared: usize, available: usize},
+    Trailing(usize),
+}
+
+/// A tag, its value and the bytes after it.
+pub(crate) type Split<'a> = (u8, &'a [u8], &'a [u8]);
+
+/// The first tag of `raw`, or `None` at the end of the body.
+pub(crate) fn split_tag(raw: &[u8]) -> Option<Result<Split<'_>, Broken>> {
+    split_tag_as(raw, false)
+}
+
+/// `split_tag`, reading lengths of 128 and up as `0x81 n` or `0x82 n n` with `ber`; any other long form breaks
+/// the body off there.
+pub(crate) fn split_tag_as(raw: &[u8], ber: bool) -> Option<Result<Split<'_>, Broken>> {
+    let (tag, len, tail) = match raw {
+        [] => return None,
+        [tag, 0x81, len, tail @ ..] if ber => (tag, *len as usize, tail),
+        [tag, 0x82, l0, l1, tail @ ..] if ber => (tag, u16::from_be_bytes([*l0, *l1]) as usize, tail),
+        [_, 0x80..=0xFF, ..] if ber => return Some(Err(Broken::Trailing(raw.len()))),
+        [tag, len, tail @ ..] => (tag, *len as usize, tail),
+        rest => return Some(Err(Broken::Trailing(rest.len()))),
+    };
+    if tail.len() < len {
+        return Some(Err(Broken::Truncated {tag: *tag, declared: len, available: tail.len()}));
+    }
+    let (value, rest) = tail.split_at(len);
+    Some(Ok((*tag, value, rest)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BadHeader {
+    Short,
+    ProtocolId([u8; 2]),
+    Length {declared: usize, available: usize},
+}
+
+/// The TLV body of the frame at the start of `raw`.
+pub(crate) fn split_frame(raw: &[u8]) -> Result<&[u8], BadHeader> {
+    // The terminal is on the network, so no arithmetic or indexing here may trust the input.
+    let [h0, h1, p0, p1, rest @ ..] = raw else {return Err(BadHeader::Short);};
+    if [*p0, *p1] != PROTOCOL_ID {
+        return Err(BadHeader::ProtocolId([*p0, *p1]));
+    }
+    let len = u16::from_be_bytes([*h0, *h1]) as usize;
+    len.checked_sub(PROTOCOL_ID.len()).and_then(|n| rest.get(..n))
+        .ok_or(BadHeader::Length {declared: len, available: rest.len() + PROTOCOL_ID.len()})
+}
+
+/// A value or body longer than its length field can declare.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TooLong(pub usize);
+
+/// Appends the length of a value, in the long form for 128 and up with `ber`.
+#[cfg(feature = "std")]
+pub(crate) fn push_len(out: &mut Vec<u8>, len: usize, ber: bool) -> Result<(), TooLong> {
+    match len {
+        0..0x80 => out.push(len as u8),
+        0x80..=0xFF if !ber => out.push(len as u8),
+        0x80..=0xFF => out.extend([0x81, len as u8]),
+        0x100..=0xFFFF if ber => {
+            out.push(0x82);
+            out.extend((len as u16).to_be_bytes());
+        },
+        _ => return Err(TooLong(len)),
+    }
+    Ok(())
+}
+
+/// Length and protocol id in front of a body of `body_len` bytes.
+pub(crate) fn header(body_len: usize) -> Result<[u8; 4], TooLong> {
+    let len = u16::try_from(body_len + PROTOCOL_ID.len()).map_err(|_| TooLong(body_len))?;
+    let [h0, h1] = len.to_be_bytes();
+    Ok([h0, h1, PROTOCOL_ID[0], PROTOCOL_ID[1]])
+}
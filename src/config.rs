@@ -0,0 +1,463 @@
+use std::{env, io::{Error, ErrorKind}, net::IpAddr, str::FromStr, time::Duration};
+#[cfg(feature = "toml")]
+use std::{fs, path::Path};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{charset::Charset, error::VtkError, frame::{LengthEncoding, ParseMode}};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct RetryPolicy {
+    /// Total tries per exchange, the first one included.
+    pub attempts: u32,
+    #[cfg_attr(feature = "serde", serde(rename = "backoff_ms", with = "millis"))]
+    pub backoff: Duration,
+    /// Up to this much more is added to each backoff at random, so machines that lost the same network do not
+    /// retry in step; drawn from `VtkBuilder::rng`.
+    #[cfg_attr(feature = "serde", serde(rename = "jitter_ms", with = "millis"))]
+    pub jitter: Duration,
+}
+
+/// Token bucket for outgoing frames: `burst` frames at once, then one per `interval`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct RateLimit {
+    #[cfg_attr(feature = "serde", serde(rename = "interval_ms", with = "millis"))]
+    pub interval: Duration,
+    pub burst: u32,
+}
+
+/// Amounts a payment request may ask for, in minor units; `exponent` is the number of minor-unit digits, for messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct AmountLimits {
+    pub min: u64,
+    pub max: Option<u64>,
+    pub exponent: u8,
+}
+
+impl Default for AmountLimits {
+    fn default() -> Self {
+        Self {min: 0, max: None, exponent: 2}
+    }
+}
+
+impl AmountLimits {
+    pub fn check(&self, amount: u64) -> Result<(), Error> {
+        if amount < self.min || self.max.is_some_and(|max| amount > max) {
+            return Err(VtkError::amount_out_of_range(amount, self));
+        }
+        Ok(())
+    }
+}
+
+/// Warns when the terminal's LocalTime is more than `threshold` off the host clock.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct ClockSkew {
+    #[cfg_attr(feature = "serde", serde(rename = "threshold_ms", with = "millis"))]
+    pub threshold: Duration,
+    /// The terminal's time zone, since LocalTime carries none; minutes east of UTC.
+    pub utc_offset_mins: i32,
+}
+
+/// The longest QrCodeData the terminal's display takes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct QrLimit {
+    /// In bytes as sent.
+    pub max_len: usize,
+    /// Shown in place of a longer payload, e.g. a short URL leading to it; with payments, `{op}`, `{amount}` and
+    /// `{product}` are filled in as in `Config::qr_template`. Without one, a longer payload is refused unsent.
+    pub fallback: Option<String>,
+}
+
+/// Wire quirks that differ between terminal firmwares, so one build can drive a mixed fleet; the default is what
+/// the driver has always done. Vendotek does not publish which firmware needs which, so there are no presets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Compat {
+    pub lengths: LengthEncoding,
+    /// Every IDL carries KeepaliveIntervalInSecs, zero when `Config::keepalive` is unset, for firmware that
+    /// refuses an IDL without it.
+    pub idl_keepalive: bool,
+    /// FIN may charge more than VRP authorized, e.g. a surcharge, for firmware that accepts it; see
+    /// `Vtk::finalize_adjusted`.
+    pub fin_above_authorized: bool,
+}
+
+/// Which side opens the TCP connection; Vendotek terminals can be set up either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "lowercase"))]
+pub enum Role {
+    /// The driver connects to the terminal at `host:port`.
+    #[default]
+    Client,
+    /// The driver listens on `port` (at `bind_addr`, or every interface) and the terminal connects to it; a `host`
+    /// that is an IP address refuses connections from anywhere else.
+    Server,
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "client" | "connect" => Ok(Role::Client),
+            "server" | "listen" => Ok(Role::Server),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown role {}", s))),
+        }
+    }
+}
+
+/// Stops trying the terminal for `cool_down` after `failures` failed exchanges in a row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct CircuitBreaker {
+    pub failures: u32,
+    #[cfg_attr(feature = "serde", serde(rename = "cool_down_ms", with = "millis"))]
+    pub cool_down: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {failures: 5, cool_down: Duration::from_secs(30)}
+    }
+}
+
+impl Default for ClockSkew {
+    fn default() -> Self {
+        Self {threshold: Duration::from_secs(60), utc_offset_mins: 0}
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {interval: Duration::from_millis(200), burst: 1}
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {attempts: 1, backoff: Duration::from_millis(500), jitter: Duration::ZERO}
+    }
+}
+
+/// Everything needed to reach a terminal; cheap to clone into several connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub role: Role,
+    /// Local address to connect from, e.g. the Ethernet address on a machine that also has a modem, or to listen on
+    /// as `Role::Server`.
+    pub bind_addr: Option<IpAddr>,
+    /// Interface to connect through, e.g. `eth0` (Linux only, usually needs `CAP_NET_RAW`).
+    pub bind_device: Option<String>,
+    /// `socks5://[user:pass@]host:port` or `http://[user:pass@]host:port` to tunnel through (needs the `proxy` feature).
+    pub proxy: Option<String>,
+    /// Bound on establishing the TCP connection, separate from the per-frame timeouts.
+    #[cfg_attr(feature = "serde", serde(rename = "connect_timeout_ms", with = "millis"))]
+    pub connect_timeout: Duration,
+    #[cfg_attr(feature = "serde", serde(rename = "write_timeout_ms", with = "millis"))]
+    pub write_timeout: Duration,
+    #[cfg_attr(feature = "serde", serde(rename = "read_timeout_ms", with = "millis"))]
+    pub read_timeout: Duration,
+    #[cfg_attr(feature = "serde", serde(rename = "watchdog_ms", with = "opt_millis"))]
+    pub watchdog: Option<Duration>,
+    /// Interval announced to the terminal in IDL frames.
+    #[cfg_attr(feature = "serde", serde(rename = "keepalive_secs", with = "opt_secs"))]
+    pub keepalive: Option<Duration>,
+    /// Disables Nagle's algorithm so short frames go out at once.
+    pub nodelay: bool,
+    /// Idle time before the OS starts probing a silent connection; unset leaves OS keepalive off.
+    #[cfg_attr(feature = "serde", serde(rename = "tcp_keepalive_secs", with = "opt_secs"))]
+    pub tcp_keepalive: Option<Duration>,
+    /// Gap between keepalive probes, where the OS allows setting it.
+    #[cfg_attr(feature = "serde", serde(rename = "tcp_keepalive_interval_secs", with = "opt_secs"))]
+    pub tcp_keepalive_interval: Option<Duration>,
+    /// A connection unused this long is reopened before VRP, FIN or ABR instead of trusted; one that the
+    /// terminal has visibly closed is reopened regardless.
+    #[cfg_attr(feature = "serde", serde(rename = "stale_after_secs", with = "opt_secs"))]
+    pub stale_after: Option<Duration>,
+    pub retry: RetryPolicy,
+    /// Throttles sends so a runaway polling loop cannot flood the terminal; sends wait rather than fail.
+    pub rate_limit: Option<RateLimit>,
+    pub redact_logs: bool,
+    /// Encoding of string tags on the wire.
+    pub charset: Charset,
+    pub parse_mode: ParseMode,
+    /// Firmware quirks on the wire; the default suits the terminals this driver was written against.
+    pub compat: Compat,
+    /// Dropping the driver runs a short `Vtk::shutdown`, so the terminal is not left accepting cards.
+    pub disable_on_drop: bool,
+    /// A `send` while the previous request is still unanswered waits for that answer, kept for `Vtk::receive`,
+    /// instead of failing with `VtkError::Busy`.
+    pub queue_sends: bool,
+    /// QR shown with each payment request, e.g. `https://pay.example/{op}/{amount}`; `{op}`, `{amount}` (minor units)
+    /// and `{product}` (id) are filled in from the operation.
+    pub qr_template: Option<String>,
+    /// What happens to QR payloads the display cannot take.
+    pub qr_limit: Option<QrLimit>,
+    /// Checked by `request_payment` before anything is sent.
+    pub amount_limits: AmountLimits,
+    pub clock_skew: Option<ClockSkew>,
+    /// Drops frames whose EventNum and OperationNum were already seen this recently, so an event the terminal resends
+    /// after a reconnect is not acted on twice.
+    #[cfg_attr(feature = "serde", serde(rename = "event_dedup_secs", with = "opt_secs"))]
+    pub event_dedup: Option<Duration>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+}
+
+impl Config {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {host: String::from(host), port, ..Self::default()}
+    }
+
+    /// Overrides fields from `VTK_*` environment variables, e.g. `VTK_HOST`, `VTK_PORT`, `VTK_READ_TIMEOUT_MS`.
+    pub fn apply_env(&mut self) -> Result<(), Error> {
+        self.apply_vars(|name| env::var(name).ok())
+    }
+
+    pub fn with_env(mut self) -> Result<Self, Error> {
+        self.apply_env()?;
+        Ok(self)
+    }
+
+    fn apply_vars(&mut self, get: impl Fn(&str) -> Option<String>) -> Result<(), Error> {
+        if let Some(host) = get("VTK_HOST") {
+            self.host = host;
+        }
+        if let Some(port) = parse(&get, "VTK_PORT")? {
+            self.port = port;
+        }
+        if let Some(role) = parse(&get, "VTK_ROLE")? {
+            self.role = role;
+        }
+        if let Some(ip) = parse(&get, "VTK_BIND_ADDR")? {
+            self.bind_addr = Some(ip);
+        }
+        if let Some(device) = get("VTK_BIND_DEVICE") {
+            self.bind_device = Some(device).filter(|d| !d.is_empty());
+        }
+        if let Some(proxy) = get("VTK_PROXY") {
+            self.proxy = Some(proxy).filter(|p| !p.is_empty());
+        }
+        if let Some(ms) = parse(&get, "VTK_CONNECT_TIMEOUT_MS")? {
+            self.connect_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = parse(&get, "VTK_WRITE_TIMEOUT_MS")? {
+            self.write_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = parse(&get, "VTK_READ_TIMEOUT_MS")? {
+            self.read_timeout = Duration::from_millis(ms);
+        }
+        if let Some(ms) = parse(&get, "VTK_WATCHDOG_MS")? {
+            self.watchdog = Some(Duration::from_millis(ms)).filter(|d| !d.is_zero());
+        }
+        if let Some(secs) = parse(&get, "VTK_KEEPALIVE_SECS")? {
+            self.keepalive = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+        }
+        if let Some(nodelay) = parse(&get, "VTK_NODELAY")? {
+            self.nodelay = nodelay;
+        }
+        if let Some(secs) = parse(&get, "VTK_TCP_KEEPALIVE_SECS")? {
+            self.tcp_keepalive = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+        }
+        if let Some(secs) = parse(&get, "VTK_TCP_KEEPALIVE_INTERVAL_SECS")? {
+            self.tcp_keepalive_interval = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+        }
+        if let Some(secs) = parse(&get, "VTK_STALE_AFTER_SECS")? {
+            self.stale_after = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+        }
+        if let Some(attempts) = parse(&get, "VTK_RETRY_ATTEMPTS")? {
+            self.retry.attempts = attempts;
+        }
+        if let Some(ms) = parse(&get, "VTK_RETRY_BACKOFF_MS")? {
+            self.retry.backoff = Duration::from_millis(ms);
+        }
+        if let Some(ms) = parse(&get, "VTK_RETRY_JITTER_MS")? {
+            self.retry.jitter = Duration::from_millis(ms);
+        }
+        if let Some(ms) = parse::<u64>(&get, "VTK_RATE_INTERVAL_MS")? {
+            self.rate_limit = (ms > 0).then(|| RateLimit {interval: Duration::from_millis(ms), ..self.rate_limit.clone().unwrap_or_default()});
+        }
+        if let Some(burst) = parse(&get, "VTK_RATE_BURST")? {
+            if let Some(limit) = &mut self.rate_limit {
+                limit.burst = burst;
+            }
+        }
+        if let Some(failures) = parse::<u32>(&get, "VTK_CIRCUIT_FAILURES")? {
+            self.circuit_breaker = (failures > 0).then(|| CircuitBreaker {failures, ..self.circuit_breaker.clone().unwrap_or_default()});
+        }
+        if let Some(secs) = parse(&get, "VTK_CIRCUIT_COOL_DOWN_SECS")? {
+            if let Some(breaker) = &mut self.circuit_breaker {
+                breaker.cool_down = Duration::from_secs(secs);
+            }
+        }
+        if let Some(redact) = parse(&get, "VTK_REDACT_LOGS")? {
+            self.redact_logs = redact;
+        }
+        if let Some(charset) = parse(&get, "VTK_CHARSET")? {
+            self.charset = charset;
+        }
+        if let Some(mode) = parse(&get, "VTK_PARSE_MODE")? {
+            self.parse_mode = mode;
+        }
+        if let Some(lengths) = parse(&get, "VTK_LENGTH_ENCODING")? {
+            self.compat.lengths = lengths;
+        }
+        if let Some(on) = parse(&get, "VTK_IDL_KEEPALIVE")? {
+            self.compat.idl_keepalive = on;
+        }
+        if let Some(on) = parse(&get, "VTK_FIN_ABOVE_AUTHORIZED")? {
+            self.compat.fin_above_authorized = on;
+        }
+        if let Some(on) = parse(&get, "VTK_DISABLE_ON_DROP")? {
+            self.disable_on_drop = on;
+        }
+        if let Some(on) = parse(&get, "VTK_QUEUE_SENDS")? {
+            self.queue_sends = on;
+        }
+        if let Some(min) = parse(&get, "VTK_MIN_AMOUNT")? {
+            self.amount_limits.min = min;
+        }
+        if let Some(max) = parse(&get, "VTK_MAX_AMOUNT")? {
+            self.amount_limits.max = Some(max).filter(|m| *m > 0);
+        }
+        if let Some(exponent) = parse(&get, "VTK_CURRENCY_EXPONENT")? {
+            self.amount_limits.exponent = exponent;
+        }
+        if let Some(secs) = parse::<u64>(&get, "VTK_CLOCK_SKEW_SECS")? {
+            self.clock_skew = (secs > 0).then(|| ClockSkew {threshold: Duration::from_secs(secs), ..self.clock_skew.clone().unwrap_or_default()});
+        }
+        if let Some(mins) = parse(&get, "VTK_TERMINAL_UTC_OFFSET_MINS")? {
+            if let Some(skew) = &mut self.clock_skew {
+                skew.utc_offset_mins = mins;
+            }
+        }
+        if let Some(secs) = parse(&get, "VTK_EVENT_DEDUP_SECS")? {
+            self.event_dedup = Some(Duration::from_secs(secs)).filter(|d| !d.is_zero());
+        }
+        if let Some(template) = get("VTK_QR_TEMPLATE") {
+            self.qr_template = Some(template).filter(|t| !t.is_empty());
+        }
+        if let Some(max_len) = parse::<usize>(&get, "VTK_QR_MAX_LEN")? {
+            self.qr_limit = (max_len > 0).then(|| QrLimit {max_len, ..self.qr_limit.clone().unwrap_or_default()});
+        }
+        if let Some(fallback) = get("VTK_QR_FALLBACK") {
+            if let Some(limit) = &mut self.qr_limit {
+                limit.fallback = Some(fallback).filter(|f| !f.is_empty());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_toml_str(&fs::read_to_string(path)?)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn from_toml_str(s: &str) -> Result<Self, Error> {
+        toml::from_str(s).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+fn parse<T: FromStr>(get: &dyn Fn(&str) -> Option<String>, name: &str) -> Result<Option<T>, Error> {
+    match get(name) {
+        None => Ok(None),
+        Some(v) => v.trim().parse().map(Some)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("{} has invalid value {:?}", name, v))),
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: 62801,
+            role: Role::default(),
+            bind_addr: None,
+            bind_device: None,
+            proxy: None,
+            connect_timeout: Duration::from_millis(3000),
+            write_timeout: Duration::from_millis(250),
+            read_timeout: Duration::from_millis(2000),
+            watchdog: None,
+            keepalive: None,
+            nodelay: true,
+            tcp_keepalive: None,
+            tcp_keepalive_interval: None,
+            stale_after: None,
+            retry: RetryPolicy::default(),
+            rate_limit: None,
+            redact_logs: true,
+            charset: Charset::default(),
+            parse_mode: ParseMode::default(),
+            compat: Compat::default(),
+            disable_on_drop: false,
+            queue_sends: false,
+            qr_template: None,
+            qr_limit: None,
+            amount_limits: AmountLimits::default(),
+            clock_skew: None,
+            event_dedup: None,
+            circuit_breaker: None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_u64(d.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_millis(u64::deserialize(d)?))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod opt_millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match d {
+            Some(d) => s.serialize_some(&(d.as_millis() as u64)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(Duration::from_millis))
+    }
+}
+
+#[cfg(feature = "serde")]
+mod opt_secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match d {
+            Some(d) => s.serialize_some(&d.as_secs()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(d)?.map(Duration::from_secs))
+    }
+}
@@ -0,0 +1,162 @@
+use std::{fmt, time::Duration};
+
+use crate::vtk::{Tlv, TlvKey, Vtk};
+
+/// One scripted exchange: a request and the tags its response must carry.
+#[derive(Clone)]
+pub struct Check {
+    pub name: String,
+    pub msg_name: String,
+    pub request: Tlv,
+    /// Message name the response must carry, by default the request's.
+    pub answer: String,
+    pub expect: Vec<TlvKey>,
+    pub expect_values: Vec<(TlvKey, Vec<u8>)>,
+    /// Exactly the tags the response must carry, e.g. decoded from a capture of a known-good terminal.
+    pub golden: Option<Tlv>,
+}
+
+impl Check {
+    pub fn new(name: &str, msg_name: &str, request: Tlv) -> Self {
+        Self {name: String::from(name), msg_name: String::from(msg_name), request, answer: String::from(msg_name), expect: Vec::new(), expect_values: Vec::new(), golden: None}
+    }
+
+    pub fn answered_by(mut self, msg_name: &str) -> Self {
+        self.answer = String::from(msg_name);
+        self
+    }
+
+    pub fn expect(mut self, key: TlvKey) -> Self {
+        self.expect.push(key);
+        self
+    }
+
+    pub fn expect_value(mut self, key: TlvKey, value: &[u8]) -> Self {
+        self.expect_values.push((key, value.to_vec()));
+        self
+    }
+
+    pub fn expect_frame(mut self, golden: Tlv) -> Self {
+        self.golden = Some(golden);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: String,
+    pub msg_name: String,
+    pub passed: bool,
+    pub detail: String,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub results: Vec<CheckResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|r| r.passed)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for r in &self.results {
+            let verdict = if r.passed {"PASS"} else {"FAIL"};
+            writeln!(f, "{:<4} {:<20} {} {:>5}ms {}", r.msg_name, r.name, verdict, r.elapsed.as_millis(), r.detail)?;
+        }
+        let failed = self.results.iter().filter(|r| !r.passed).count();
+        write!(f, "{} checks, {} failed", self.results.len(), failed)
+    }
+}
+
+/// The default battery, covering the messages that move no money; see `battery_with_sale` for the rest.
+pub fn battery() -> Vec<Check> {
+    let mut qr = Tlv::new();
+    qr.set_str(TlvKey::QrCodeData, "https://example.com/conformance");
+    vec![
+        Check::new("idle", "IDL", Tlv::new()),
+        Check::new("idle with qr", "IDL", qr),
+        Check::new("disable", "DIS", Tlv::new()),
+        Check::new("idle after disable", "IDL", Tlv::new()),
+    ]
+}
+
+/// `battery` and the sale path: VRP for `amount` then FIN as `operation`, and VRP then ABR as `operation + 1`.
+/// Opt-in, as the first sale charges `amount` once a card is presented within the check's timeout; run it
+/// against a test terminal, with operation numbers it has not seen.
+pub fn battery_with_sale(operation: u32, amount: u64) -> Vec<Check> {
+    let request = |op: u32, amount: Option<u64>| {
+        let mut tlv = Tlv::new();
+        tlv.set_u32(TlvKey::OperationNum, op);
+        if let Some(amount) = amount {
+            tlv.set_u64(TlvKey::AmountInMinorCurrencyUnit, amount);
+        }
+        tlv
+    };
+    let mut checks = battery();
+    checks.extend([
+        Check::new("sale", "VRP", request(operation, Some(amount))),
+        Check::new("finalize", "FIN", request(operation, Some(amount))),
+        Check::new("sale to abort", "VRP", request(operation + 1, Some(amount))),
+        Check::new("abort", "ABR", request(operation + 1, None)),
+    ]);
+    checks
+}
+
+pub fn run(vtk: &mut Vtk, checks: &[Check], timeout: Duration) -> Report {
+    let mut report = Report::default();
+    for check in checks {
+        let start = vtk.now();
+        vtk.disconnect();
+        let res = vtk.exchange(&check.msg_name, check.request.clone(), timeout);
+        let (passed, detail) = match res {
+            Err(e) => (false, e.to_string()),
+            Ok(resp) => verify(check, &resp),
+        };
+        report.results.push(CheckResult {
+            name: check.name.clone(),
+            msg_name: check.msg_name.clone(),
+            passed,
+            detail,
+            elapsed: vtk.now().duration_since(start),
+        });
+    }
+    vtk.disconnect();
+    report
+}
+
+#[deprecated(note = "use `run` with a `Duration`")]
+pub fn run_ms(vtk: &mut Vtk, checks: &[Check], timeout_ms: u64) -> Report {
+    run(vtk, checks, Duration::from_millis(timeout_ms))
+}
+
+fn verify(check: &Check, resp: &Tlv) -> (bool, String) {
+    match resp.get_str(TlvKey::MsgName) {
+        Some(name) if name == check.answer => (),
+        Some(name) => return (false, format!("answered with {}", name)),
+        None => return (false, String::from("response has no message name")),
+    }
+    let missing: Vec<String> = check.expect.iter()
+        .filter(|k| resp.get_bin(**k).is_none())
+        .map(|k| format!("{:?}", k))
+        .collect();
+    if !missing.is_empty() {
+        return (false, format!("missing {}", missing.join(", ")));
+    }
+    for (k, v) in &check.expect_values {
+        match resp.get_bin(*k) {
+            Some(got) if got == v => (),
+            Some(got) => return (false, format!("{:?} is {:?}, expected {:?}", k, String::from_utf8_lossy(got), String::from_utf8_lossy(v))),
+            None => return (false, format!("missing {:?}", k)),
+        }
+    }
+    let diff = check.golden.as_ref().map(|golden| golden.diff(resp)).unwrap_or_default();
+    if !diff.is_empty() {
+        return (false, diff.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "));
+    }
+    (true, String::new())
+}
@@ -0,0 +1,165 @@
+use std::{io::{Error, ErrorKind, Read, Write}, net::{IpAddr, Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs}, time::Duration};
+
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+
+use crate::{clock::Clock, config::Config, session::deadline_after, transport::Transport};
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "fuchsia"))]
+fn bind_device(socket: &Socket, device: &str) -> Result<(), Error> {
+    socket.bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "fuchsia")))]
+fn bind_device(_socket: &Socket, device: &str) -> Result<(), Error> {
+    Err(Error::new(ErrorKind::Unsupported, format!("cannot bind to interface {} on this OS, bind to its address instead", device)))
+}
+
+pub(crate) fn connect_one(config: &Config, addr: SocketAddr) -> Result<TcpStream, Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if let Some(device) = &config.bind_device {
+        bind_device(&socket, device)?;
+    }
+    if let Some(ip) = config.bind_addr {
+        socket.bind(&SocketAddr::new(ip, 0).into())?;
+    }
+    tune(config, &socket)?;
+    socket.connect_timeout(&addr.into(), config.connect_timeout)?;
+    Ok(socket.into())
+}
+
+/// Options that apply whichever side opened the connection.
+fn tune(config: &Config, socket: &Socket) -> Result<(), Error> {
+    socket.set_tcp_nodelay(config.nodelay)?;
+    if let Some(idle) = config.tcp_keepalive {
+        let keepalive = TcpKeepalive::new().with_time(idle);
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd", windows))]
+        let keepalive = match config.tcp_keepalive_interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "proxy")]
+fn via_proxy(config: &Config, url: &str) -> Result<TcpStream, Error> {
+    crate::proxy::connect(config, url)
+}
+
+#[cfg(not(feature = "proxy"))]
+fn via_proxy(_config: &Config, url: &str) -> Result<TcpStream, Error> {
+    Err(Error::new(ErrorKind::Unsupported, format!("proxy {} configured but the proxy feature is disabled", url)))
+}
+
+/// Tries every resolved address in turn, each bounded by the connect timeout rather than the OS default.
+fn connect(config: &Config) -> Result<TcpStream, Error> {
+    if let Some(proxy) = &config.proxy {
+        return via_proxy(config, proxy);
+    }
+    let mut last = Error::new(ErrorKind::NotFound, format!("{} resolves to no address", config.host));
+    for addr in (config.host.as_str(), config.port).to_socket_addrs()? {
+        match connect_one(config, addr) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// A live socket to the terminal, opened from a `Config`.
+pub(crate) struct Connection {
+    tcp: TcpStream,
+}
+
+impl Connection {
+    pub fn open(config: &Config) -> Result<Self, Error> {
+        Self::ready(config, connect(config)?)
+    }
+
+    fn ready(config: &Config, tcp: TcpStream) -> Result<Self, Error> {
+        tcp.set_write_timeout(Some(config.write_timeout))?;
+        Ok(Self {tcp})
+    }
+}
+
+/// Where a `Role::Server` driver waits for the terminal; kept across connections so the port stays bound.
+pub(crate) struct Listener {
+    tcp: TcpListener,
+}
+
+/// How often `accept` looks for a connection, since std has no accept timeout.
+const ACCEPT_POLL: Duration = Duration::from_millis(10);
+
+impl Listener {
+    pub fn bind(config: &Config) -> Result<Self, Error> {
+        let addr = SocketAddr::new(config.bind_addr.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)), config.port);
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(4)?;
+        let tcp: TcpListener = socket.into();
+        tcp.set_nonblocking(true)?;
+        log::info!("waiting for the terminal on {}", tcp.local_addr()?);
+        Ok(Self {tcp})
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.tcp.local_addr()
+    }
+
+    /// The next connection from the terminal within `Config::connect_timeout` on `clock`; others are closed as
+    /// they come.
+    pub fn accept(&self, config: &Config, clock: &dyn Clock) -> Result<Connection, Error> {
+        let terminal = config.host.parse::<IpAddr>().ok().filter(|ip| !ip.is_unspecified());
+        let deadline = deadline_after(clock.now(), config.connect_timeout);
+        loop {
+            match self.tcp.accept() {
+                Ok((tcp, peer)) if terminal.is_some_and(|ip| ip != peer.ip()) => {
+                    log::warn!("refusing a connection from {}, expecting the terminal at {}", peer, config.host);
+                    drop(tcp);
+                },
+                Ok((tcp, peer)) => {
+                    log::info!("terminal connected from {}", peer);
+                    tcp.set_nonblocking(false)?;
+                    let socket = Socket::from(tcp);
+                    tune(config, &socket)?;
+                    return Connection::ready(config, socket.into());
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    let left = deadline.saturating_duration_since(clock.now());
+                    if left.is_zero() {
+                        return Err(Error::new(ErrorKind::TimedOut, format!("the terminal did not connect to port {} in time", config.port)));
+                    }
+                    clock.sleep(left.min(ACCEPT_POLL));
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Transport for Connection {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        self.tcp.write_all(buf)
+    }
+
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        self.tcp.set_read_timeout(Some(timeout))?;
+        self.tcp.read(buf)
+    }
+
+    /// A rebooted terminal shows up as end-of-stream or a reset; bytes waiting to be read mean it is alive.
+    fn is_stale(&mut self) -> bool {
+        if self.tcp.set_nonblocking(true).is_err() {return true;}
+        let stale = match self.tcp.peek(&mut [0]) {
+            Ok(n) => n == 0,
+            Err(e) => e.kind() != ErrorKind::WouldBlock,
+        };
+        stale || self.tcp.set_nonblocking(false).is_err()
+    }
+
+    fn close(&mut self) {
+        _ = self.tcp.shutdown(Shutdown::Both);
+    }
+}
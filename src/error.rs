@@ -0,0 +1,204 @@
+use std::{error, fmt, io::{Error, ErrorKind}, time::Duration};
+
+use crate::{amount::AmountFormat, config::AmountLimits, record::SaleOutcome, vtk::{LastFrames, TlvKey}};
+
+/// Details the driver attaches to the `io::Error`s it returns; the error kind is kept from the cause.
+#[derive(Debug)]
+pub enum VtkError {
+    /// An exchange failed; `request` is the message sent, or `None` for a bare receive.
+    Exchange {
+        request: Option<String>,
+        /// `None` when the failure happened before reading.
+        bytes_read: Option<usize>,
+        /// The header and the MsgName tag of what was read, leaving out the other tags, which may be sensitive.
+        prefix: Vec<u8>,
+        source: Error,
+    },
+    /// `request_payment` refused an amount outside `Config::amount_limits`; nothing was sent.
+    AmountOutOfRange {
+        amount: u64,
+        min: u64,
+        max: Option<u64>,
+        exponent: u8,
+    },
+    /// Nothing was sent: `Config::circuit_breaker` is open for another `retry_in`.
+    CircuitOpen {
+        retry_in: Duration,
+    },
+    /// The connection dropped while the terminal had the payment request, so whether the customer paid is
+    /// unknown: do not dispense. The journal keeps the sale unresolved for recovery.
+    Interrupted {
+        operation: u32,
+        source: Error,
+    },
+    /// `request` was not sent because the answer to `pending` has not been read; see `Config::queue_sends`.
+    Busy {
+        pending: String,
+        request: String,
+    },
+    /// `Vtk::sell_once` was not sent: the journal has a sale with the same `key` that may have charged, finalized
+    /// or, with `outcome` `None`, still unresolved.
+    Duplicate {
+        key: String,
+        operation: u32,
+        outcome: Option<SaleOutcome>,
+    },
+}
+
+/// What the driver knew when sending or reading failed, for an `ErrorSink`.
+#[derive(Debug, Clone, Copy)]
+pub struct ErrorReport<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    /// The message being sent, or the one whose answer was awaited; `None` for a bare receive.
+    pub request: Option<&'a str>,
+    /// `VtkError::code` of `error`, when it has one.
+    pub code: Option<u16>,
+    pub error: &'a Error,
+    /// Redacted as the debug log is, unless `Config::redact_logs` is off.
+    pub last_frames: &'a LastFrames,
+}
+
+/// Receives every failed send or read, e.g. to forward it to Sentry or a pager; a bare `receive` timing out is
+/// not a failure and is left out.
+pub trait ErrorSink: Send {
+    fn report(&mut self, _report: &ErrorReport<'_>) {}
+}
+
+/// The default sink, which drops every report.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoErrorSink;
+
+impl ErrorSink for NoErrorSink {}
+
+pub(crate) const PREFIX_LEN: usize = 16;
+
+impl VtkError {
+    /// The details behind an error returned by the driver, if it has any.
+    pub fn of(err: &Error) -> Option<&VtkError> {
+        err.get_ref().and_then(|e| e.downcast_ref::<VtkError>())
+    }
+
+    /// A number for the variant that stays the same across releases, for dashboards and support to key off
+    /// instead of the text; codes go to new variants in order and are never reused.
+    pub fn code(&self) -> u16 {
+        match self {
+            VtkError::Exchange {..} => 1,
+            VtkError::AmountOutOfRange {..} => 2,
+            VtkError::CircuitOpen {..} => 3,
+            VtkError::Busy {..} => 4,
+            VtkError::Interrupted {..} => 5,
+            VtkError::Duplicate {..} => 6,
+        }
+    }
+
+    /// How soon the same request may go through, for the cases where the driver knows it refused only for now:
+    /// `CircuitOpen` after `retry_in`, and `Busy` as soon as the pending answer is read. A machine can ask the
+    /// customer to wait on these rather than show a failure. The protocol has no busy answer from the terminal
+    /// itself, so everything else is `None`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            VtkError::CircuitOpen {retry_in} => Some(*retry_in),
+            VtkError::Busy {..} => Some(Duration::ZERO),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn exchange(request: Option<&str>, read: Option<&[u8]>, source: Error) -> Error {
+        let kind = source.kind();
+        let prefix = read.map(shown).unwrap_or_default();
+        Error::new(kind, VtkError::Exchange {request: request.map(String::from), bytes_read: read.map(<[u8]>::len), prefix, source})
+    }
+
+    pub(crate) fn amount_out_of_range(amount: u64, limits: &AmountLimits) -> Error {
+        let AmountLimits {min, max, exponent} = *limits;
+        Error::new(ErrorKind::InvalidInput, VtkError::AmountOutOfRange {amount, min, max, exponent})
+    }
+
+    pub(crate) fn circuit_open(retry_in: Duration) -> Error {
+        Error::new(ErrorKind::ConnectionRefused, VtkError::CircuitOpen {retry_in})
+    }
+
+    pub(crate) fn interrupted(operation: u32, source: Error) -> Error {
+        Error::new(source.kind(), VtkError::Interrupted {operation, source})
+    }
+
+    pub(crate) fn busy(pending: &str, request: &str) -> Error {
+        Error::new(ErrorKind::ResourceBusy, VtkError::Busy {pending: String::from(pending), request: String::from(request)})
+    }
+
+    pub(crate) fn duplicate(key: &str, operation: u32, outcome: Option<SaleOutcome>) -> Error {
+        Error::new(ErrorKind::AlreadyExists, VtkError::Duplicate {key: String::from(key), operation, outcome})
+    }
+}
+
+/// What of a read goes in an error: the header and the MsgName tag, wherever it is, up to `PREFIX_LEN`. The other
+/// tags may be card or receipt data, which `Display` would put in logs unredacted.
+fn shown(read: &[u8]) -> Vec<u8> {
+    let mut shown = read[..read.len().min(4)].to_vec();
+    let mut rest = read.get(4..).unwrap_or_default();
+    while let [tag, len, ..] = rest {
+        let end = 2 + *len as usize;
+        if *len >= 0x80 || rest.len() < end {break;}
+        if *tag == TlvKey::MsgName.as_u8() {
+            shown.extend_from_slice(&rest[..end]);
+            break;
+        }
+        rest = &rest[end..];
+    }
+    shown.truncate(PREFIX_LEN);
+    shown
+}
+
+fn major(amount: u64, exponent: u8) -> String {
+    AmountFormat::new(exponent).format(amount)
+}
+
+impl fmt::Display for VtkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VtkError::Exchange {request, bytes_read, prefix, source} => {
+                match request {
+                    Some(msg) => write!(f, "{} ({} exchange", source, msg)?,
+                    None => write!(f, "{} (receive", source)?,
+                }
+                match bytes_read {
+                    None => write!(f, ", nothing read)"),
+                    Some(0) => write!(f, ", 0 bytes read)"),
+                    Some(n) => {
+                        write!(f, ", {} bytes read:", n)?;
+                        for b in prefix {
+                            write!(f, " {:02X}", b)?;
+                        }
+                        if *n > prefix.len() {
+                            write!(f, " ..")?;
+                        }
+                        write!(f, ")")
+                    },
+                }
+            },
+            VtkError::AmountOutOfRange {amount, min, max, exponent} => {
+                write!(f, "amount {} is outside the allowed range {}..", major(*amount, *exponent), major(*min, *exponent))?;
+                match max {
+                    Some(max) => write!(f, "={}", major(*max, *exponent)),
+                    None => Ok(()),
+                }
+            },
+            VtkError::CircuitOpen {retry_in} => write!(f, "circuit open after repeated failures, retry in {:?}", retry_in),
+            VtkError::Interrupted {operation, source} =>
+                write!(f, "operation {} interrupted, whether it was paid is unknown: {}", operation, source),
+            VtkError::Busy {pending, request} => write!(f, "cannot send {} while the answer to {} is pending", request, pending),
+            VtkError::Duplicate {key, operation, outcome} => write!(f, "sale {:?} was already made as operation {} ({})", key, operation,
+                outcome.map_or("unresolved", |o| o.as_str())),
+        }
+    }
+}
+
+impl error::Error for VtkError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            VtkError::Exchange {source, ..} | VtkError::Interrupted {source, ..} => Some(source),
+            VtkError::AmountOutOfRange {..} | VtkError::CircuitOpen {..} | VtkError::Busy {..} | VtkError::Duplicate {..} => None,
+        }
+    }
+}
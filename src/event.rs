@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use crate::frame::Anomaly;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// No successful exchange with the terminal within the watchdog timeout.
+    Degraded { silent_for: Duration },
+    /// The terminal answered again after being marked degraded.
+    Recovered,
+    /// Something odd in the terminal's input that did not fail the call; see `Vtk::warnings`.
+    Anomaly(Anomaly),
+    /// A `Scheduler` resumed (`open`) or paused the terminal.
+    OperatingHours {open: bool},
+    /// The terminal's LocalTime drifted past `Config::clock_skew`; positive when the terminal is ahead.
+    ClockSkew {skew_ms: i64},
+    /// Nothing arrived for `silent_for`, past 1.5 times the keepalive `interval` agreed in IDL; see `Vtk::check_heartbeat`.
+    TerminalSilent {interval: Duration, silent_for: Duration},
+    /// `Config::circuit_breaker` tripped after `failures` failed exchanges; calls fail at once for `cool_down`.
+    CircuitOpen {failures: u32, cool_down: Duration},
+    /// An exchange succeeded after the circuit had opened.
+    CircuitClosed,
+}
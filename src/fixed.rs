@@ -0,0 +1,221 @@
+//! A TLV that never allocates, for firmware-side code and targets without a heap.
+//!
+//! Everything but the conversions to and from `Tlv` at the bottom uses only `core`, so this builds without the
+//! default `std` feature for targets with no heap.
+
+use core::fmt;
+
+use crate::{codec::{header, split_frame, split_tag, Broken}, tag::TlvKey};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedError {
+    /// More than `N_TAGS` tags.
+    TooManyTags,
+    /// The encoded tags do not fit in `BUF` bytes, or the frame in the output buffer.
+    BufferFull,
+    /// A value longer than the 255 bytes a tag can declare.
+    ValueTooLong(usize),
+    /// A frame header with a bad protocol id or a length past the input.
+    BadHeader,
+    /// The value of this tag runs past the end of the body.
+    TruncatedTag(u8),
+    TrailingBytes(usize),
+    DuplicateTag(u8),
+}
+
+impl fmt::Display for FixedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixedError::TooManyTags => write!(f, "too many tags"),
+            FixedError::BufferFull => write!(f, "buffer full"),
+            FixedError::ValueTooLong(n) => write!(f, "value of {} bytes is too long for a tag", n),
+            FixedError::BadHeader => write!(f, "bad frame header"),
+            FixedError::TruncatedTag(tag) => write!(f, "tag 0x{:02X} is truncated", tag),
+            FixedError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            FixedError::DuplicateTag(tag) => write!(f, "duplicate tag 0x{:02X}", tag),
+        }
+    }
+}
+
+impl From<Broken> for FixedError {
+    fn from(broken: Broken) -> Self {
+        match broken {
+            Broken::Truncated {tag, ..} => FixedError::TruncatedTag(tag),
+            Broken::Trailing(n) => FixedError::TrailingBytes(n),
+        }
+    }
+}
+
+/// Up to `N_TAGS` tags kept encoded, in insertion order, in a `BUF`-byte array.
+///
+/// Parsing is always strict: there is nowhere to record what a lenient parse would have skipped.
+#[derive(Clone)]
+pub struct FixedTlv<const N_TAGS: usize, const BUF: usize> {
+    buf: [u8; BUF],
+    used: usize,
+    tags: usize,
+}
+
+impl<const N_TAGS: usize, const BUF: usize> FixedTlv<N_TAGS, BUF> {
+    pub const fn new() -> Self {
+        Self {buf: [0; BUF], used: 0, tags: 0}
+    }
+
+    pub fn parse(raw: &[u8]) -> Result<Self, FixedError> {
+        let mut tlv = Self::new();
+        let mut rest = raw;
+        while let Some(next) = split_tag(rest) {
+            let (tag, value, tail) = next?;
+            rest = tail;
+            let key = TlvKey::from_u8(tag);
+            if tlv.find(key).is_some() {
+                return Err(FixedError::DuplicateTag(tag));
+            }
+            tlv.set_bin(key, value)?;
+        }
+        Ok(tlv)
+    }
+
+    /// Decodes the frame at the start of `raw`, returning it with the number of bytes it took.
+    pub fn decode_frame(raw: &[u8]) -> Result<(Self, usize), FixedError> {
+        let body = split_frame(raw).map_err(|_| FixedError::BadHeader)?;
+        Ok((Self::parse(body)?, body.len() + 4))
+    }
+
+    /// Writes the frame into `out` and returns its length; the MsgName tag must already be set.
+    pub fn encode_frame(&self, out: &mut [u8]) -> Result<usize, FixedError> {
+        let size = self.used + 4;
+        let out = out.get_mut(..size).ok_or(FixedError::BufferFull)?;
+        out[..4].copy_from_slice(&header(self.used).map_err(|_| FixedError::BufferFull)?);
+        out[4..].copy_from_slice(self.as_bytes());
+        Ok(size)
+    }
+
+    /// The encoded body, ready to go on the wire.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.used]
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TlvKey, &[u8])> {
+        let mut rest = self.as_bytes();
+        core::iter::from_fn(move || {
+            let (tag, value, tail) = split_tag(rest)?.ok()?;
+            rest = tail;
+            Some((TlvKey::from_u8(tag), value))
+        })
+    }
+
+    /// Offset and encoded length of `key`.
+    fn find(&self, key: TlvKey) -> Option<(usize, usize)> {
+        let mut offset = 0;
+        for (k, value) in self.iter() {
+            if k == key {
+                return Some((offset, value.len() + 2));
+            }
+            offset += value.len() + 2;
+        }
+        None
+    }
+
+    pub fn get_bin(&self, key: TlvKey) -> Option<&[u8]> {
+        let (offset, size) = self.find(key)?;
+        Some(&self.buf[offset + 2..offset + size])
+    }
+
+    pub fn get_str(&self, key: TlvKey) -> Option<&str> {
+        self.get_bin(key).and_then(|v| core::str::from_utf8(v).ok())
+    }
+
+    /// Sets `key`, replacing any previous value; on error the tlv is left unchanged.
+    pub fn set_bin(&mut self, key: TlvKey, data: &[u8]) -> Result<(), FixedError> {
+        if data.len() > u8::MAX as usize {
+            return Err(FixedError::ValueTooLong(data.len()));
+        }
+        let old = self.find(key);
+        let freed = old.map_or(0, |(_, size)| size);
+        if old.is_none() && self.tags >= N_TAGS {
+            return Err(FixedError::TooManyTags);
+        }
+        if self.used - freed + data.len() + 2 > BUF {
+            return Err(FixedError::BufferFull);
+        }
+        self.remove(key);
+        self.buf[self.used] = key.as_u8();
+        self.buf[self.used + 1] = data.len() as u8;
+        self.buf[self.used + 2..self.used + 2 + data.len()].copy_from_slice(data);
+        self.used += data.len() + 2;
+        self.tags += 1;
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: TlvKey, data: &str) -> Result<(), FixedError> {
+        self.set_bin(key, data.as_bytes())
+    }
+
+    /// Removes `key`, returning whether it was there.
+    pub fn remove(&mut self, key: TlvKey) -> bool {
+        let Some((offset, size)) = self.find(key) else {return false;};
+        self.buf.copy_within(offset + size..self.used, offset);
+        self.used -= size;
+        self.tags -= 1;
+        true
+    }
+}
+
+impl<const N_TAGS: usize, const BUF: usize> Default for FixedTlv<N_TAGS, BUF> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Masks sensitive values as `Tlv`'s `Debug` does.
+impl<const N_TAGS: usize, const BUF: usize> fmt::Debug for FixedTlv<N_TAGS, BUF> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter().map(|(k, v)| (k, Value(k, v)))).finish()
+    }
+}
+
+struct Value<'a>(TlvKey, &'a [u8]);
+
+impl fmt::Debug for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_sensitive() {
+            return write!(f, "<redacted {} bytes>", self.1.len());
+        }
+        write!(f, "{:?}", self.1)
+    }
+}
+
+impl core::error::Error for FixedError {}
+
+#[cfg(feature = "std")]
+impl<const N_TAGS: usize, const BUF: usize> From<&FixedTlv<N_TAGS, BUF>> for crate::Tlv {
+    fn from(fixed: &FixedTlv<N_TAGS, BUF>) -> Self {
+        let mut tlv = crate::Tlv::new();
+        for (key, value) in fixed.iter() {
+            tlv.set_bin(key, value);
+        }
+        tlv
+    }
+}
+
+#[cfg(feature = "std")]
+impl<const N_TAGS: usize, const BUF: usize> TryFrom<&crate::Tlv> for FixedTlv<N_TAGS, BUF> {
+    type Error = FixedError;
+
+    fn try_from(tlv: &crate::Tlv) -> Result<Self, FixedError> {
+        let mut fixed = Self::new();
+        for (key, value) in tlv.data() {
+            fixed.set_bin(*key, value)?;
+        }
+        Ok(fixed)
+    }
+}
@@ -0,0 +1,169 @@
+use std::{fmt, io::{Error, ErrorKind}, str::FromStr};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{codec::{header, split_frame, BadHeader, Broken, TooLong}, schema::Violation, vtk::{Tlv, TlvKey}};
+
+pub use crate::codec::PROTOCOL_ID;
+
+/// How the parser treats malformed input, and `send` frames that break their schema; lenient keeps what it can and records an `Anomaly`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "lowercase"))]
+pub enum ParseMode {
+    Strict,
+    #[default]
+    Lenient,
+}
+
+impl ParseMode {
+    pub(crate) fn tolerate(self, warnings: &mut Vec<Anomaly>, anomaly: Anomaly) -> Result<(), Error> {
+        match self {
+            ParseMode::Strict => Err(Error::new(ErrorKind::InvalidData, anomaly.to_string())),
+            ParseMode::Lenient => {
+                warnings.push(anomaly);
+                Ok(())
+            },
+        }
+    }
+}
+
+impl FromStr for ParseMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(ParseMode::Strict),
+            "lenient" => Ok(ParseMode::Lenient),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown parse mode {}", s))),
+        }
+    }
+}
+
+/// How tag lengths go on the wire. Plain one-byte lengths are what this driver has always sent; some terminal
+/// firmware wants BER long forms, `0x81 n` and `0x82 n n`, for values of 128 bytes and up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "lowercase"))]
+pub enum LengthEncoding {
+    #[default]
+    Short,
+    Ber,
+}
+
+impl FromStr for LengthEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "short" => Ok(LengthEncoding::Short),
+            "ber" => Ok(LengthEncoding::Ber),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown length encoding {}", s))),
+        }
+    }
+}
+
+/// Unexpected input the driver got past without failing the call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A tag this driver does not know; it is kept as `TlvKey::Unknown` in both modes.
+    UnknownTag(u8),
+    /// Bytes after the last complete tag or frame.
+    TrailingBytes(usize),
+    /// The tag appeared again; the last value is kept.
+    DuplicateTag(u8),
+    /// The value runs past the end of the body and was dropped.
+    TruncatedTag {tag: u8, declared: usize, available: usize},
+    /// The answer's MsgName differs from the request's.
+    UnexpectedMessage {expected: String, got: String},
+    /// An answer carrying another operation's number, e.g. one that arrived after its request timed out; skipped.
+    StaleAnswer {operation: u64},
+    /// A frame repeating an event already seen within `Config::event_dedup`, e.g. resent after a reconnect; dropped.
+    ReplayedEvent {event: u64, operation: Option<u64>},
+    /// The frame departs from its message's entry in `schema::REGISTRY`.
+    SchemaViolation {msg_name: String, violation: Violation},
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::UnknownTag(tag) => write!(f, "unknown tag 0x{:02X}", tag),
+            Anomaly::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+            Anomaly::DuplicateTag(tag) => write!(f, "duplicate tag 0x{:02X}", tag),
+            Anomaly::TruncatedTag {tag, declared, available} =>
+                write!(f, "tag 0x{:02X} declares {} bytes, {} available", tag, declared, available),
+            Anomaly::UnexpectedMessage {expected, got} => write!(f, "expected {} answer, got {}", expected, got),
+            Anomaly::StaleAnswer {operation} => write!(f, "skipped a stale answer for operation {}", operation),
+            Anomaly::ReplayedEvent {event, operation: Some(operation)} =>
+                write!(f, "dropped a replayed event {} for operation {}", event, operation),
+            Anomaly::ReplayedEvent {event, operation: None} => write!(f, "dropped a replayed event {}", event),
+            Anomaly::SchemaViolation {msg_name, violation} => write!(f, "{}: {}", msg_name, violation),
+        }
+    }
+}
+
+impl From<Broken> for Anomaly {
+    fn from(broken: Broken) -> Self {
+        match broken {
+            Broken::Truncated {tag, declared, available} => Anomaly::TruncatedTag {tag, declared, available},
+            Broken::Trailing(n) => Anomaly::TrailingBytes(n),
+        }
+    }
+}
+
+/// One VTK message: a big-endian length, the protocol id and the TLV body.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub msg_name: String,
+    pub tlv: Tlv,
+}
+
+impl Frame {
+    pub fn new(msg_name: &str, tlv: Tlv) -> Self {
+        Self {msg_name: String::from(msg_name), tlv}
+    }
+
+    /// `msg_name` and the `tags` of `Tlv::to_json`, e.g. for shipping traffic to a log store.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({"msg_name": self.msg_name, "tags": self.tlv.to_json()})
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        self.encode_as(LengthEncoding::Short)
+    }
+
+    /// Fails with `InvalidInput` on a value longer than `lengths` can declare or a body past the 16-bit header.
+    pub fn encode_as(&self, lengths: LengthEncoding) -> Result<Vec<u8>, Error> {
+        let mut tlv = self.tlv.clone();
+        tlv.set_str(TlvKey::MsgName, &self.msg_name);
+        let mut body = tlv.serialize_as(lengths)?;
+        let header = header(body.len()).map_err(|TooLong(n)| {
+            Error::new(ErrorKind::InvalidInput, format!("{} body of {} bytes is too long for a frame", self.msg_name, n))
+        })?;
+        let mut buf = Vec::with_capacity(body.len() + 4);
+        buf.extend_from_slice(&header);
+        buf.append(&mut body);
+        Ok(buf)
+    }
+
+    /// Decodes the frame at the start of `raw`, returning it with the number of bytes it took.
+    pub fn decode(raw: &[u8]) -> Result<(Self, usize), Error> {
+        Self::decode_with(raw, ParseMode::Lenient, &mut Vec::new())
+    }
+
+    pub fn decode_with(raw: &[u8], mode: ParseMode, warnings: &mut Vec<Anomaly>) -> Result<(Self, usize), Error> {
+        Self::decode_as(raw, mode, LengthEncoding::Short, warnings)
+    }
+
+    pub fn decode_as(raw: &[u8], mode: ParseMode, lengths: LengthEncoding, warnings: &mut Vec<Anomaly>) -> Result<(Self, usize), Error> {
+        let body = split_frame(raw).map_err(|e| match e {
+            BadHeader::Short => Error::new(ErrorKind::UnexpectedEof, "frame shorter than its header"),
+            BadHeader::ProtocolId([p0, p1]) => Error::new(ErrorKind::InvalidData, format!("unexpected protocol id {:02X}{:02X}", p0, p1)),
+            BadHeader::Length {declared, available} =>
+                Error::new(ErrorKind::UnexpectedEof, format!("frame declares {} bytes, {} available", declared, available)),
+        })?;
+        let tlv = Tlv::parse_as(body, mode, lengths, warnings)?;
+        let msg_name = String::from(tlv.get_str(TlvKey::MsgName).unwrap_or_default());
+        Ok((Self {msg_name, tlv}, body.len() + 4))
+    }
+}
@@ -0,0 +1,172 @@
+use std::{collections::{BTreeMap, HashMap}, fs::{File, OpenOptions}, io::{Error, ErrorKind, Read, Write}, path::{Path, PathBuf}};
+
+use crate::{record::SaleOutcome, session::Product};
+
+/// A payment request that was sent to the terminal, written before the frame goes out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub operation: u32,
+    pub amount: u64,
+    pub product: Option<Product>,
+    pub requested_at_ms: u64,
+    /// The caller's idempotency key, from `Vtk::sell_once`.
+    pub key: Option<String>,
+}
+
+/// Durable record of in-flight sales; entries left unresolved after a crash need reconciling with the terminal.
+pub trait Journal: Send {
+    fn append(&mut self, entry: &JournalEntry) -> Result<(), Error>;
+    fn list_unresolved(&self) -> Result<Vec<JournalEntry>, Error>;
+    fn mark_resolved(&mut self, operation: u32, outcome: SaleOutcome) -> Result<(), Error>;
+    /// The operation appended with idempotency `key`, resolved or not, and its outcome once it has one. Keys
+    /// must outlive resolution, so a journal that does not keep them refuses.
+    fn find_key(&self, _key: &str) -> Result<Option<(u32, Option<SaleOutcome>)>, Error> {
+        Err(Error::new(ErrorKind::Unsupported, "this journal does not keep idempotency keys"))
+    }
+    /// The highest operation number ever appended, resolved or not, so a restarted driver does not reuse it; by
+    /// default only unresolved entries are looked at.
+    fn last_operation(&self) -> Option<u32> {
+        self.list_unresolved().ok()?.iter().map(|e| e.operation).max()
+    }
+    /// Makes everything written so far durable; called on shutdown.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Append-only text file, synced after every write. One line per event:
+/// `P <op> <amount> <requested_at_ms> <product id|-> <hex product name|-> [<hex key>]` or `R <op> <outcome>`.
+pub struct FileJournal {
+    path: PathBuf,
+    file: File,
+    pending: BTreeMap<u32, JournalEntry>,
+    keys: HashMap<String, (u32, Option<SaleOutcome>)>,
+    last: Option<u32>,
+}
+
+fn bad(line: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad journal line: {}", line))
+}
+
+/// The whole lines of `file`. An unterminated last line, left by a crash in the middle of a write, is cut off
+/// the file so appends start on a line of their own.
+pub(crate) fn complete_lines(mut file: &File, path: &Path) -> Result<String, Error> {
+    let mut raw = Vec::new();
+    file.read_to_end(&mut raw)?;
+    let complete = raw.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    if complete < raw.len() {
+        log::warn!("{}: dropping the unterminated last line {:?}", path.display(), String::from_utf8_lossy(&raw[complete..]));
+        file.set_len(complete as u64)?;
+        raw.truncate(complete);
+    }
+    String::from_utf8(raw).map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))
+}
+
+pub(crate) fn hex(s: &str) -> String {
+    s.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(s: &str) -> Option<String> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {return None;}
+    let bytes = (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_entry(f: &[&str]) -> Option<JournalEntry> {
+    let (f, key) = match f {
+        [rest @ .., key] if rest.len() == 5 => (rest, Some(unhex(key)?)),
+        _ => (f, None),
+    };
+    let [op, amount, at, id, name] = f else {return None;};
+    let id = if *id == "-" {None} else {Some(id.parse().ok()?)};
+    let name = if *name == "-" {None} else {Some(unhex(name)?)};
+    Some(JournalEntry {
+        operation: op.parse().ok()?,
+        amount: amount.parse().ok()?,
+        product: (id.is_some() || name.is_some()).then_some(Product {id, name}),
+        requested_at_ms: at.parse().ok()?,
+        key,
+    })
+}
+
+impl FileJournal {
+    /// Opens or creates the journal, replaying it to find unresolved entries.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).read(true).open(&path)?;
+        let (mut pending, mut keys, mut last) = (BTreeMap::new(), HashMap::new(), None);
+        for line in complete_lines(&file, &path)?.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.split_first() {
+                Some((&"P", rest)) => {
+                    let entry = parse_entry(rest).ok_or_else(|| bad(line))?;
+                    if let Some(key) = &entry.key {
+                        keys.insert(key.clone(), (entry.operation, None));
+                    }
+                    last = last.max(Some(entry.operation));
+                    pending.insert(entry.operation, entry);
+                },
+                Some((&"R", [op, outcome])) => {
+                    let op = op.parse().map_err(|_| bad(line))?;
+                    if let Some(key) = pending.remove(&op).and_then(|e| e.key) {
+                        keys.insert(key, (op, Some(outcome.parse().map_err(|_| bad(line))?)));
+                    }
+                },
+                Some((&"", [])) => (),
+                _ => return Err(bad(line)),
+            }
+        }
+        Ok(Self {path, file, pending, keys, last})
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_line(&mut self, line: String) -> Result<(), Error> {
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()
+    }
+}
+
+impl Journal for FileJournal {
+    fn append(&mut self, entry: &JournalEntry) -> Result<(), Error> {
+        let product = entry.product.as_ref();
+        let id = product.and_then(|p| p.id).map(|id| id.to_string()).unwrap_or_else(|| String::from("-"));
+        let name = product.and_then(|p| p.name.as_deref()).map(hex).unwrap_or_else(|| String::from("-"));
+        let key = entry.key.as_deref().map(|k| format!(" {}", hex(k))).unwrap_or_default();
+        self.write_line(format!("P {} {} {} {} {}{}", entry.operation, entry.amount, entry.requested_at_ms, id, name, key))?;
+        if let Some(key) = &entry.key {
+            self.keys.insert(key.clone(), (entry.operation, None));
+        }
+        self.last = self.last.max(Some(entry.operation));
+        self.pending.insert(entry.operation, entry.clone());
+        Ok(())
+    }
+
+    fn list_unresolved(&self) -> Result<Vec<JournalEntry>, Error> {
+        Ok(self.pending.values().cloned().collect())
+    }
+
+    fn mark_resolved(&mut self, operation: u32, outcome: SaleOutcome) -> Result<(), Error> {
+        if let Some(entry) = self.pending.remove(&operation) {
+            self.write_line(format!("R {} {}", operation, outcome.as_str()))?;
+            if let Some(key) = entry.key {
+                self.keys.insert(key, (operation, Some(outcome)));
+            }
+        }
+        Ok(())
+    }
+
+    fn last_operation(&self) -> Option<u32> {
+        self.last
+    }
+
+    fn find_key(&self, key: &str) -> Result<Option<(u32, Option<SaleOutcome>)>, Error> {
+        Ok(self.keys.get(key).copied())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.file.sync_all()
+    }
+}
@@ -0,0 +1,107 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+#[cfg(feature = "std")]
+mod amount;
+#[cfg(feature = "std")]
+pub mod audit;
+#[cfg(feature = "std")]
+pub mod capability;
+#[cfg(feature = "std")]
+pub mod catalog;
+#[cfg(feature = "std")]
+mod charset;
+#[cfg(feature = "std")]
+pub mod clock;
+mod codec;
+#[cfg(feature = "std")]
+mod config;
+#[cfg(feature = "std")]
+pub mod conformance;
+#[cfg(feature = "std")]
+mod connection;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "std")]
+mod event;
+mod fixed;
+#[cfg(feature = "std")]
+pub mod frame;
+#[cfg(feature = "std")]
+pub mod journal;
+#[cfg(feature = "std")]
+pub mod logging;
+#[cfg(feature = "std")]
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod mdb;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "std")]
+pub mod outbox;
+#[cfg(feature = "std")]
+pub mod prelude;
+#[cfg(feature = "std")]
+pub mod receipt;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+pub mod rng;
+#[cfg(feature = "std")]
+mod rotate;
+#[cfg(feature = "schedule")]
+pub mod schedule;
+#[cfg(feature = "std")]
+pub mod schema;
+#[cfg(feature = "std")]
+mod session;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+mod tag;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+mod vtk;
+#[cfg(feature = "node")]
+mod node;
+#[cfg(feature = "proxy")]
+mod proxy;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "std")]
+pub use crate::amount::AmountFormat;
+#[cfg(feature = "std")]
+pub use crate::capability::Capabilities;
+#[cfg(feature = "std")]
+pub use crate::catalog::Catalog;
+#[cfg(feature = "std")]
+pub use crate::charset::Charset;
+#[cfg(feature = "std")]
+pub use crate::config::{AmountLimits, CircuitBreaker, ClockSkew, Compat, Config, QrLimit, RateLimit, RetryPolicy, Role};
+#[cfg(feature = "std")]
+pub use crate::error::{ErrorReport, ErrorSink, NoErrorSink, VtkError};
+#[cfg(feature = "std")]
+pub use crate::event::Event;
+pub use crate::fixed::{FixedError, FixedTlv};
+#[cfg(feature = "std")]
+pub use crate::frame::{Anomaly, Frame, LengthEncoding, ParseMode};
+#[cfg(feature = "std")]
+pub use crate::journal::{FileJournal, Journal, JournalEntry};
+#[cfg(feature = "std")]
+pub use crate::outbox::{FileOutbox, Outbox};
+#[cfg(feature = "std")]
+pub use crate::receipt::{EscPos, ReceiptSink};
+#[cfg(feature = "std")]
+pub use crate::record::{SaleOutcome, SaleRecord};
+#[cfg(feature = "std")]
+pub use crate::rotate::{Rotate, RotatingFile};
+#[cfg(feature = "std")]
+pub use crate::session::{Payment, Product};
+pub use crate::tag::{TagCategory, TlvKey, ValueType};
+#[cfg(feature = "std")]
+pub use crate::vtk::{operator_token, Authorize, Counters, LastFrames, TagDiff, Tlv, TlvValue, Vtk, VtkBuilder, MAX_DISPLAY_TIME};
@@ -0,0 +1,85 @@
+use std::fmt;
+
+use crate::{codec::{split_frame, split_tag_as}, vtk::{Tlv, TlvKey}};
+
+/// Tags that may carry card data or receipts and are masked in wire logs; vendor tags the driver has no name for
+/// might, too.
+pub fn is_sensitive(key: TlvKey) -> bool {
+    key.is_sensitive()
+}
+
+/// Displays a frame's tags for logging, masking sensitive ones unless `redact` is off.
+pub struct FrameDump<'a> {
+    pub msg_name: &'a str,
+    pub tlv: &'a Tlv,
+    pub redact: bool,
+}
+
+impl fmt::Display for FrameDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg_name)?;
+        let mut keys: Vec<&TlvKey> = self.tlv.data().keys().filter(|k| **k != TlvKey::MsgName).collect();
+        keys.sort_by_key(|k| k.as_u8());
+        for k in keys {
+            let v = &self.tlv.data()[k];
+            write!(f, " {:?}=", k)?;
+            if self.redact {
+                write_masked(f, *k, v)?;
+            } else {
+                write_value(f, v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `write_value`, or `<redacted N bytes>` for a sensitive `key`.
+pub(crate) fn write_masked(f: &mut fmt::Formatter<'_>, key: TlvKey, v: &[u8]) -> fmt::Result {
+    if is_sensitive(key) {
+        return write!(f, "<redacted {} bytes>", v.len());
+    }
+    write_value(f, v)
+}
+
+/// A value for `{:?}`, through `write_masked`.
+pub(crate) struct Masked<'a>(pub TlvKey, pub &'a [u8]);
+
+impl fmt::Debug for Masked<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_masked(f, self.0, self.1)
+    }
+}
+
+/// `raw`, one or more frames as read off the wire, with the values of sensitive tags overwritten by `*`. Whatever
+/// does not parse as a frame is overwritten whole, as it might be anything.
+pub(crate) fn redact_wire(raw: &[u8], ber: bool) -> Vec<u8> {
+    let mut out = raw.to_vec();
+    let mut at = 0;
+    while at < out.len() {
+        let Ok(body) = split_frame(&raw[at..]) else {break;};
+        let (start, mut rest) = (at + 4, body);
+        while let Some(Ok((tag, value, tail))) = split_tag_as(rest, ber) {
+            if is_sensitive(TlvKey::from_u8(tag)) {
+                let end = start + body.len() - tail.len();
+                out[end - value.len()..end].fill(b'*');
+            }
+            rest = tail;
+        }
+        out[start + body.len() - rest.len()..start + body.len()].fill(b'*');
+        at = start + body.len();
+    }
+    out[at..].fill(b'*');
+    out
+}
+
+/// Printable ASCII as a quoted string, anything else as `0x` and hex.
+pub(crate) fn write_value(f: &mut fmt::Formatter<'_>, v: &[u8]) -> fmt::Result {
+    if v.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        return write!(f, "{:?}", String::from_utf8_lossy(v));
+    }
+    write!(f, "0x")?;
+    for b in v {
+        write!(f, "{:02X}", b)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! The protocol without I/O: bytes from the terminal go in, split or merged however the network delivered them,
+//! and frames come out; frames to send come back as bytes. `Vtk` drives a `Machine` over a blocking `Transport`;
+//! an async client can drive the same one over its own socket.
+
+use std::{io::{Error, ErrorKind}, mem};
+
+use crate::{codec::{split_frame, BadHeader}, frame::{Anomaly, Frame, LengthEncoding, ParseMode, PROTOCOL_ID}, schema::{self, Direction, Violation}, vtk::{Tlv, TlvKey}};
+
+/// What `Machine::handle_input` made of the input, in the order it arrived.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// A frame from the terminal, checked against its schema.
+    Received(Tlv),
+    /// Input got past in lenient mode.
+    Anomaly(Anomaly),
+    /// Input dropped as unusable: a bad header, after which nothing can be trusted to start a frame, or a frame
+    /// strict mode does not accept.
+    Rejected(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Machine {
+    pub mode: ParseMode,
+    pub lengths: LengthEncoding,
+    /// The start of a frame whose rest has not arrived.
+    buf: Vec<u8>,
+    awaiting: Option<String>,
+}
+
+fn bad_header(e: BadHeader) -> String {
+    match e {
+        BadHeader::ProtocolId([p0, p1]) => format!("unexpected protocol id {:02X}{:02X}", p0, p1),
+        BadHeader::Length {declared, ..} => format!("frame declares {} bytes", declared),
+        BadHeader::Short => String::from("frame shorter than its header"),
+    }
+}
+
+impl Machine {
+    pub fn new(mode: ParseMode, lengths: LengthEncoding) -> Self {
+        Self {mode, lengths, ..Self::default()}
+    }
+
+    /// The bytes of `msg_name` for the wire; in strict mode, an error if it breaks its schema.
+    pub fn encode(&self, msg_name: &str, tlv: Tlv) -> Result<Vec<u8>, Error> {
+        self.encode_allowing(msg_name, tlv, &[])
+    }
+
+    /// `encode` that lets `extra` tags through even where the schema does not allow them.
+    pub fn encode_allowing(&self, msg_name: &str, tlv: Tlv, extra: &[TlvKey]) -> Result<Vec<u8>, Error> {
+        let violations = schema::check(msg_name, Direction::ToTerminal, &tlv).into_iter()
+            .filter(|v| !matches!(v, Violation::Unexpected(key) if extra.contains(key)));
+        for violation in violations {
+            match self.mode {
+                ParseMode::Strict => return Err(Error::new(ErrorKind::InvalidInput, format!("{}: {}", msg_name, violation))),
+                ParseMode::Lenient => log::warn!("sending {} anyway: {}", msg_name, violation),
+            }
+        }
+        Frame::new(msg_name, tlv).encode_as(self.lengths)
+    }
+
+    /// Records that `msg_name` went out, so its answer is awaited.
+    pub fn sent(&mut self, msg_name: &str) {
+        self.awaiting = Some(String::from(msg_name));
+    }
+
+    /// The request no frame has arrived for since it was sent.
+    pub fn awaiting(&self) -> Option<&str> {
+        self.awaiting.as_deref()
+    }
+
+    /// Stops waiting for the answer, e.g. after it timed out; one arriving late is still decoded.
+    pub fn abandon(&mut self) {
+        self.awaiting = None;
+    }
+
+    /// Bytes held back until the rest of their frame arrives.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Forgets the partial frame and the awaited answer, e.g. when the connection closes.
+    pub fn reset(&mut self) {
+        self.buf.clear();
+        self.awaiting = None;
+    }
+
+    /// Takes the next bytes from the terminal and returns what became of every frame they complete.
+    ///
+    /// In lenient mode, a bad header right after a frame in the same input is `Anomaly::TrailingBytes`, as when
+    /// decoding one frame; anywhere else it is rejected.
+    pub fn handle_input(&mut self, input: &[u8]) -> Vec<Action> {
+        self.buf.extend_from_slice(input);
+        let mut actions = Vec::new();
+        let mut decoded = false;
+        loop {
+            let used = match split_frame(&self.buf) {
+                Ok(body) => body.len() + 4,
+                Err(BadHeader::Short) => break,
+                Err(BadHeader::Length {declared, ..}) if declared >= PROTOCOL_ID.len() => break,
+                Err(e) => {
+                    let dropped = mem::take(&mut self.buf).len();
+                    actions.push(match self.mode {
+                        ParseMode::Lenient if decoded => Action::Anomaly(Anomaly::TrailingBytes(dropped)),
+                        _ => Action::Rejected(bad_header(e)),
+                    });
+                    break;
+                },
+            };
+            let raw: Vec<u8> = self.buf.drain(..used).collect();
+            self.decode(&raw, &mut actions);
+            decoded = true;
+        }
+        actions
+    }
+
+    fn decode(&mut self, raw: &[u8], actions: &mut Vec<Action>) {
+        let mode = self.mode;
+        let mut warnings = Vec::new();
+        let res = Frame::decode_as(raw, mode, self.lengths, &mut warnings).and_then(|(frame, _)| {
+            for violation in schema::check(&frame.msg_name, Direction::FromTerminal, &frame.tlv) {
+                mode.tolerate(&mut warnings, Anomaly::SchemaViolation {msg_name: frame.msg_name.clone(), violation})?;
+            }
+            Ok(frame)
+        });
+        self.awaiting = None;
+        actions.extend(warnings.into_iter().map(Action::Anomaly));
+        actions.push(match res {
+            Ok(frame) => Action::Received(frame.tlv),
+            Err(e) => Action::Rejected(e.to_string()),
+        });
+    }
+}
@@ -1,8 +1,6 @@
-mod vtk;
-
 use std::{thread, time::Duration};
 
-use crate::vtk::{Tlv, TlvKey};
+use vtk::{Tlv, TlvKey};
 
 fn main() {
     let mut dev = vtk::Vtk::new("192.168.0.12", 62801).unwrap();
@@ -0,0 +1,146 @@
+use std::{collections::VecDeque, io::Error, time::Duration};
+
+use crate::{session::{Payment, Product}, vtk::Vtk};
+
+/// Cashless device states from the MDB specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdbState {
+    Inactive,
+    Disabled,
+    Enabled,
+    SessionIdle,
+    Vend,
+}
+
+/// Commands the VMC sends to the cashless device, already decoded from the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdbCommand {
+    Reset,
+    Poll,
+    ReaderEnable,
+    ReaderDisable,
+    ReaderCancel,
+    /// Price in scaled MDB units and the selected item number.
+    VendRequest {price: u16, item: u16},
+    VendCancel,
+    VendSuccess {item: u16},
+    VendFailure,
+    SessionComplete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MdbResponse {
+    Ack,
+    JustReset,
+    /// Funds are unknown while the card has not been presented yet.
+    BeginSession {funds: Option<u16>},
+    VendApproved {price: u16},
+    VendDenied,
+    EndSession,
+    Cancelled,
+    CommandOutOfSequence,
+}
+
+/// Drives the terminal from MDB cashless commands, in "always idle" mode:
+/// a session is opened as soon as the reader is enabled and the card is
+/// read during VEND REQUEST.
+pub struct MdbAdapter {
+    state: MdbState,
+    /// Minor currency units per MDB price unit, from the reader config.
+    scale: u64,
+    vend_timeout: Duration,
+    payment: Option<Payment>,
+    polls: VecDeque<MdbResponse>,
+}
+
+impl MdbAdapter {
+    pub fn new(scale: u64, vend_timeout: Duration) -> Self {
+        Self {state: MdbState::Inactive, scale, vend_timeout, payment: None, polls: VecDeque::new()}
+    }
+
+    pub fn state(&self) -> MdbState {
+        self.state
+    }
+
+    fn amount(&self, price: u16) -> u64 {
+        price as u64 * self.scale
+    }
+
+    /// Queues the next session so the VMC can send VEND REQUEST without a card tap first.
+    fn begin_session(&mut self) {
+        self.state = MdbState::Enabled;
+        self.polls.push_back(MdbResponse::BeginSession {funds: None});
+    }
+
+    pub fn handle(&mut self, vtk: &mut Vtk, cmd: MdbCommand) -> Result<MdbResponse, Error> {
+        use MdbCommand::*;
+        use MdbState::*;
+        let resp = match (self.state, cmd) {
+            (_, Reset) => {
+                if let Some(p) = self.payment.take() {
+                    vtk.abort(p.operation)?;
+                }
+                self.polls.clear();
+                self.polls.push_back(MdbResponse::JustReset);
+                self.state = Inactive;
+                MdbResponse::Ack
+            },
+            (_, Poll) => {
+                let resp = self.polls.pop_front().unwrap_or(MdbResponse::Ack);
+                if let (Enabled, MdbResponse::BeginSession {..}) = (self.state, resp) {
+                    self.state = SessionIdle;
+                }
+                resp
+            },
+            (Inactive | Disabled, ReaderEnable) => {
+                vtk.idle(None)?;
+                self.begin_session();
+                MdbResponse::Ack
+            },
+            (Inactive | Enabled | SessionIdle, ReaderDisable) => {
+                vtk.disable()?;
+                self.polls.retain(|r| !matches!(r, MdbResponse::BeginSession {..}));
+                self.state = Disabled;
+                MdbResponse::Ack
+            },
+            (SessionIdle | Vend, ReaderCancel) => {
+                if let Some(p) = self.payment.take() {
+                    vtk.abort(p.operation)?;
+                }
+                self.begin_session();
+                MdbResponse::Cancelled
+            },
+            (SessionIdle, VendRequest {price, item}) => {
+                let product = Product {id: Some(item as u32), name: None};
+                let payment = vtk.request_payment(self.amount(price), Some(&product), self.vend_timeout)?;
+                if payment.approved {
+                    self.payment = Some(payment);
+                    self.state = Vend;
+                    MdbResponse::VendApproved {price}
+                } else {
+                    MdbResponse::VendDenied
+                }
+            },
+            (Vend, VendCancel) | (Vend, VendFailure) => {
+                if let Some(p) = self.payment.take() {
+                    vtk.abort(p.operation)?;
+                }
+                self.state = SessionIdle;
+                if cmd == VendCancel {MdbResponse::VendDenied} else {MdbResponse::Ack}
+            },
+            (Vend, VendSuccess {..}) => {
+                if let Some(p) = self.payment.take() {
+                    vtk.finalize(p.operation, p.amount)?;
+                }
+                self.state = SessionIdle;
+                MdbResponse::Ack
+            },
+            (SessionIdle, SessionComplete) => {
+                self.begin_session();
+                MdbResponse::EndSession
+            },
+            _ => MdbResponse::CommandOutOfSequence,
+        };
+        Ok(resp)
+    }
+}
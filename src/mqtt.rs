@@ -0,0 +1,166 @@
+use std::{io::Error, sync::{atomic::{AtomicBool, Ordering}, mpsc::Receiver, Arc}, thread::{self, JoinHandle}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use rumqttc::{Client, MqttOptions, Outgoing, QoS, TlsConfiguration, Transport};
+use serde_json::{json, Value};
+
+use crate::{event::Event, record::SaleOutcome, session::Payment, vtk::Vtk};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topics {
+    pub status: String,
+    pub events: String,
+    pub sales: String,
+}
+
+impl Topics {
+    /// `<prefix>/status`, `<prefix>/events` and `<prefix>/sales`.
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            status: format!("{}/status", prefix),
+            events: format!("{}/events", prefix),
+            sales: format!("{}/sales", prefix),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub broker: String,
+    pub port: u16,
+    pub client_id: String,
+    pub topics: Topics,
+    pub credentials: Option<(String, String)>,
+    /// Use TLS; with `ca` unset the system roots are trusted.
+    pub tls: bool,
+    pub ca: Option<Vec<u8>>,
+    pub keep_alive: Duration,
+}
+
+impl MqttConfig {
+    pub fn new(broker: &str, port: u16, client_id: &str) -> Self {
+        Self {
+            broker: String::from(broker),
+            port,
+            client_id: String::from(client_id),
+            topics: Topics::with_prefix(&format!("vtk/{}", client_id)),
+            credentials: None,
+            tls: false,
+            ca: None,
+            keep_alive: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Publishes terminal telemetry as JSON; the broker connection is kept up by a background thread.
+pub struct Telemetry {
+    client: Client,
+    topics: Topics,
+    closing: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+impl Telemetry {
+    pub fn connect(config: MqttConfig) -> Result<Self, Error> {
+        let mut opts = MqttOptions::new(config.client_id, config.broker, config.port);
+        opts.set_keep_alive(config.keep_alive);
+        if let Some((user, pass)) = config.credentials {
+            opts.set_credentials(user, pass);
+        }
+        if config.tls {
+            opts.set_transport(match config.ca {
+                Some(ca) => Transport::tls_with_config(TlsConfiguration::Simple {ca, alpn: None, client_auth: None}),
+                None => Transport::tls_with_default_config(),
+            });
+        }
+        let (client, mut connection) = Client::new(opts, 64);
+        let closing = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let closing = closing.clone();
+            thread::spawn(move || {
+                for notification in connection.iter() {
+                    match notification {
+                        Ok(rumqttc::Event::Outgoing(Outgoing::Disconnect)) => break,
+                        Ok(_) => (),
+                        Err(_) if closing.load(Ordering::SeqCst) => break,
+                        Err(e) => {
+                            log::warn!("mqtt: {}", e);
+                            thread::sleep(Duration::from_secs(1));
+                        },
+                    }
+                }
+            })
+        };
+        Ok(Self {client, topics: config.topics, closing, worker: Some(worker)})
+    }
+
+    fn publish(&self, topic: &str, retain: bool, payload: Value) -> Result<(), Error> {
+        self.client.publish(topic, QoS::AtLeastOnce, retain, payload.to_string()).map_err(Error::other)
+    }
+
+    /// Retained, so dashboards see the last known state right after subscribing.
+    pub fn publish_status(&self, vtk: &Vtk) -> Result<(), Error> {
+        let c = vtk.counters();
+        self.publish(&self.topics.status, true, json!({
+            "ts": now_ms(),
+            "host": vtk.config().host,
+            "connected": vtk.is_connected(),
+            "degraded": vtk.is_degraded(),
+            "frames_sent": c.frames_sent,
+            "frames_received": c.frames_received,
+            "errors": c.errors,
+        }))
+    }
+
+    pub fn publish_event(&self, event: &Event) -> Result<(), Error> {
+        let payload = match event {
+            Event::Degraded {silent_for} => json!({"ts": now_ms(), "event": "degraded", "silent_for_ms": silent_for.as_millis() as u64}),
+            Event::Recovered => json!({"ts": now_ms(), "event": "recovered"}),
+            Event::Anomaly(a) => json!({"ts": now_ms(), "event": "anomaly", "detail": a.to_string()}),
+            Event::OperatingHours {open} => json!({"ts": now_ms(), "event": "operating_hours", "open": open}),
+            Event::ClockSkew {skew_ms} => json!({"ts": now_ms(), "event": "clock_skew", "skew_ms": skew_ms}),
+            Event::TerminalSilent {interval, silent_for} => json!({"ts": now_ms(), "event": "terminal_silent",
+                "interval_ms": interval.as_millis() as u64, "silent_for_ms": silent_for.as_millis() as u64}),
+            Event::CircuitOpen {failures, cool_down} =>
+                json!({"ts": now_ms(), "event": "circuit_open", "failures": failures, "cool_down_ms": cool_down.as_millis() as u64}),
+            Event::CircuitClosed => json!({"ts": now_ms(), "event": "circuit_closed"}),
+        };
+        self.publish(&self.topics.events, false, payload)
+    }
+
+    /// Publishes `outcome` as `SaleOutcome::as_str`, as the audit log and sale records spell it.
+    pub fn publish_sale(&self, payment: &Payment, outcome: SaleOutcome) -> Result<(), Error> {
+        self.publish(&self.topics.sales, false, json!({
+            "ts": now_ms(),
+            "operation": payment.operation,
+            "amount": payment.amount,
+            "approved": payment.approved,
+            "outcome": outcome.as_str(),
+        }))
+    }
+
+    /// Publishes every event from `events` until the driver is dropped.
+    pub fn forward(&self, events: Receiver<Event>) -> JoinHandle<()> {
+        let telemetry = Self {client: self.client.clone(), topics: self.topics.clone(), closing: self.closing.clone(), worker: None};
+        thread::spawn(move || {
+            for event in events {
+                if let Err(e) = telemetry.publish_event(&event) {
+                    log::warn!("mqtt: {}", e);
+                }
+            }
+        })
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.closing.store(true, Ordering::SeqCst);
+            _ = self.client.disconnect();
+            _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::{collections::HashMap, io, sync::{mpsc::{self, Receiver}, Arc, Mutex}, time::Duration};
+
+use napi::{bindgen_prelude::*, threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode}, Task};
+use napi_derive::napi;
+
+use crate::{error::VtkError, event::Event, record::SaleRecord, session::{Payment, Product}, vtk::{Tlv, TlvKey, Vtk}};
+
+/// `e` for JS, with `VtkError::code` in front as `[code] ` when it has one.
+fn js_error(e: io::Error) -> Error {
+    match VtkError::of(&e).map(VtkError::code) {
+        Some(code) => Error::new(Status::GenericFailure, format!("[{}] {}", code, e)),
+        None => Error::from_reason(e.to_string()),
+    }
+}
+
+fn tags(tlv: &Tlv) -> HashMap<String, Buffer> {
+    tlv.data().iter().map(|(k, v)| (format!("{:?}", k), Buffer::from(v.to_vec()))).collect()
+}
+
+enum Op {
+    Idle,
+    Disable,
+    ShowQr(String),
+    Send(String, Tlv),
+    Exchange(String, Tlv, u32),
+    Receive(u32),
+}
+
+/// Runs one blocking driver call on the libuv pool and resolves with the answer's tags.
+pub struct Exchange {
+    vtk: Arc<Mutex<Vtk>>,
+    op: Option<Op>,
+}
+
+impl Task for Exchange {
+    type Output = Option<Tlv>;
+    type JsValue = Option<HashMap<String, Buffer>>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let mut vtk = self.vtk.lock().map_err(|_| Error::from_reason("driver poisoned"))?;
+        let res = match self.op.take() {
+            Some(Op::Idle) => vtk.idle(None).map(Some),
+            Some(Op::Disable) => vtk.disable().map(Some),
+            Some(Op::ShowQr(data)) => vtk.show_qr(&data).map(Some),
+            Some(Op::Send(msg_name, tlv)) => vtk.send(&msg_name, tlv).map(|_| None),
+            Some(Op::Exchange(msg_name, tlv, timeout_ms)) => vtk.exchange(&msg_name, tlv, Duration::from_millis(timeout_ms as u64)).map(Some),
+            Some(Op::Receive(timeout_ms)) => vtk.receive(Duration::from_millis(timeout_ms as u64)).map(Some),
+            None => Ok(None),
+        };
+        res.map_err(js_error)
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(output.as_ref().map(tags))
+    }
+}
+
+/// An answered payment request; pass it back to `finalizeSale` or `abortSale`.
+#[napi(object)]
+pub struct JsPayment {
+    pub operation: u32,
+    pub amount: i64,
+    pub approved: bool,
+    pub product_id: Option<u32>,
+    pub product_name: Option<String>,
+    pub response: HashMap<String, Buffer>,
+}
+
+/// `n` as a JS integer; amounts and times past `i64::MAX` fail rather than wrap negative.
+fn js_int(n: u64, what: &str) -> Result<i64> {
+    i64::try_from(n).map_err(|_| Error::from_reason(format!("{} {} does not fit an i64", what, n)))
+}
+
+impl TryFrom<&Payment> for JsPayment {
+    type Error = Error;
+
+    fn try_from(p: &Payment) -> Result<Self> {
+        let product = p.product.as_ref();
+        Ok(Self {operation: p.operation, amount: js_int(p.amount, "amount")?, approved: p.approved, product_id: product.and_then(|p| p.id),
+            product_name: product.and_then(|p| p.name.clone()), response: tags(&p.response)})
+    }
+}
+
+/// How a sale ended; `outcome` is `declined`, `finalized` or `aborted`.
+#[napi(object)]
+pub struct JsSaleRecord {
+    pub operation_num: u32,
+    pub amount: i64,
+    pub product_id: Option<u32>,
+    pub product_name: Option<String>,
+    pub outcome: String,
+    pub requested_at_ms: i64,
+    pub settled_at_ms: i64,
+    pub receipt: Option<String>,
+}
+
+impl TryFrom<SaleRecord> for JsSaleRecord {
+    type Error = Error;
+
+    fn try_from(r: SaleRecord) -> Result<Self> {
+        let (product_id, product_name) = r.product.map_or((None, None), |p| (p.id, p.name));
+        Ok(Self {operation_num: r.operation_num, amount: js_int(r.amount, "amount")?, product_id, product_name,
+            outcome: String::from(r.outcome.as_str()), requested_at_ms: js_int(r.requested_at_ms, "requested_at_ms")?,
+            settled_at_ms: js_int(r.settled_at_ms, "settled_at_ms")?, receipt: r.receipt})
+    }
+}
+
+/// `dispense(payment)` for `sell`, returning the amount to charge; throwing aborts the sale.
+type Dispense = ThreadsafeFunction<JsPayment, i64, JsPayment, Status, false>;
+
+enum SaleOp {
+    Payment(u64, Option<Product>, u32),
+    Finalize(u32, u64),
+    Abort(u32),
+    Sell(u64, Option<Product>, u32, Dispense),
+}
+
+/// Runs one blocking sale call on the libuv pool and resolves with a payment or a sale record.
+pub struct Sale {
+    vtk: Arc<Mutex<Vtk>>,
+    /// Approved payments awaiting FIN or ABR, by operation.
+    open: Arc<Mutex<HashMap<u32, Payment>>>,
+    op: Option<SaleOp>,
+}
+
+fn dispense(f: &Dispense, payment: &Payment) -> io::Result<u64> {
+    let payment = JsPayment::try_from(payment).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.reason))?;
+    let (tx, rx) = mpsc::channel();
+    f.call_with_return_value(payment, ThreadsafeFunctionCallMode::Blocking, move |charged, _| {
+        _ = tx.send(charged);
+        Ok(())
+    });
+    let charged = rx.recv().map_err(|_| io::Error::other("dispense was never called"))?.map_err(|e| io::Error::other(e.reason))?;
+    u64::try_from(charged).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("dispense charged {}", charged)))
+}
+
+impl Task for Sale {
+    type Output = Either<Payment, SaleRecord>;
+    type JsValue = Either<JsPayment, JsSaleRecord>;
+
+    fn compute(&mut self) -> Result<Self::Output> {
+        let mut vtk = self.vtk.lock().map_err(|_| Error::from_reason("driver poisoned"))?;
+        let mut open = self.open.lock().map_err(|_| Error::from_reason("driver poisoned"))?;
+        let unknown = |op| Error::from_reason(format!("no open payment for operation {}", op));
+        match self.op.take() {
+            Some(SaleOp::Payment(amount, product, timeout_ms)) => {
+                let payment = vtk.request_payment(amount, product.as_ref(), Duration::from_millis(timeout_ms as u64)).map_err(js_error)?;
+                if payment.approved {
+                    open.insert(payment.operation, payment.clone());
+                }
+                Ok(Either::A(payment))
+            },
+            Some(SaleOp::Finalize(op, amount)) => {
+                let payment = open.get(&op).ok_or_else(|| unknown(op))?;
+                let record = vtk.finalize_sale(payment, amount).map_err(js_error)?;
+                open.remove(&op);
+                Ok(Either::B(record))
+            },
+            Some(SaleOp::Abort(op)) => {
+                let payment = open.get(&op).ok_or_else(|| unknown(op))?;
+                let record = vtk.abort_sale(payment).map_err(js_error)?;
+                open.remove(&op);
+                Ok(Either::B(record))
+            },
+            Some(SaleOp::Sell(amount, product, budget_ms, f)) => {
+                let budget = Duration::from_millis(budget_ms as u64);
+                vtk.sell(amount, product.as_ref(), budget, |payment| dispense(&f, payment)).map(Either::B).map_err(js_error)
+            },
+            None => Err(Error::from_reason("sale task ran twice")),
+        }
+    }
+
+    fn resolve(&mut self, _env: Env, output: Self::Output) -> Result<Self::JsValue> {
+        Ok(match output {
+            Either::A(payment) => Either::A((&payment).try_into()?),
+            Either::B(record) => Either::B(record.try_into()?),
+        })
+    }
+}
+
+fn amount(amount: i64) -> Result<u64> {
+    u64::try_from(amount).map_err(|_| Error::from_reason(format!("negative amount {}", amount)))
+}
+
+fn product(id: Option<u32>, name: Option<String>) -> Option<Product> {
+    (id.is_some() || name.is_some()).then_some(Product {id, name})
+}
+
+fn from_tags(tags: Option<HashMap<String, Buffer>>) -> Result<Tlv> {
+    let mut tlv = Tlv::new();
+    for (name, v) in tags.unwrap_or_default() {
+        let key: TlvKey = name.parse().map_err(|e: std::io::Error| Error::from_reason(e.to_string()))?;
+        tlv.set_bin(key, &v);
+    }
+    Ok(tlv)
+}
+
+#[napi(js_name = "Vtk")]
+pub struct JsVtk {
+    inner: Arc<Mutex<Vtk>>,
+    open: Arc<Mutex<HashMap<u32, Payment>>>,
+    events: Mutex<Receiver<Event>>,
+}
+
+#[napi]
+impl JsVtk {
+    #[napi(constructor)]
+    pub fn new(host: String, port: Option<u16>) -> Result<Self> {
+        let mut vtk = Vtk::new(&host, port.unwrap_or(62801)).map_err(js_error)?;
+        let events = Mutex::new(vtk.subscribe());
+        Ok(Self {inner: Arc::new(Mutex::new(vtk)), open: Arc::default(), events})
+    }
+
+    fn task(&self, op: Op) -> AsyncTask<Exchange> {
+        AsyncTask::new(Exchange {vtk: self.inner.clone(), op: Some(op)})
+    }
+
+    fn sale(&self, op: SaleOp) -> AsyncTask<Sale> {
+        AsyncTask::new(Sale {vtk: self.inner.clone(), open: self.open.clone(), op: Some(op)})
+    }
+
+    #[napi]
+    pub fn request_payment(&self, amount: i64, product_id: Option<u32>, product_name: Option<String>, timeout_ms: Option<u32>) -> Result<AsyncTask<Sale>> {
+        Ok(self.sale(SaleOp::Payment(self::amount(amount)?, product(product_id, product_name), timeout_ms.unwrap_or(60000))))
+    }
+
+    #[napi]
+    pub fn finalize_sale(&self, payment: JsPayment, amount: i64) -> Result<AsyncTask<Sale>> {
+        Ok(self.sale(SaleOp::Finalize(payment.operation, self::amount(amount)?)))
+    }
+
+    #[napi]
+    pub fn abort_sale(&self, payment: JsPayment) -> AsyncTask<Sale> {
+        self.sale(SaleOp::Abort(payment.operation))
+    }
+
+    /// A whole sale: `dispense(payment)` runs for an approved payment and returns the amount to charge, not a
+    /// promise; throwing aborts the sale.
+    #[napi(ts_args_type = "amount: number, dispense: (payment: JsPayment) => number, productId?: number, productName?: string, budgetMs?: number")]
+    pub fn sell(&self, amount: i64, dispense: Dispense, product_id: Option<u32>, product_name: Option<String>, budget_ms: Option<u32>) -> Result<AsyncTask<Sale>> {
+        Ok(self.sale(SaleOp::Sell(self::amount(amount)?, product(product_id, product_name), budget_ms.unwrap_or(90000), dispense)))
+    }
+
+    #[napi]
+    pub fn idle(&self) -> AsyncTask<Exchange> {
+        self.task(Op::Idle)
+    }
+
+    #[napi]
+    pub fn disable(&self) -> AsyncTask<Exchange> {
+        self.task(Op::Disable)
+    }
+
+    #[napi]
+    pub fn show_qr(&self, data: String) -> AsyncTask<Exchange> {
+        self.task(Op::ShowQr(data))
+    }
+
+    /// Tags are passed as Buffers keyed by tag name.
+    #[napi]
+    pub fn send(&self, msg_name: String, tags: Option<HashMap<String, Buffer>>) -> Result<AsyncTask<Exchange>> {
+        Ok(self.task(Op::Send(msg_name, from_tags(tags)?)))
+    }
+
+    /// `send` and the matching answer in one call, with the driver's retries.
+    #[napi]
+    pub fn exchange(&self, msg_name: String, tags: Option<HashMap<String, Buffer>>, timeout_ms: Option<u32>) -> Result<AsyncTask<Exchange>> {
+        Ok(self.task(Op::Exchange(msg_name, from_tags(tags)?, timeout_ms.unwrap_or(2000))))
+    }
+
+    #[napi]
+    pub fn receive(&self, timeout_ms: Option<u32>) -> AsyncTask<Exchange> {
+        self.task(Op::Receive(timeout_ms.unwrap_or(2000)))
+    }
+
+    /// Drains pending driver events as their debug strings.
+    #[napi]
+    pub fn events(&self) -> Vec<String> {
+        match self.events.lock() {
+            Ok(rx) => rx.try_iter().map(|e| format!("{:?}", e)).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Sale outcomes waiting for a downstream system, e.g. an ERP, to take them.
+//!
+//! Delivery is at least once: an outcome stays pending until acknowledged, across restarts, so a consumer that
+//! crashed between sending and `ack` sends it again. The operation number is the idempotency key downstream.
+
+use std::{collections::{BTreeMap, BTreeSet}, fs::{File, OpenOptions}, io::{Error, ErrorKind, Write}, path::{Path, PathBuf}};
+
+use crate::{journal::{complete_lines, hex, unhex}, record::SaleRecord, session::Product};
+
+pub trait Outbox: Send {
+    /// Queues `record`; an operation already queued or acknowledged is ignored.
+    fn push(&mut self, record: &SaleRecord) -> Result<(), Error>;
+    /// Unacknowledged records, oldest operation first.
+    fn pending(&self) -> Result<Vec<SaleRecord>, Error>;
+    fn ack(&mut self, operation: u32) -> Result<(), Error>;
+    /// The highest operation number ever queued, so a restarted driver does not reuse it.
+    fn last_operation(&self) -> Option<u32>;
+
+    /// Hands each pending record to `send`, acknowledging it once `send` returns; stops at the first error.
+    fn deliver(&mut self, send: &mut dyn FnMut(&SaleRecord) -> Result<(), Error>) -> Result<usize, Error> {
+        let mut delivered = 0;
+        for record in self.pending()? {
+            send(&record)?;
+            self.ack(record.operation_num)?;
+            delivered += 1;
+        }
+        Ok(delivered)
+    }
+}
+
+/// Append-only text file, synced after every write. One line per event:
+/// `O <op> <amount> <outcome> <requested_at_ms> <settled_at_ms> <product id|-> <hex product name|-> <hex receipt|->`
+/// or `A <op>`.
+pub struct FileOutbox {
+    path: PathBuf,
+    file: File,
+    pending: BTreeMap<u32, SaleRecord>,
+    acked: BTreeSet<u32>,
+}
+
+fn bad(line: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad outbox line: {}", line))
+}
+
+fn dash(s: Option<String>) -> String {
+    s.unwrap_or_else(|| String::from("-"))
+}
+
+fn parse_record(f: &[&str]) -> Option<SaleRecord> {
+    let [op, amount, outcome, requested, settled, id, name, receipt] = f else {return None;};
+    let id = if *id == "-" {None} else {Some(id.parse().ok()?)};
+    let name = if *name == "-" {None} else {Some(unhex(name)?)};
+    Some(SaleRecord {
+        operation_num: op.parse().ok()?,
+        amount: amount.parse().ok()?,
+        product: (id.is_some() || name.is_some()).then_some(Product {id, name}),
+        outcome: outcome.parse().ok()?,
+        requested_at_ms: requested.parse().ok()?,
+        settled_at_ms: settled.parse().ok()?,
+        receipt: if *receipt == "-" {None} else {Some(unhex(receipt)?)},
+    })
+}
+
+impl FileOutbox {
+    /// Opens or creates the outbox, replaying it to find what is still pending.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).read(true).open(&path)?;
+        let (mut pending, mut acked) = (BTreeMap::new(), BTreeSet::new());
+        for line in complete_lines(&file, &path)?.lines() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.split_first() {
+                Some((&"O", rest)) => {
+                    let record = parse_record(rest).ok_or_else(|| bad(line))?;
+                    pending.insert(record.operation_num, record);
+                },
+                Some((&"A", [op])) => {
+                    let op = op.parse().map_err(|_| bad(line))?;
+                    pending.remove(&op);
+                    acked.insert(op);
+                },
+                Some((&"", [])) => (),
+                _ => return Err(bad(line)),
+            }
+        }
+        Ok(Self {path, file, pending, acked})
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_line(&mut self, line: String) -> Result<(), Error> {
+        writeln!(self.file, "{}", line)?;
+        self.file.sync_data()
+    }
+}
+
+impl Outbox for FileOutbox {
+    fn push(&mut self, r: &SaleRecord) -> Result<(), Error> {
+        if self.pending.contains_key(&r.operation_num) || self.acked.contains(&r.operation_num) {return Ok(());}
+        let product = r.product.as_ref();
+        self.write_line(format!("O {} {} {} {} {} {} {} {}", r.operation_num, r.amount, r.outcome.as_str(), r.requested_at_ms, r.settled_at_ms,
+            dash(product.and_then(|p| p.id).map(|id| id.to_string())), dash(product.and_then(|p| p.name.as_deref()).map(hex)),
+            dash(r.receipt.as_deref().map(hex))))?;
+        self.pending.insert(r.operation_num, r.clone());
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<SaleRecord>, Error> {
+        Ok(self.pending.values().cloned().collect())
+    }
+
+    fn ack(&mut self, operation: u32) -> Result<(), Error> {
+        if self.pending.remove(&operation).is_some() {
+            self.write_line(format!("A {}", operation))?;
+            self.acked.insert(operation);
+        }
+        Ok(())
+    }
+
+    fn last_operation(&self) -> Option<u32> {
+        self.pending.keys().chain(&self.acked).max().copied()
+    }
+}
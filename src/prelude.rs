@@ -0,0 +1,3 @@
+//! What most integrations need, for `use vtk::prelude::*`; a VTK message is a `Frame`.
+
+pub use crate::{error::VtkError, frame::Frame, record::SaleOutcome, vtk::{Tlv, TlvKey, Vtk, VtkBuilder}};
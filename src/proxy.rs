@@ -0,0 +1,181 @@
+use std::{io::{Error, ErrorKind, Read, Write}, net::{IpAddr, TcpStream, ToSocketAddrs}};
+
+use crate::{config::Config, connection::connect_one};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Socks5,
+    Http,
+}
+
+/// `socks5://[user:pass@]host[:port]` or `http://[user:pass@]host[:port]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ProxyUrl {
+    kind: Kind,
+    host: String,
+    port: u16,
+    auth: Option<(String, String)>,
+}
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+impl ProxyUrl {
+    fn parse(url: &str) -> Result<Self, Error> {
+        let (kind, rest, default_port) = if let Some(rest) = url.strip_prefix("socks5://").or_else(|| url.strip_prefix("socks5h://")) {
+            (Kind::Socks5, rest, 1080)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (Kind::Http, rest, 8080)
+        } else {
+            return Err(invalid(format!("unsupported proxy {}, expected socks5:// or http://", url)));
+        };
+        let rest = rest.trim_end_matches('/');
+        let (auth, hostport) = match rest.rsplit_once('@') {
+            Some((auth, hostport)) => {
+                let (user, pass) = auth.split_once(':').unwrap_or((auth, ""));
+                (Some((String::from(user), String::from(pass))), hostport)
+            },
+            None => (None, rest),
+        };
+        let (host, port) = match hostport.rsplit_once(':') {
+            Some((host, port)) if !host.ends_with(':') => (host, port.parse().map_err(|_| invalid(format!("bad proxy port in {}", url)))?),
+            _ => (hostport, default_port),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err(invalid(format!("no proxy host in {}", url)));
+        }
+        Ok(Self {kind, host: String::from(host), port, auth})
+    }
+}
+
+fn refused(msg: String) -> Error {
+    Error::new(ErrorKind::ConnectionRefused, msg)
+}
+
+/// Connects to the proxy with the usual socket options, then asks it for a tunnel to the terminal.
+pub(crate) fn connect(config: &Config, url: &str) -> Result<TcpStream, Error> {
+    let proxy = ProxyUrl::parse(url)?;
+    let mut last = Error::new(ErrorKind::NotFound, format!("proxy {} resolves to no address", proxy.host));
+    for addr in (proxy.host.as_str(), proxy.port).to_socket_addrs()? {
+        match connect_one(config, addr) {
+            Ok(tcp) => {
+                tcp.set_read_timeout(Some(config.connect_timeout))?;
+                tcp.set_write_timeout(Some(config.connect_timeout))?;
+                match proxy.kind {
+                    Kind::Socks5 => socks5(&tcp, &proxy, &config.host, config.port)?,
+                    Kind::Http => http_connect(&tcp, &proxy, &config.host, config.port)?,
+                }
+                return Ok(tcp);
+            },
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// RFC 1928 CONNECT, with RFC 1929 username/password when credentials are given.
+fn socks5(mut tcp: &TcpStream, proxy: &ProxyUrl, host: &str, port: u16) -> Result<(), Error> {
+    let methods: &[u8] = if proxy.auth.is_some() {&[0x00, 0x02]} else {&[0x00]};
+    tcp.write_all(&[&[0x05, methods.len() as u8], methods].concat())?;
+    let mut reply = [0u8; 2];
+    tcp.read_exact(&mut reply)?;
+    match (reply, &proxy.auth) {
+        ([0x05, 0x00], _) => (),
+        ([0x05, 0x02], Some((user, pass))) => {
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(invalid(String::from("socks5 credentials longer than 255 bytes")));
+            }
+            let mut req = vec![0x01, user.len() as u8];
+            req.extend_from_slice(user.as_bytes());
+            req.push(pass.len() as u8);
+            req.extend_from_slice(pass.as_bytes());
+            tcp.write_all(&req)?;
+            tcp.read_exact(&mut reply)?;
+            if reply[1] != 0x00 {
+                return Err(refused(String::from("socks5 proxy rejected the credentials")));
+            }
+        },
+        _ => return Err(refused(format!("socks5 proxy offers no usable auth method ({:02X}{:02X})", reply[0], reply[1]))),
+    }
+    let mut req = vec![0x05, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(0x01);
+            req.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            req.push(0x04);
+            req.extend_from_slice(&ip.octets());
+        },
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(invalid(format!("host name {} too long for socks5", host)));
+            }
+            req.extend_from_slice(&[0x03, host.len() as u8]);
+            req.extend_from_slice(host.as_bytes());
+        },
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    tcp.write_all(&req)?;
+    let mut head = [0u8; 4];
+    tcp.read_exact(&mut head)?;
+    if head[1] != 0x00 {
+        return Err(refused(format!("socks5 proxy failed to connect to {}:{} (reply {})", host, port, head[1])));
+    }
+    // The bound address is of no use to us, but it has to be drained.
+    let addr_len = match head[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => {
+            let mut len = [0u8; 1];
+            tcp.read_exact(&mut len)?;
+            len[0] as usize
+        },
+        atyp => return Err(Error::new(ErrorKind::InvalidData, format!("socks5 reply with address type {}", atyp))),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    tcp.read_exact(&mut bound)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            out.push(if i <= chunk.len() {ALPHABET[((n >> (18 - 6 * i)) & 0x3F) as usize] as char} else {'='});
+        }
+    }
+    out
+}
+
+fn http_connect(mut tcp: &TcpStream, proxy: &ProxyUrl, host: &str, port: u16) -> Result<(), Error> {
+    let target = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let mut req = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", target, target);
+    if let Some((user, pass)) = &proxy.auth {
+        req.push_str(&format!("Proxy-Authorization: Basic {}\r\n", base64(format!("{}:{}", user, pass).as_bytes())));
+    }
+    req.push_str("\r\n");
+    tcp.write_all(req.as_bytes())?;
+    // Read byte by byte so nothing the terminal sends after the headers is swallowed.
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= 8192 {
+            return Err(Error::new(ErrorKind::InvalidData, "http proxy response headers too long"));
+        }
+        tcp.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    let status = String::from_utf8_lossy(&head);
+    let status = status.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(refused(format!("http proxy refused CONNECT {}: {}", target, status))),
+    }
+}
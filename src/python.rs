@@ -0,0 +1,200 @@
+use std::{collections::HashMap, io::Error, sync::mpsc::Receiver, time::Duration};
+
+use pyo3::{create_exception, exceptions::{PyOSError, PyValueError}, prelude::*};
+
+use crate::{error::VtkError, event::Event, record::SaleRecord, session::{Payment, Product}, vtk::{Tlv, TlvKey, Vtk}};
+
+create_exception!(vtk, PyVtkError, PyOSError, "A driver error; `code` is `VtkError::code`, `None` for plain I/O errors.");
+
+/// `e` raised as `vtk.VtkError`, an `OSError`, with its code.
+fn raise(e: Error) -> PyErr {
+    let code = VtkError::of(&e).map(VtkError::code);
+    let err = PyVtkError::new_err(e.to_string());
+    let set = Python::attach(|py| err.value(py).setattr("code", code));
+    match set {
+        Ok(()) => err,
+        Err(e) => e,
+    }
+}
+
+fn tags(tlv: &Tlv) -> HashMap<String, Vec<u8>> {
+    tlv.data().iter().map(|(k, v)| (format!("{:?}", k), v.to_vec())).collect()
+}
+
+/// Tags come from Python as `bytes` values keyed by tag name.
+fn from_tags(tags: HashMap<String, Vec<u8>>) -> PyResult<Tlv> {
+    let mut tlv = Tlv::new();
+    for (name, v) in tags {
+        let key: TlvKey = name.parse().map_err(|e: std::io::Error| PyValueError::new_err(e.to_string()))?;
+        tlv.set_bin(key, &v);
+    }
+    Ok(tlv)
+}
+
+fn product(id: Option<u32>, name: Option<String>) -> Option<Product> {
+    (id.is_some() || name.is_some()).then_some(Product {id, name})
+}
+
+/// An answered payment request, for `finalize_sale` or `abort_sale`.
+#[pyclass(name = "Payment", frozen)]
+struct PyPayment {
+    inner: Payment,
+}
+
+#[pymethods]
+impl PyPayment {
+    #[getter]
+    fn operation(&self) -> u32 {
+        self.inner.operation
+    }
+
+    #[getter]
+    fn amount(&self) -> u64 {
+        self.inner.amount
+    }
+
+    #[getter]
+    fn approved(&self) -> bool {
+        self.inner.approved
+    }
+
+    #[getter]
+    fn product_id(&self) -> Option<u32> {
+        self.inner.product.as_ref().and_then(|p| p.id)
+    }
+
+    #[getter]
+    fn product_name(&self) -> Option<String> {
+        self.inner.product.as_ref().and_then(|p| p.name.clone())
+    }
+
+    #[getter]
+    fn response(&self) -> HashMap<String, Vec<u8>> {
+        tags(&self.inner.response)
+    }
+}
+
+/// How a sale ended, as `SaleRecord`; `outcome` is `declined`, `finalized` or `aborted`.
+#[pyclass(name = "SaleRecord", frozen, get_all)]
+struct PySaleRecord {
+    operation_num: u32,
+    amount: u64,
+    product_id: Option<u32>,
+    product_name: Option<String>,
+    outcome: &'static str,
+    requested_at_ms: u64,
+    settled_at_ms: u64,
+    receipt: Option<String>,
+}
+
+impl From<SaleRecord> for PySaleRecord {
+    fn from(r: SaleRecord) -> Self {
+        let (product_id, product_name) = r.product.map_or((None, None), |p| (p.id, p.name));
+        Self {operation_num: r.operation_num, amount: r.amount, product_id, product_name, outcome: r.outcome.as_str(),
+            requested_at_ms: r.requested_at_ms, settled_at_ms: r.settled_at_ms, receipt: r.receipt}
+    }
+}
+
+/// Blocking calls release the GIL while they wait on the terminal.
+#[pyclass(name = "Vtk", unsendable)]
+struct PyVtk {
+    inner: Vtk,
+    events: Receiver<Event>,
+}
+
+#[pymethods]
+impl PyVtk {
+    #[new]
+    #[pyo3(signature = (host, port = 62801))]
+    fn new(host: &str, port: u16) -> PyResult<Self> {
+        let mut inner = Vtk::new(host, port).map_err(raise)?;
+        let events = inner.subscribe();
+        Ok(Self {inner, events})
+    }
+
+    fn connect(&mut self, py: Python<'_>) -> PyResult<()> {
+        py.detach(|| self.inner.connect()).map_err(raise)
+    }
+
+    fn disconnect(&mut self) {
+        self.inner.disconnect();
+    }
+
+    fn idle(&mut self, py: Python<'_>) -> PyResult<HashMap<String, Vec<u8>>> {
+        Ok(tags(&py.detach(|| self.inner.idle(None)).map_err(raise)?))
+    }
+
+    fn disable(&mut self, py: Python<'_>) -> PyResult<HashMap<String, Vec<u8>>> {
+        Ok(tags(&py.detach(|| self.inner.disable()).map_err(raise)?))
+    }
+
+    fn show_qr(&mut self, py: Python<'_>, data: &str) -> PyResult<HashMap<String, Vec<u8>>> {
+        Ok(tags(&py.detach(|| self.inner.show_qr(data)).map_err(raise)?))
+    }
+
+    #[pyo3(signature = (msg_name, tags = HashMap::new()))]
+    fn send(&mut self, py: Python<'_>, msg_name: &str, tags: HashMap<String, Vec<u8>>) -> PyResult<()> {
+        let tlv = from_tags(tags)?;
+        py.detach(|| self.inner.send(msg_name, tlv)).map_err(raise)
+    }
+
+    /// `send` and the matching answer in one call, with the driver's retries.
+    #[pyo3(signature = (msg_name, tags = HashMap::new(), timeout_ms = 2000))]
+    fn exchange(&mut self, py: Python<'_>, msg_name: &str, tags: HashMap<String, Vec<u8>>, timeout_ms: u64) -> PyResult<HashMap<String, Vec<u8>>> {
+        let tlv = from_tags(tags)?;
+        let answer = py.detach(|| self.inner.exchange(msg_name, tlv, Duration::from_millis(timeout_ms))).map_err(raise)?;
+        Ok(self::tags(&answer))
+    }
+
+    #[pyo3(signature = (timeout_ms = 2000))]
+    fn receive(&mut self, py: Python<'_>, timeout_ms: u64) -> PyResult<HashMap<String, Vec<u8>>> {
+        Ok(tags(&py.detach(|| self.inner.receive(Duration::from_millis(timeout_ms))).map_err(raise)?))
+    }
+
+    #[pyo3(signature = (amount, product_id = None, product_name = None, timeout_ms = 60000))]
+    fn request_payment(&mut self, py: Python<'_>, amount: u64, product_id: Option<u32>, product_name: Option<String>, timeout_ms: u64)
+        -> PyResult<PyPayment> {
+        let product = product(product_id, product_name);
+        let inner = py.detach(|| self.inner.request_payment(amount, product.as_ref(), Duration::from_millis(timeout_ms))).map_err(raise)?;
+        Ok(PyPayment {inner})
+    }
+
+    fn finalize_sale(&mut self, py: Python<'_>, payment: &PyPayment, amount: u64) -> PyResult<PySaleRecord> {
+        Ok(py.detach(|| self.inner.finalize_sale(&payment.inner, amount)).map_err(raise)?.into())
+    }
+
+    fn abort_sale(&mut self, py: Python<'_>, payment: &PyPayment) -> PyResult<PySaleRecord> {
+        Ok(py.detach(|| self.inner.abort_sale(&payment.inner)).map_err(raise)?.into())
+    }
+
+    /// A whole sale: `dispense(payment)` runs for an approved payment and returns the amount to charge; raising
+    /// aborts the sale. Only `dispense` holds the GIL.
+    #[pyo3(signature = (amount, dispense, product_id = None, product_name = None, budget_ms = 90000))]
+    fn sell(&mut self, py: Python<'_>, amount: u64, dispense: Py<PyAny>, product_id: Option<u32>, product_name: Option<String>, budget_ms: u64)
+        -> PyResult<PySaleRecord> {
+        let product = product(product_id, product_name);
+        let record = py.detach(|| self.inner.sell(amount, product.as_ref(), Duration::from_millis(budget_ms), |payment| {
+            Python::attach(|py| dispense.call1(py, (PyPayment {inner: payment.clone()},)).and_then(|charged| charged.extract::<u64>(py)))
+                .map_err(|e| Error::other(e.to_string()))
+        }));
+        Ok(record.map_err(raise)?.into())
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    /// Drains pending driver events as their debug strings.
+    fn events(&mut self) -> Vec<String> {
+        self.events.try_iter().map(|e| format!("{:?}", e)).collect()
+    }
+}
+
+#[pymodule]
+fn vtk(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyVtk>()?;
+    m.add_class::<PyPayment>()?;
+    m.add_class::<PySaleRecord>()?;
+    m.add("VtkError", m.py().get_type::<PyVtkError>())?;
+    Ok(())
+}
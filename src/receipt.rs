@@ -0,0 +1,73 @@
+use std::io::{Error, Write};
+
+use crate::record::SaleRecord;
+
+/// Receives the bank slip of every sale settled with `finalize_sale`.
+pub trait ReceiptSink: Send {
+    /// `receipt` is the BankingReceipt value as sent by the terminal, in its own code page.
+    fn print(&mut self, record: &SaleRecord, receipt: &[u8]) -> Result<(), Error>;
+}
+
+const ESC: u8 = 0x1B;
+const GS: u8 = 0x1D;
+
+/// Formats receipts for ESC/POS thermal printers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscPos {
+    /// Characters per line; longer lines are wrapped.
+    pub width: usize,
+    /// Printer code table (ESC t n) matching the terminal's receipt encoding.
+    pub code_page: Option<u8>,
+    pub cut: bool,
+}
+
+impl Default for EscPos {
+    fn default() -> Self {
+        Self {width: 32, code_page: None, cut: true}
+    }
+}
+
+impl EscPos {
+    pub fn format(&self, receipt: &[u8]) -> Vec<u8> {
+        let mut out = vec![ESC, b'@'];
+        if let Some(n) = self.code_page {
+            out.extend([ESC, b't', n]);
+        }
+        let receipt = receipt.strip_suffix(b"\n").unwrap_or(receipt);
+        for line in receipt.split(|b| *b == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                out.push(b'\n');
+            }
+            for chunk in line.chunks(self.width.max(1)) {
+                out.extend_from_slice(chunk);
+                out.push(b'\n');
+            }
+        }
+        // Feed past the tear bar before cutting.
+        out.extend([ESC, b'd', 4]);
+        if self.cut {
+            out.extend([GS, b'V', 1]);
+        }
+        out
+    }
+}
+
+/// Writes formatted receipts to a printer device or socket, e.g. /dev/usb/lp0 or port 9100.
+pub struct EscPosPrinter<W: Write + Send> {
+    pub format: EscPos,
+    out: W,
+}
+
+impl<W: Write + Send> EscPosPrinter<W> {
+    pub fn new(out: W, format: EscPos) -> Self {
+        Self {format, out}
+    }
+}
+
+impl<W: Write + Send> ReceiptSink for EscPosPrinter<W> {
+    fn print(&mut self, _record: &SaleRecord, receipt: &[u8]) -> Result<(), Error> {
+        self.out.write_all(&self.format.format(receipt))?;
+        self.out.flush()
+    }
+}
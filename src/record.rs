@@ -0,0 +1,118 @@
+use std::{io::{Error, ErrorKind, Write}, str::FromStr, time::{SystemTime, UNIX_EPOCH}};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{charset::Charset, session::{Payment, Product}, vtk::{Tlv, TlvKey}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "snake_case"))]
+pub enum SaleOutcome {
+    /// The terminal did not approve the payment request.
+    Declined,
+    Finalized,
+    Aborted,
+}
+
+impl SaleOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SaleOutcome::Declined => "declined",
+            SaleOutcome::Finalized => "finalized",
+            SaleOutcome::Aborted => "aborted",
+        }
+    }
+}
+
+impl FromStr for SaleOutcome {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "declined" => Ok(SaleOutcome::Declined),
+            "finalized" => Ok(SaleOutcome::Finalized),
+            "aborted" => Ok(SaleOutcome::Aborted),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown sale outcome {}", s))),
+        }
+    }
+}
+
+/// One settled sale, for reconciliation against acquirer statements. Times are Unix milliseconds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct SaleRecord {
+    pub operation_num: u32,
+    /// The finalized amount, or the requested one for declined and aborted sales.
+    pub amount: u64,
+    pub product: Option<Product>,
+    pub outcome: SaleOutcome,
+    pub requested_at_ms: u64,
+    pub settled_at_ms: u64,
+    /// BankingReceipt text from the settlement answer, or from the payment answer when absent there.
+    pub receipt: Option<String>,
+}
+
+pub(crate) fn unix_ms(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default()
+}
+
+fn receipt(tlv: &Tlv, charset: Charset) -> Option<String> {
+    tlv.get_text(TlvKey::BankingReceipt, charset).map(|s| s.into_owned())
+}
+
+impl Payment {
+    /// Builds the record of a sale that was over at `settled_at`; `settlement` is the FIN or ABR answer, if any.
+    pub fn record(&self, outcome: SaleOutcome, amount: u64, settlement: Option<&Tlv>, settled_at: SystemTime) -> SaleRecord {
+        SaleRecord {
+            operation_num: self.operation,
+            amount,
+            product: self.product.clone(),
+            outcome,
+            requested_at_ms: unix_ms(self.requested_at),
+            settled_at_ms: unix_ms(settled_at),
+            receipt: settlement.and_then(|t| receipt(t, self.charset)).or_else(|| receipt(&self.response, self.charset)),
+        }
+    }
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        String::from(s)
+    }
+}
+
+pub const CSV_HEADER: &str = "operation_num,amount,product_id,product_name,outcome,requested_at_ms,settled_at_ms,receipt";
+
+impl SaleRecord {
+    /// One CSV line (RFC 4180 quoting) matching `CSV_HEADER`, without the line break.
+    pub fn to_csv(&self) -> String {
+        let product = self.product.as_ref();
+        [
+            self.operation_num.to_string(),
+            self.amount.to_string(),
+            product.and_then(|p| p.id).map(|id| id.to_string()).unwrap_or_default(),
+            csv_field(product.and_then(|p| p.name.as_deref()).unwrap_or_default()),
+            String::from(self.outcome.as_str()),
+            self.requested_at_ms.to_string(),
+            self.settled_at_ms.to_string(),
+            csv_field(self.receipt.as_deref().unwrap_or_default()),
+        ].join(",")
+    }
+}
+
+/// Writes a header line followed by one line per record.
+pub fn write_csv<W: Write>(mut w: W, records: &[SaleRecord]) -> Result<(), Error> {
+    writeln!(w, "{}", CSV_HEADER)?;
+    for r in records {
+        writeln!(w, "{}", r.to_csv())?;
+    }
+    Ok(())
+}
+
+/// Writes the records as a JSON array.
+#[cfg(feature = "json")]
+pub fn write_json<W: Write>(w: W, records: &[SaleRecord]) -> Result<(), Error> {
+    serde_json::to_writer_pretty(w, records).map_err(Error::other)
+}
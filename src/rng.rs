@@ -0,0 +1,41 @@
+//! Where the driver gets random numbers: only for `RetryPolicy::jitter`, so a `SeededRng` with a fixed seed makes
+//! retries reproducible in tests.
+//!
+//! Operation numbers are not random: they count up from 1, or from past what the journal and outbox have seen, and
+//! wrap to 1 after `u32::MAX`, so the terminal and the journal can match them.
+
+use std::{process, time::{SystemTime, UNIX_EPOCH}};
+
+pub trait Rng: Send {
+    fn next_u32(&mut self) -> u32;
+}
+
+/// SplitMix64: fast and evenly spread, and not for anything secret.
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self {state: seed}
+    }
+}
+
+/// Seeded from the time and the process, so machines restarted together do not retry in step.
+impl Default for SeededRng {
+    fn default() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+        Self::new(nanos ^ u64::from(process::id()).rotate_left(32))
+    }
+}
+
+impl Rng for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        ((z ^ (z >> 31)) >> 32) as u32
+    }
+}
@@ -0,0 +1,201 @@
+use std::{fs::{self, File, OpenOptions}, io::{Error, ErrorKind, Write}, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::clock::civil_from_days;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+/// When a `RotatingFile` starts over; both limits may be set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rotate {
+    /// Rotates once the file reaches this size, so it may overrun by one record.
+    pub max_bytes: Option<u64>,
+    /// Rotates when the UTC day changes.
+    pub daily: bool,
+    /// Rotated files to keep; the oldest go first.
+    pub keep: Option<usize>,
+    /// Compresses each rotated file to `<name>.gz` (needs the `gzip` feature).
+    pub gzip: bool,
+}
+
+/// An append-only file that moves itself aside to `<path>.<YYYY-MM-DD>.<n>`, or `<n>.gz`, when `Rotate` says so.
+///
+/// Rotation only happens on the first write after a `flush`, so records flushed one at a time are never split.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotate: Rotate,
+    file: File,
+    size: u64,
+    /// UTC day of the first write to the current file.
+    day: u64,
+    at_boundary: bool,
+}
+
+fn day_of(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map(|d| d.as_secs() / DAY_SECS).unwrap_or_default()
+}
+
+fn today() -> u64 {
+    day_of(SystemTime::now())
+}
+
+/// `YYYY-MM-DD` for days since the Unix epoch.
+fn date(day: u64) -> String {
+    let (y, m, d) = civil_from_days(day as i64);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+fn open_append(path: &Path) -> Result<File, Error> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(feature = "gzip")]
+fn compress(path: &Path) -> Result<PathBuf, Error> {
+    use flate2::{write::GzEncoder, Compression};
+
+    let gz = PathBuf::from(format!("{}.gz", path.display()));
+    let mut out = GzEncoder::new(File::create(&gz)?, Compression::default());
+    std::io::copy(&mut File::open(path)?, &mut out)?;
+    out.finish()?.sync_all()?;
+    fs::remove_file(path)?;
+    Ok(gz)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress(_path: &Path) -> Result<PathBuf, Error> {
+    Err(Error::new(ErrorKind::Unsupported, "gzip rotation needs the gzip feature"))
+}
+
+/// Date, number and path of a rotated file, in that order for sorting.
+type Part = (String, u32, PathBuf);
+
+/// Date and number of a file rotated from `name`, for ordering.
+fn rotated_as(name: &str, file: &str) -> Option<(String, u32)> {
+    let rest = file.strip_prefix(name)?.strip_prefix('.')?;
+    let (date, n) = rest.strip_suffix(".gz").unwrap_or(rest).split_once('.')?;
+    if date.len() != 10 {return None;}
+    Some((String::from(date), n.parse().ok()?))
+}
+
+impl RotatingFile {
+    /// Appends to the file at `path`, creating it if needed; an existing file counts towards `max_bytes` and
+    /// is dated by its last change.
+    pub fn open<P: AsRef<Path>>(path: P, rotate: Rotate) -> Result<Self, Error> {
+        if rotate.gzip && cfg!(not(feature = "gzip")) {
+            return Err(Error::new(ErrorKind::Unsupported, "gzip rotation needs the gzip feature"));
+        }
+        let path = path.as_ref().to_path_buf();
+        let file = open_append(&path)?;
+        let meta = file.metadata()?;
+        let day = meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .filter(|_| meta.len() > 0)
+            .map_or_else(today, |d| d.as_secs() / DAY_SECS);
+        Ok(Self {path, rotate, file, size: meta.len(), day, at_boundary: true})
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Nothing was written to the current file yet, e.g. right after a rotation.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Makes everything written so far durable.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data()
+    }
+
+    fn due(&self, today: u64) -> bool {
+        self.size > 0 && (self.rotate.max_bytes.is_some_and(|max| self.size >= max) || (self.rotate.daily && today != self.day))
+    }
+
+    /// Moves the current file aside, compressed if asked to, starts an empty one and drops rotated files past
+    /// `Rotate::keep`. Failing to compress or drop only logs, so new records still go somewhere.
+    pub fn rotate(&mut self) -> Result<PathBuf, Error> {
+        self.rotate_on(today())
+    }
+
+    fn rotate_on(&mut self, today: u64) -> Result<PathBuf, Error> {
+        self.file.sync_data()?;
+        let day = date(self.day);
+        let base = format!("{}.{}", self.path.display(), day);
+        // After the latest of the day rather than in the first gap, so pruning never makes a new part look old.
+        let last = self.rotations()?.into_iter().filter(|(d, _, _)| *d == day).map(|(_, n, _)| n).max().unwrap_or(0);
+        let rotated = last.checked_add(1).map(|n| PathBuf::from(format!("{}.{}", base, n)))
+            .ok_or_else(|| Error::new(ErrorKind::AlreadyExists, format!("no free name for {}", base)))?;
+        fs::rename(&self.path, &rotated)?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.day = today;
+        let rotated = match self.rotate.gzip {
+            true => compress(&rotated).unwrap_or_else(|e| {
+                log::warn!("cannot compress {}: {}", rotated.display(), e);
+                rotated
+            }),
+            false => rotated,
+        };
+        if let Some(Err(e)) = self.rotate.keep.map(|keep| self.prune(keep)) {
+            log::warn!("cannot drop old rotations of {}: {}", self.path.display(), e);
+        }
+        Ok(rotated)
+    }
+
+    /// Files rotated from this one, oldest first.
+    fn rotations(&self) -> Result<Vec<Part>, Error> {
+        let Some(name) = self.path.file_name().and_then(|n| n.to_str()) else {return Ok(Vec::new());};
+        let dir = self.path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut rotated: Vec<Part> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter_map(|p| {
+                let (date, n) = rotated_as(name, p.file_name()?.to_str()?)?;
+                Some((date, n, p))
+            })
+            .collect();
+        rotated.sort();
+        Ok(rotated)
+    }
+
+    fn prune(&self, keep: usize) -> Result<(), Error> {
+        let rotated = self.rotations()?;
+        for (_, _, path) in &rotated[..rotated.len().saturating_sub(keep)] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Rotates now if `Rotate` says the file is due, as the next write after a `flush` would.
+    pub fn rotate_if_due(&mut self) -> Result<(), Error> {
+        self.rotate_if_due_at(SystemTime::now())
+    }
+
+    /// As `rotate_if_due`, taking the day from `at` rather than the OS clock, e.g. the time of the record about
+    /// to be written.
+    pub fn rotate_if_due_at(&mut self, at: SystemTime) -> Result<(), Error> {
+        if !self.at_boundary {return Ok(());}
+        let today = day_of(at);
+        if self.due(today) {
+            self.rotate_on(today)?;
+        }
+        if self.size == 0 {
+            self.day = today;
+        }
+        self.at_boundary = false;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.rotate_if_due()?;
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.at_boundary = true;
+        self.file.flush()
+    }
+}
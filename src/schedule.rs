@@ -0,0 +1,130 @@
+//! Weekly operating hours that pause and resume card acceptance on their own.
+
+use std::{io::{Error, ErrorKind}, str::FromStr, time::SystemTime};
+
+use jiff::{tz::TimeZone, Timestamp};
+
+use crate::{event::Event, vtk::Vtk};
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const DAY_MINUTES: u16 = 24 * 60;
+
+fn invalid(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+fn day(s: &str) -> Result<usize, Error> {
+    DAYS.iter().position(|d| d.eq_ignore_ascii_case(s)).ok_or_else(|| invalid(format!("unknown day {}", s)))
+}
+
+/// `HH:MM`, with `24:00` for the end of the day.
+fn minutes(s: &str) -> Result<u16, Error> {
+    let bad = || invalid(format!("bad time {}, expected HH:MM", s));
+    let (h, m) = s.split_once(':').ok_or_else(bad)?;
+    let (h, m): (u16, u16) = (h.parse().map_err(|_| bad())?, m.parse().map_err(|_| bad())?);
+    if h > 24 || m >= 60 || h * 60 + m > DAY_MINUTES {
+        return Err(bad());
+    }
+    Ok(h * 60 + m)
+}
+
+/// Open hours per weekday in a time zone, e.g. `Mon-Fri 08:00-20:00, Sat 10:00-16:00` in `Europe/Moscow`.
+///
+/// A window closing before it opens runs past midnight into the next day.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    tz: TimeZone,
+    /// Minutes since midnight, open inclusive and close exclusive, Monday first.
+    windows: [Vec<(u16, u16)>; 7],
+}
+
+impl Schedule {
+    /// Closed all week; see `parse`.
+    pub fn new(tz: &str) -> Result<Self, Error> {
+        let tz = TimeZone::get(tz).map_err(|e| invalid(format!("time zone {}: {}", tz, e)))?;
+        Ok(Self {tz, windows: Default::default()})
+    }
+
+    /// Parses `spec` as comma-separated `<day>[-<day>] HH:MM-HH:MM` windows in `tz`.
+    pub fn parse(spec: &str, tz: &str) -> Result<Self, Error> {
+        let mut schedule = Self::new(tz)?;
+        for window in spec.split([',', ';']).map(str::trim).filter(|w| !w.is_empty()) {
+            let (days, hours) = window.split_once(' ').ok_or_else(|| invalid(format!("bad window {}", window)))?;
+            let (first, last) = match days.split_once('-') {
+                Some((first, last)) => (day(first)?, day(last)?),
+                None => (day(days)?, day(days)?),
+            };
+            let (open, close) = hours.trim().split_once('-').ok_or_else(|| invalid(format!("bad hours {}", hours)))?;
+            let (open, close) = (minutes(open)?, minutes(close)?);
+            // Ranges may wrap around the week, e.g. Fri-Mon.
+            let count = (last + 7 - first) % 7 + 1;
+            for d in (first..first + count).map(|d| d % 7) {
+                schedule.add(d, open, close);
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// Opens day `day` (0 for Monday) from `open` to `close`, both in minutes since midnight.
+    fn add(&mut self, day: usize, open: u16, close: u16) {
+        if close > open {
+            self.windows[day].push((open, close));
+        } else {
+            self.windows[day].push((open, DAY_MINUTES));
+            self.windows[(day + 1) % 7].push((0, close));
+        }
+    }
+
+    pub fn is_open_at(&self, t: SystemTime) -> bool {
+        let Ok(ts) = Timestamp::try_from(t) else {return false;};
+        let local = ts.to_zoned(self.tz.clone());
+        let day = local.weekday().to_monday_zero_offset() as usize;
+        let now = local.hour() as u16 * 60 + local.minute() as u16;
+        self.windows[day].iter().any(|(open, close)| (*open..*close).contains(&now))
+    }
+}
+
+/// `<tz> <windows>`, e.g. `UTC Mon-Sun 00:00-24:00`.
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (tz, spec) = s.trim().split_once(' ').ok_or_else(|| invalid(format!("bad schedule {}, expected <tz> <windows>", s)))?;
+        Self::parse(spec, tz)
+    }
+}
+
+/// Pauses the terminal outside its schedule and resumes it inside, emitting `Event::OperatingHours` on each change.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    schedule: Schedule,
+    open: Option<bool>,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule) -> Self {
+        Self {schedule, open: None}
+    }
+
+    /// Applies the schedule; call it from the application loop, about once a minute.
+    /// Returns the new state on a transition, and retries on the next call when the terminal did not answer.
+    pub fn tick(&mut self, vtk: &mut Vtk) -> Result<Option<bool>, Error> {
+        let now = vtk.clock.system_now();
+        self.tick_at(vtk, now)
+    }
+
+    pub fn tick_at(&mut self, vtk: &mut Vtk, now: SystemTime) -> Result<Option<bool>, Error> {
+        let open = self.schedule.is_open_at(now);
+        if self.open == Some(open) {
+            return Ok(None);
+        }
+        if open {
+            vtk.resume()?;
+        } else {
+            vtk.pause()?;
+        }
+        self.open = Some(open);
+        vtk.emit(Event::OperatingHours {open});
+        Ok(Some(open))
+    }
+}
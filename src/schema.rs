@@ -0,0 +1,95 @@
+//! Which tags each message requires and allows, shared by the driver, the CLI and the mock terminal.
+//!
+//! MsgName is left out of the lists: every frame carries it and `Frame::encode` sets it.
+
+use std::fmt;
+
+use crate::vtk::{Tlv, TlvKey};
+
+use TlvKey::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToTerminal,
+    FromTerminal,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Schema {
+    pub msg_name: &'static str,
+    pub direction: Direction,
+    pub required: &'static [TlvKey],
+    pub optional: &'static [TlvKey],
+}
+
+/// Events ride on IDL and DIS answers as EventName and EventNum.
+const TERMINAL_STATUS: &[TlvKey] = &[KeepaliveIntervalInSecs, EventName, EventNum, LocalTime, SysInfo, OperationNum, PosManagementData];
+
+pub static REGISTRY: &[Schema] = &[
+    Schema {msg_name: "IDL", direction: Direction::ToTerminal, required: &[],
+        optional: &[OperationNum, KeepaliveIntervalInSecs, ProductId, ProductName, QrCodeData, DisplayTimeInMs, AmountInMinorCurrencyUnit]},
+    Schema {msg_name: "IDL", direction: Direction::FromTerminal, required: &[], optional: TERMINAL_STATUS},
+    Schema {msg_name: "DIS", direction: Direction::ToTerminal, required: &[], optional: &[OperationNum]},
+    Schema {msg_name: "DIS", direction: Direction::FromTerminal, required: &[], optional: TERMINAL_STATUS},
+    Schema {msg_name: "VRP", direction: Direction::ToTerminal, required: &[OperationNum, AmountInMinorCurrencyUnit],
+        optional: &[OperationTimeoutInSecs, ProductId, ProductName, QrCodeData]},
+    Schema {msg_name: "VRP", direction: Direction::FromTerminal, required: &[],
+        optional: &[OperationNum, AmountInMinorCurrencyUnit, ProductId, BankingReceipt, EventName, EventNum]},
+    Schema {msg_name: "FIN", direction: Direction::ToTerminal, required: &[OperationNum, AmountInMinorCurrencyUnit], optional: &[ProductId]},
+    Schema {msg_name: "FIN", direction: Direction::FromTerminal, required: &[],
+        optional: &[OperationNum, AmountInMinorCurrencyUnit, BankingReceipt]},
+    Schema {msg_name: "ABR", direction: Direction::ToTerminal, required: &[OperationNum], optional: &[]},
+    Schema {msg_name: "ABR", direction: Direction::FromTerminal, required: &[], optional: &[OperationNum, BankingReceipt]},
+];
+
+pub fn lookup(msg_name: &str, direction: Direction) -> Option<&'static Schema> {
+    REGISTRY.iter().find(|s| s.msg_name == msg_name && s.direction == direction)
+}
+
+/// A way a frame departs from its message's schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Violation {
+    Missing(TlvKey),
+    /// A known tag the message does not allow; unknown tags are reported as `Anomaly::UnknownTag` instead.
+    Unexpected(TlvKey),
+    /// A numeric tag whose value is empty or longer than eight bytes.
+    BadValue(TlvKey),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Missing(key) => write!(f, "missing {:?} (0x{:02X})", key, key.as_u8()),
+            Violation::Unexpected(key) => write!(f, "unexpected {:?} (0x{:02X})", key, key.as_u8()),
+            Violation::BadValue(key) => write!(f, "{:?} (0x{:02X}) is not a 1 to 8 byte number", key, key.as_u8()),
+        }
+    }
+}
+
+impl Schema {
+    pub fn allows(&self, key: TlvKey) -> bool {
+        key == MsgName || self.required.contains(&key) || self.optional.contains(&key)
+    }
+
+    pub fn check(&self, tlv: &Tlv) -> Vec<Violation> {
+        let mut violations: Vec<Violation> = self.required.iter()
+            .filter(|k| tlv.get_bin(**k).is_none())
+            .map(|k| Violation::Missing(*k))
+            .collect();
+        let mut unexpected: Vec<TlvKey> = tlv.data().keys().copied().filter(|k| k.is_known() && !self.allows(*k)).collect();
+        unexpected.sort_by_key(|k| k.as_u8());
+        violations.extend(unexpected.into_iter().map(Violation::Unexpected));
+        let mut bad: Vec<TlvKey> = tlv.data().iter()
+            .filter(|(k, v)| k.value_type().is_numeric() && !(1..=8).contains(&v.len()))
+            .map(|(k, _)| *k)
+            .collect();
+        bad.sort_by_key(|k| k.as_u8());
+        violations.extend(bad.into_iter().map(Violation::BadValue));
+        violations
+    }
+}
+
+/// Violations of `msg_name`'s schema; messages not in the registry have none.
+pub fn check(msg_name: &str, direction: Direction, tlv: &Tlv) -> Vec<Violation> {
+    lookup(msg_name, direction).map(|s| s.check(tlv)).unwrap_or_default()
+}
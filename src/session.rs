@@ -0,0 +1,356 @@
+use std::{io::{Error, ErrorKind}, time::{Duration, Instant, SystemTime}};
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::{audit::AuditEntry, charset::Charset, error::VtkError, journal::JournalEntry, record::{unix_ms, SaleOutcome, SaleRecord}, vtk::{Tlv, TlvKey, Vtk}};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Product {
+    pub id: Option<u32>,
+    pub name: Option<String>,
+}
+
+/// The terminal's answer to a payment request (VRP).
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub operation: u32,
+    pub amount: u64,
+    /// The terminal confirmed VRP for this operation, i.e. the customer paid.
+    pub approved: bool,
+    pub product: Option<Product>,
+    pub requested_at: SystemTime,
+    pub response: Tlv,
+    /// Encoding of the string tags in `response`.
+    pub charset: Charset,
+}
+
+/// What the FIN `answer` says was charged, or the `asked` amount when it does not say.
+fn accepted(answer: &Tlv, asked: u64) -> u64 {
+    answer.get_uint(TlvKey::AmountInMinorCurrencyUnit).unwrap_or(asked)
+}
+
+pub(crate) fn be_uint(v: &[u8]) -> Option<u64> {
+    if v.is_empty() || v.len() > 8 {return None;}
+    Some(v.iter().fold(0, |n, b| (n << 8) | *b as u64))
+}
+
+/// Time left from `now` until `deadline`, or a timeout error once it has passed.
+pub(crate) fn remaining(deadline: Instant, now: Instant) -> Result<Duration, Error> {
+    match deadline.checked_duration_since(now) {
+        Some(d) if !d.is_zero() => Ok(d),
+        _ => Err(Error::new(ErrorKind::TimedOut, "deadline passed")),
+    }
+}
+
+/// `now + timeout`, saturating a century out so a timeout of `Duration::MAX` never expires.
+pub(crate) fn deadline_after(now: Instant, timeout: Duration) -> Instant {
+    now.checked_add(timeout).unwrap_or_else(|| now + Duration::from_secs(100 * 365 * 24 * 3600))
+}
+
+/// Whether `e` means the connection went away, rather than the terminal being slow or saying no.
+fn connection_lost(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe | ErrorKind::NotConnected)
+}
+
+/// `template` with the operation's `{op}`, `{amount}` and `{product}` filled in; a missing product id leaves `{product}` empty.
+fn fill_qr(template: &str, operation: u32, amount: u64, product: Option<&Product>) -> String {
+    let product = product.and_then(|p| p.id).map(|id| id.to_string()).unwrap_or_default();
+    template.replace("{op}", &operation.to_string()).replace("{amount}", &amount.to_string()).replace("{product}", &product)
+}
+
+/// Approved payments kept for their outbox records; older ones are recorded without product and request time.
+const OPEN_SALES: usize = 64;
+
+impl Vtk {
+    /// The terminal already settled the sale, so a journal or outbox failure here is only logged and the entry stays
+    /// for recovery. `amount` is the finalized one, `None` for the requested one; `settlement` is the FIN or ABR answer.
+    fn resolve(&mut self, operation: u32, outcome: SaleOutcome, amount: Option<u64>, settlement: Option<&Tlv>) {
+        self.audit(AuditEntry::Outcome {operation, outcome});
+        if self.outbox.is_some() {
+            let record = match self.open_sales.remove(&operation) {
+                Some(payment) => payment.record(outcome, amount.unwrap_or(payment.amount), settlement, self.clock.system_now()),
+                None => {
+                    // Started before a restart: the journal may still know what was asked for.
+                    let entry = self.journal.as_ref().and_then(|j| j.list_unresolved().ok())
+                        .and_then(|entries| entries.into_iter().find(|e| e.operation == operation));
+                    SaleRecord {
+                        operation_num: operation,
+                        amount: amount.or(entry.as_ref().map(|e| e.amount)).unwrap_or_default(),
+                        product: entry.as_ref().and_then(|e| e.product.clone()),
+                        outcome,
+                        requested_at_ms: entry.map_or(0, |e| e.requested_at_ms),
+                        settled_at_ms: unix_ms(self.clock.system_now()),
+                        receipt: settlement.and_then(|t| t.get_text(TlvKey::BankingReceipt, self.config().charset)).map(|s| s.into_owned()),
+                    }
+                },
+            };
+            if let Some(Err(e)) = self.outbox.as_mut().map(|o| o.push(&record)) {
+                log::warn!("outbox: cannot queue operation {}: {}", operation, e);
+            }
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.mark_resolved(operation, outcome) {
+                log::warn!("journal: cannot resolve operation {}: {}", operation, e);
+            }
+        }
+    }
+
+    /// Asks the terminal to collect `amount` minor units; blocks while the customer pays, up to `timeout`.
+    pub fn request_payment(&mut self, amount: u64, product: Option<&Product>, timeout: Duration) -> Result<Payment, Error> {
+        let slack = self.config().read_timeout;
+        self.payment(amount, product, None, timeout, timeout.saturating_add(slack))
+    }
+
+    /// `request_payment` with `extras`, e.g. a loyalty id or discount data, sent in the VRP as they are. They are
+    /// exempt from the VRP schema, as an acquirer's own features may use any tag, but may not replace a tag the
+    /// driver sets.
+    pub fn request_payment_with(&mut self, amount: u64, product: Option<&Product>, extras: &Tlv, timeout: Duration) -> Result<Payment, Error> {
+        let slack = self.config().read_timeout;
+        self.payment(amount, product, Some(extras), timeout, timeout.saturating_add(slack))
+    }
+
+    /// Like `request_payment`, but everything is over by `deadline`: the terminal is told to give up
+    /// one read timeout early so its answer still arrives in time.
+    pub fn request_payment_deadline(&mut self, amount: u64, product: Option<&Product>, deadline: Instant) -> Result<Payment, Error> {
+        self.config().amount_limits.check(amount)?;
+        let remaining = remaining(deadline, self.now())?;
+        let timeout = remaining.saturating_sub(self.config().read_timeout);
+        if timeout.as_secs() == 0 {
+            return Err(Error::new(ErrorKind::TimedOut, "deadline leaves the customer no time to pay"));
+        }
+        self.payment(amount, product, None, timeout, remaining)
+    }
+
+    fn payment(&mut self, amount: u64, product: Option<&Product>, extras: Option<&Tlv>, timeout: Duration, wait: Duration) -> Result<Payment, Error> {
+        self.config().amount_limits.check(amount)?;
+        let operation = self.next_operation();
+        let requested_at = self.clock.system_now();
+        let mut tlv = Tlv::new();
+        tlv.set_u32(TlvKey::OperationNum, operation);
+        tlv.set_u64(TlvKey::AmountInMinorCurrencyUnit, amount);
+        tlv.set_duration(TlvKey::OperationTimeoutInSecs, timeout)?;
+        if let Some(product) = product {
+            if let Some(id) = product.id {
+                tlv.set_u32(TlvKey::ProductId, id);
+            }
+            if let Some(name) = &product.name {
+                tlv.set_text(TlvKey::ProductName, name, self.config().charset);
+            }
+        }
+        if let Some(template) = &self.config().qr_template {
+            let fill = |t: &str| fill_qr(t, operation, amount, product);
+            tlv.set_str(TlvKey::QrCodeData, &self.fit_qr(fill(template), fill)?);
+        }
+        let mut passthrough = Vec::new();
+        for (key, value) in extras.map(Tlv::data).into_iter().flatten() {
+            if *key == TlvKey::MsgName || tlv.get_bin(*key).is_some() {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("extra {:?} would replace the driver's", key)));
+            }
+            tlv.set_bin(*key, value);
+            passthrough.push(*key);
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            journal.append(&JournalEntry {operation, amount, product: product.cloned(), requested_at_ms: unix_ms(requested_at),
+                key: self.sale_key.take()})?;
+        }
+        self.passthrough = passthrough;
+        let sent = self.transact("VRP", tlv, wait);
+        self.passthrough.clear();
+        let response = match sent {
+            Err(e) if connection_lost(&e) => return Err(self.interrupted(operation, e)),
+            res => res?,
+        };
+        let same_op = response.get_uint(TlvKey::OperationNum).is_none_or(|op| op == operation as u64);
+        let approved = response.get_str(TlvKey::MsgName) == Some("VRP") && same_op;
+        let payment = Payment {operation, amount, approved, product: product.cloned(), requested_at, response, charset: self.config().charset};
+        if self.outbox.is_some() {
+            self.open_sales.insert(operation, payment.clone());
+            while self.open_sales.len() > OPEN_SALES {
+                self.open_sales.pop_first();
+            }
+        }
+        if !approved {
+            self.resolve(operation, SaleOutcome::Declined, None, None);
+        }
+        Ok(payment)
+    }
+
+    /// The connection dropped with VRP out, e.g. because the terminal rebooted. The protocol cannot ask what became
+    /// of `operation`, so the journal entry stays unresolved for recovery and the caller must not dispense.
+    fn interrupted(&mut self, operation: u32, lost: Error) -> Error {
+        log::warn!("operation {}: connection lost waiting for VRP, outcome unknown: {}", operation, lost);
+        self.disconnect();
+        VtkError::interrupted(operation, lost)
+    }
+
+    /// FIN for `amount`, or ABR without one, waiting up to `timeout` for the answer.
+    fn settle(&mut self, operation: u32, amount: Option<u64>, timeout: Duration) -> Result<Tlv, Error> {
+        let mut tlv = Tlv::new();
+        tlv.set_u32(TlvKey::OperationNum, operation);
+        let (msg_name, outcome) = match amount {
+            Some(amount) => {
+                tlv.set_u64(TlvKey::AmountInMinorCurrencyUnit, amount);
+                ("FIN", SaleOutcome::Finalized)
+            },
+            None => ("ABR", SaleOutcome::Aborted),
+        };
+        let answer = self.transact(msg_name, tlv, timeout)?;
+        let charged = amount.map(|a| accepted(&answer, a));
+        self.resolve(operation, outcome, charged, Some(&answer));
+        Ok(answer)
+    }
+
+    /// Confirms the sale after dispensing, charging `amount` (at most the authorized one).
+    pub fn finalize(&mut self, operation: u32, amount: u64) -> Result<Tlv, Error> {
+        let timeout = self.config().read_timeout;
+        self.settle(operation, Some(amount), timeout)
+    }
+
+    /// Cancels the operation, e.g. when the product could not be dispensed.
+    pub fn abort(&mut self, operation: u32) -> Result<Tlv, Error> {
+        let timeout = self.config().read_timeout;
+        self.settle(operation, None, timeout)
+    }
+
+    /// `finalize` for an approved payment, returning its record and printing its receipt.
+    pub fn finalize_sale(&mut self, payment: &Payment, amount: u64) -> Result<SaleRecord, Error> {
+        let timeout = self.config().read_timeout;
+        self.finalize_within(payment, amount, timeout)
+    }
+
+    fn finalize_within(&mut self, payment: &Payment, amount: u64, timeout: Duration) -> Result<SaleRecord, Error> {
+        let answer = self.settle(payment.operation, Some(amount), timeout)?;
+        let record = payment.record(SaleOutcome::Finalized, accepted(&answer, amount), Some(&answer), self.clock.system_now());
+        let slip = answer.get_bin(TlvKey::BankingReceipt).or_else(|| payment.response.get_bin(TlvKey::BankingReceipt));
+        if let (Some(sink), Some(slip)) = (self.receipts.as_mut(), slip) {
+            if let Err(e) = sink.print(&record, slip) {
+                log::warn!("receipt: operation {}: {}", payment.operation, e);
+            }
+        }
+        Ok(record)
+    }
+
+    /// Settles a sale where only some of what was paid for came out: FIN for `dispensed_amount`, or ABR when
+    /// nothing did. The protocol has no refund message; FIN below the authorized amount only charges that much,
+    /// and the terminal releases the rest with the authorization.
+    pub fn settle_partial(&mut self, payment: &Payment, dispensed_amount: u64) -> Result<SaleRecord, Error> {
+        match dispensed_amount {
+            0 => self.abort_sale(payment),
+            amount if amount > payment.amount =>
+                Err(Error::new(ErrorKind::InvalidInput, format!("dispensed {} of the {} paid for", amount, payment.amount))),
+            amount => self.finalize_sale(payment, amount),
+        }
+    }
+
+    /// `finalize_sale` for the authorized amount moved by `adjustment`, e.g. minus a deposit returned or plus a
+    /// surcharge; the record has the amount the terminal accepted. Going above the authorized amount needs
+    /// `Compat::fin_above_authorized` and must stay within `Config::amount_limits`; nothing is sent otherwise.
+    pub fn finalize_adjusted(&mut self, payment: &Payment, adjustment: i64) -> Result<SaleRecord, Error> {
+        let amount = payment.amount.checked_add_signed(adjustment)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("cannot adjust {} by {}", payment.amount, adjustment)))?;
+        if amount > payment.amount {
+            if !self.config().compat.fin_above_authorized {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("{} is above the {} authorized", amount, payment.amount)));
+            }
+            self.config().amount_limits.check(amount)?;
+        }
+        self.finalize_sale(payment, amount)
+    }
+
+    /// `abort` for a payment, returning its record.
+    pub fn abort_sale(&mut self, payment: &Payment) -> Result<SaleRecord, Error> {
+        let answer = self.abort(payment.operation)?;
+        Ok(payment.record(SaleOutcome::Aborted, payment.amount, Some(&answer), self.clock.system_now()))
+    }
+
+    /// `sell` under the caller's idempotency `key`, e.g. a basket id, kept in the journal: when an application
+    /// retries after a crash, a sale with the same key that finalized or is still unresolved is refused with
+    /// `VtkError::Duplicate` instead of charging again. A declined or aborted one charged nothing and may be
+    /// retried under its key. Needs a journal that keeps keys, such as `FileJournal`.
+    pub fn sell_once<F>(&mut self, key: &str, amount: u64, product: Option<&Product>, budget: Duration, dispense: F) -> Result<SaleRecord, Error>
+    where F: FnOnce(&Payment) -> Result<u64, Error> {
+        if key.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "empty idempotency key"));
+        }
+        let journal = self.journal.as_ref().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "idempotency keys need a journal"))?;
+        if let Some((operation, outcome)) = journal.find_key(key)? {
+            if outcome.is_none_or(|o| o == SaleOutcome::Finalized) {
+                return Err(VtkError::duplicate(key, operation, outcome));
+            }
+        }
+        self.sale_key = Some(String::from(key));
+        let sold = self.sell(amount, product, budget, dispense);
+        self.sale_key = None;
+        sold
+    }
+
+    /// Holds up to `max_amount` for a sale priced only after dispensing, e.g. a coffee with add-ons; `capture`
+    /// charges what it came to and `void` releases the hold. Only one hold is open at a time.
+    pub fn preauthorize(&mut self, max_amount: u64, product: Option<&Product>, timeout: Duration) -> Result<Payment, Error> {
+        if let Some(hold) = &self.hold {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("operation {} is still held", hold.operation)));
+        }
+        let payment = self.request_payment(max_amount, product, timeout)?;
+        if payment.approved {
+            self.hold = Some(payment.clone());
+        }
+        Ok(payment)
+    }
+
+    /// The hold `preauthorize` left open.
+    pub fn hold(&self) -> Option<&Payment> {
+        self.hold.as_ref()
+    }
+
+    /// Charges `actual`, at most the held amount, and closes the hold. It stays open when FIN fails, so the
+    /// capture can be retried or the hold voided.
+    pub fn capture(&mut self, actual: u64) -> Result<SaleRecord, Error> {
+        let hold = self.hold.clone().ok_or_else(|| Error::new(ErrorKind::NotFound, "nothing is held"))?;
+        if actual > hold.amount {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("cannot capture {} of the {} held", actual, hold.amount)));
+        }
+        let record = self.finalize_sale(&hold, actual)?;
+        self.hold = None;
+        Ok(record)
+    }
+
+    /// Releases the hold without charging, e.g. when dispensing failed.
+    pub fn void(&mut self) -> Result<SaleRecord, Error> {
+        let hold = self.hold.clone().ok_or_else(|| Error::new(ErrorKind::NotFound, "nothing is held"))?;
+        let record = self.abort_sale(&hold)?;
+        self.hold = None;
+        Ok(record)
+    }
+
+    /// A whole sale within `budget`: IDL, VRP, then `dispense` for an approved payment and FIN for the amount it
+    /// returns, or ABR when it fails.
+    ///
+    /// The legs share the budget rather than each taking its own timeout: one `Config::read_timeout` is held back
+    /// for the settlement, IDL and VRP must be over before it, and the customer gets what VRP leaves. A `dispense`
+    /// that runs into the held-back time shortens the settlement; one that outlasts the budget leaves the sale
+    /// unsettled, for `Journal` recovery, with a timeout error.
+    pub fn sell<F>(&mut self, amount: u64, product: Option<&Product>, budget: Duration, dispense: F) -> Result<SaleRecord, Error>
+    where F: FnOnce(&Payment) -> Result<u64, Error> {
+        let deadline = deadline_after(self.now(), budget);
+        let reserve = self.config().read_timeout;
+        let vrp_deadline = deadline.checked_sub(reserve).filter(|d| *d > self.now())
+            .ok_or_else(|| Error::new(ErrorKind::TimedOut, "budget leaves no time for the settlement"))?;
+        self.idle_until(None, Some(vrp_deadline))?;
+        let payment = self.request_payment_deadline(amount, product, vrp_deadline)?;
+        if !payment.approved {
+            return Ok(payment.record(SaleOutcome::Declined, amount, None, self.clock.system_now()));
+        }
+        let dispensed = dispense(&payment);
+        let left = remaining(deadline, self.now())?.min(reserve);
+        match dispensed {
+            Ok(charged) => self.finalize_within(&payment, charged, left),
+            Err(e) => {
+                log::warn!("operation {}: aborting, dispensing failed: {}", payment.operation, e);
+                let answer = self.settle(payment.operation, None, left)?;
+                Ok(payment.record(SaleOutcome::Aborted, payment.amount, Some(&answer), self.clock.system_now()))
+            },
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! `proptest` strategies for well-formed terminal data, for property tests in downstream crates.
+
+use proptest::{collection::vec, prelude::*, sample::{select, subsequence}};
+
+use crate::{frame::Frame, schema::{Direction, Schema, REGISTRY}, vtk::{be_bytes, Tlv, TlvKey, ValueType}};
+
+/// Any tag this driver has a name for.
+pub fn known_key() -> impl Strategy<Value = TlvKey> {
+    select(TlvKey::ALL.to_vec())
+}
+
+/// A value shaped like what terminals put in `key`: numbers big-endian, text printable, the rest raw.
+pub fn value(key: TlvKey) -> BoxedStrategy<Vec<u8>> {
+    match (key, key.value_type()) {
+        (TlvKey::MsgName, _) => select(vec!["IDL", "DIS", "VRP", "FIN", "ABR"]).prop_map(|s| s.as_bytes().to_vec()).boxed(),
+        (TlvKey::BankingReceipt, _) => "[ -~\n]{0,255}".prop_map(String::into_bytes).boxed(),
+        (_, t) if t.is_numeric() => any::<u32>().prop_map(|n| be_bytes(n as u64)).boxed(),
+        (_, ValueType::Text) => "[ -~]{0,64}".prop_map(String::into_bytes).boxed(),
+        _ => vec(any::<u8>(), 0..=64).boxed(),
+    }
+}
+
+fn tags(keys: Vec<TlvKey>) -> impl Strategy<Value = Tlv> {
+    keys.into_iter().map(|k| value(k).prop_map(move |v| (k, v))).collect::<Vec<_>>().prop_map(|tags| {
+        let mut tlv = Tlv::new();
+        for (k, v) in tags {
+            tlv.set_bin(k, &v);
+        }
+        tlv
+    })
+}
+
+/// Up to `max_tags` distinct known tags with realistic values, MsgName included or not.
+pub fn tlv(max_tags: usize) -> impl Strategy<Value = Tlv> {
+    subsequence(TlvKey::ALL.to_vec(), 0..=max_tags.min(TlvKey::ALL.len())).prop_flat_map(tags)
+}
+
+fn message(schema: &'static Schema) -> impl Strategy<Value = Frame> {
+    subsequence(schema.optional.to_vec(), 0..=schema.optional.len())
+        .prop_flat_map(move |optional| tags([schema.required, &optional].concat()))
+        .prop_map(move |tlv| Frame::new(schema.msg_name, tlv))
+}
+
+/// A frame that passes `schema::check` for its message in `direction`.
+pub fn frame(direction: Direction) -> impl Strategy<Value = Frame> {
+    select(REGISTRY.iter().filter(|s| s.direction == direction).collect::<Vec<_>>()).prop_flat_map(message)
+}
+
+/// `frame(direction)`, encoded for the wire.
+pub fn frame_bytes(direction: Direction) -> impl Strategy<Value = Vec<u8>> {
+    frame(direction).prop_filter_map("too long to encode", |f| f.encode().ok())
+}
@@ -0,0 +1,183 @@
+//! Tag names, shared by `Tlv` and `FixedTlv`; only `core` is used without the `std` feature.
+
+#[cfg(feature = "std")]
+use std::{io::{Error, ErrorKind}, str::FromStr};
+
+#[derive(PartialEq, Hash, Eq, Debug, Clone, Copy)]
+pub enum TlvKey {
+    MsgName,
+    OperationNum,
+    AmountInMinorCurrencyUnit,
+    KeepaliveIntervalInSecs,
+    OperationTimeoutInSecs,
+    EventName,
+    EventNum,
+    ProductId,
+    QrCodeData,
+    TcpIpDestantion,
+    OutgoingByteCounter,
+    SimpleDataBlock,
+    ConfirmableDataBlock,
+    ProductName,
+    PosManagementData,
+    LocalTime,
+    SysInfo,
+    BankingReceipt,
+    DisplayTimeInMs,
+    /// A tag this driver has no name for; `from_u8` never wraps a known one.
+    Unknown(u8),
+}
+
+impl TlvKey {
+    pub const ALL: [TlvKey; 19] = [
+        TlvKey::MsgName, TlvKey::OperationNum, TlvKey::AmountInMinorCurrencyUnit, TlvKey::KeepaliveIntervalInSecs,
+        TlvKey::OperationTimeoutInSecs, TlvKey::EventName, TlvKey::EventNum, TlvKey::ProductId, TlvKey::QrCodeData,
+        TlvKey::TcpIpDestantion, TlvKey::OutgoingByteCounter, TlvKey::SimpleDataBlock, TlvKey::ConfirmableDataBlock,
+        TlvKey::ProductName, TlvKey::PosManagementData, TlvKey::LocalTime, TlvKey::SysInfo, TlvKey::BankingReceipt,
+        TlvKey::DisplayTimeInMs,
+    ];
+
+    pub const fn as_u8(self) -> u8 {
+        match self {
+            TlvKey::MsgName => 0x01,
+            TlvKey::OperationNum => 0x03,
+            TlvKey::AmountInMinorCurrencyUnit => 0x04,
+            TlvKey::KeepaliveIntervalInSecs => 0x05,
+            TlvKey::OperationTimeoutInSecs => 0x06,
+            TlvKey::EventName => 0x07,
+            TlvKey::EventNum => 0x08,
+            TlvKey::ProductId => 0x09,
+            TlvKey::QrCodeData => 0x0A,
+            TlvKey::TcpIpDestantion => 0x0B,
+            TlvKey::OutgoingByteCounter => 0x0C,
+            TlvKey::SimpleDataBlock => 0x0D,
+            TlvKey::ConfirmableDataBlock => 0x0E,
+            TlvKey::ProductName => 0x0F,
+            TlvKey::PosManagementData => 0x10,
+            TlvKey::LocalTime => 0x11,
+            TlvKey::SysInfo => 0x12,
+            TlvKey::BankingReceipt => 0x13,
+            TlvKey::DisplayTimeInMs => 0x14,
+            TlvKey::Unknown(tag) => tag,
+        }
+    }
+
+    /// The named key for `tag`, or `Unknown(tag)`.
+    pub fn from_u8(tag: u8) -> Self {
+        Self::try_from(tag).unwrap_or(TlvKey::Unknown(tag))
+    }
+
+    pub fn is_known(self) -> bool {
+        !matches!(self, TlvKey::Unknown(_))
+    }
+
+    /// See `logging::is_sensitive`.
+    pub(crate) const fn is_sensitive(self) -> bool {
+        matches!(self, TlvKey::BankingReceipt | TlvKey::SimpleDataBlock | TlvKey::ConfirmableDataBlock | TlvKey::PosManagementData
+            | TlvKey::Unknown(_))
+    }
+
+    /// `None` for unknown tags.
+    pub const fn category(self) -> Option<TagCategory> {
+        use TlvKey::*;
+        Some(match self {
+            MsgName | OperationNum | AmountInMinorCurrencyUnit | OperationTimeoutInSecs | EventName | EventNum | ProductId
+                | ProductName | BankingReceipt => TagCategory::Session,
+            QrCodeData | DisplayTimeInMs => TagCategory::Display,
+            TcpIpDestantion | OutgoingByteCounter | SimpleDataBlock | ConfirmableDataBlock => TagCategory::DataTransfer,
+            KeepaliveIntervalInSecs | PosManagementData | LocalTime | SysInfo => TagCategory::Management,
+            Unknown(_) => return None,
+        })
+    }
+
+    /// Unknown tags are `ValueType::Bytes`.
+    pub const fn value_type(self) -> ValueType {
+        use TlvKey::*;
+        match self {
+            OperationNum | AmountInMinorCurrencyUnit | EventNum | ProductId | OutgoingByteCounter => ValueType::Uint,
+            KeepaliveIntervalInSecs | OperationTimeoutInSecs => ValueType::Seconds,
+            DisplayTimeInMs => ValueType::Millis,
+            MsgName | EventName | QrCodeData | TcpIpDestantion | ProductName | LocalTime | SysInfo | BankingReceipt => ValueType::Text,
+            SimpleDataBlock | ConfirmableDataBlock | PosManagementData | Unknown(_) => ValueType::Bytes,
+        }
+    }
+}
+
+/// The part of the protocol a tag belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TagCategory {
+    /// Messages and the sale they carry: operation, amount, product, events and the receipt.
+    Session,
+    /// What the terminal shows.
+    Display,
+    /// Data blocks the terminal relays to and from its host.
+    DataTransfer,
+    /// Keepalive, clock, identity and management blocks.
+    Management,
+}
+
+impl TagCategory {
+    pub const fn name(self) -> &'static str {
+        match self {
+            TagCategory::Session => "session",
+            TagCategory::Display => "display",
+            TagCategory::DataTransfer => "data_transfer",
+            TagCategory::Management => "management",
+        }
+    }
+}
+
+/// How a tag's value is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    /// Text in `Config::charset`.
+    Text,
+    /// Unsigned big-endian, one to eight bytes.
+    Uint,
+    /// `Uint` counting seconds.
+    Seconds,
+    /// `Uint` counting milliseconds.
+    Millis,
+    Bytes,
+}
+
+impl ValueType {
+    pub const fn is_numeric(self) -> bool {
+        matches!(self, ValueType::Uint | ValueType::Seconds | ValueType::Millis)
+    }
+}
+
+/// Succeeds for named tags only; the error carries the tag back.
+impl TryFrom<u8> for TlvKey {
+    type Error = u8;
+
+    fn try_from(tag: u8) -> Result<Self, u8> {
+        TlvKey::ALL.iter().find(|k| k.as_u8() == tag).copied().ok_or(tag)
+    }
+}
+
+impl From<TlvKey> for u8 {
+    fn from(key: TlvKey) -> u8 {
+        key.as_u8()
+    }
+}
+
+/// Parses a tag from its name (case-insensitive) or its number, decimal or `0x` hex; numbers may be unknown tags.
+#[cfg(feature = "std")]
+impl FromStr for TlvKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let num = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u8::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        };
+        if let Some(num) = num {
+            return Ok(TlvKey::from_u8(num));
+        }
+        TlvKey::ALL.iter()
+            .find(|k| format!("{:?}", k).eq_ignore_ascii_case(s))
+            .copied()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unknown tag {}", s)))
+    }
+}
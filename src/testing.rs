@@ -0,0 +1,236 @@
+// A poisoned lock here means a test thread already panicked.
+#![allow(clippy::unwrap_used)]
+
+use std::{collections::{HashMap, VecDeque}, io::{Error, Read, Write}, net::{Shutdown, TcpListener, TcpStream}, sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex}, thread::{self, JoinHandle}, time::Duration};
+
+use crate::{frame::Frame, schema::{self, Direction, Violation}, vtk::{Tlv, TlvKey}};
+
+/// Failure applied to one response of the mock terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Hold the response back before writing it.
+    Delay(Duration),
+    /// Read the request but never answer it.
+    DropFrame,
+    /// Write only the first `n` bytes of the response and keep the socket open.
+    Truncate(usize),
+    /// Write half of the response, then close the connection.
+    Disconnect,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    msg_name: String,
+    answer: Option<String>,
+    tags: Tlv,
+    fault: Option<Fault>,
+}
+
+/// The requests a test expects, in order, and what the mock answers to each; load it with `MockTerminal::script`.
+///
+/// ```ignore
+/// mock.script(ExpectScript::new()
+///     .expect("IDL")
+///     .expect("VRP").respond_as("ABR", Tlv::new())
+///     .expect("IDL"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ExpectScript {
+    steps: Vec<Step>,
+}
+
+impl ExpectScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next request must be `msg_name`; by default it is answered with an empty frame of the same name.
+    pub fn expect(mut self, msg_name: &str) -> Self {
+        self.steps.push(Step {msg_name: String::from(msg_name), answer: None, tags: Tlv::new(), fault: None});
+        self
+    }
+
+    /// Tags of the answer to the last `expect`.
+    pub fn respond(mut self, tags: Tlv) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.tags = tags;
+        }
+        self
+    }
+
+    /// Answers the last `expect` under another message name, e.g. a VRP with ABR.
+    pub fn respond_as(mut self, msg_name: &str, tags: Tlv) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.answer = Some(String::from(msg_name));
+            step.tags = tags;
+        }
+        self
+    }
+
+    /// Applies `fault` to the answer of the last `expect`.
+    pub fn fault(mut self, fault: Fault) -> Self {
+        if let Some(step) = self.steps.last_mut() {
+            step.fault = Some(fault);
+        }
+        self
+    }
+}
+
+#[derive(Default)]
+struct State {
+    faults: VecDeque<Fault>,
+    latency: Duration,
+    replies: HashMap<String, Tlv>,
+    received: Vec<Tlv>,
+    violations: Vec<(String, Violation)>,
+    /// `Some` once a script is loaded, even after all its steps are used up.
+    script: Option<VecDeque<Step>>,
+    script_errors: Vec<String>,
+}
+
+/// A terminal double on a loopback port that echoes each request's message name.
+pub struct MockTerminal {
+    port: u16,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl MockTerminal {
+    pub fn start() -> Result<Self, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let state = Arc::new(Mutex::new(State::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let state = state.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {break;}
+                    if let Ok(stream) = stream {
+                        serve(stream, &state);
+                    }
+                }
+            })
+        };
+        Ok(Self {port, state, stop, worker: Some(worker)})
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Queues a fault for the next response; faults are consumed in order.
+    pub fn inject(&self, fault: Fault) {
+        self.state.lock().unwrap().faults.push_back(fault);
+    }
+
+    /// Delay added to every response on top of injected faults.
+    pub fn set_latency(&self, latency: Duration) {
+        self.state.lock().unwrap().latency = latency;
+    }
+
+    /// Extra tags sent back whenever `msg_name` is received.
+    pub fn reply(&self, msg_name: &str, tlv: Tlv) {
+        self.state.lock().unwrap().replies.insert(String::from(msg_name), tlv);
+    }
+
+    pub fn received(&self) -> Vec<Tlv> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Replaces the echo behaviour with `script`; requests that break it are answered by echo and reported by
+    /// `assert_script_done`.
+    pub fn script(&self, script: ExpectScript) {
+        let mut state = self.state.lock().unwrap();
+        state.script = Some(script.steps.into());
+        state.script_errors.clear();
+    }
+
+    /// Panics unless every scripted request arrived, in order, and nothing else did.
+    pub fn assert_script_done(&self) {
+        let state = self.state.lock().unwrap();
+        let mut problems = state.script_errors.clone();
+        let left = state.script.iter().flatten().map(|s| s.msg_name.as_str()).collect::<Vec<_>>();
+        if !left.is_empty() {
+            problems.push(format!("never received {}", left.join(", ")));
+        }
+        assert!(problems.is_empty(), "mock terminal script: {}", problems.join("; "));
+    }
+
+    /// Schema violations in the requests received so far, with their message names.
+    pub fn violations(&self) -> Vec<(String, Violation)> {
+        self.state.lock().unwrap().violations.clone()
+    }
+}
+
+impl Drop for MockTerminal {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        _ = TcpStream::connect(("127.0.0.1", self.port));
+        if let Some(worker) = self.worker.take() {
+            _ = worker.join();
+        }
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<Tlv> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).ok()?;
+    let mut body = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).ok()?;
+    if body.len() < 2 {return None;}
+    Some(Tlv::deserialize(&body[2..]))
+}
+
+/// The script step `msg_name` satisfies, recording a mismatch when it breaks the script.
+fn scripted(state: &mut State, msg_name: &str) -> Option<Step> {
+    let script = state.script.as_mut()?;
+    let problem = match script.pop_front() {
+        Some(step) if step.msg_name == msg_name => return Some(step),
+        Some(step) => format!("expected {}, got {}", step.msg_name, msg_name),
+        None => format!("unexpected {} after the script ended", msg_name),
+    };
+    state.script_errors.push(problem);
+    None
+}
+
+fn serve(mut stream: TcpStream, state: &Mutex<State>) {
+    while let Some(request) = read_request(&mut stream) {
+        let msg_name = String::from(request.get_str(TlvKey::MsgName).unwrap_or_default());
+        let (fault, latency, frame) = {
+            let mut state = state.lock().unwrap();
+            for violation in schema::check(&msg_name, Direction::ToTerminal, &request) {
+                state.violations.push((msg_name.clone(), violation));
+            }
+            state.received.push(request);
+            let step = scripted(&mut state, &msg_name);
+            let (answer, reply, fault) = match step {
+                Some(step) => (step.answer.unwrap_or_else(|| msg_name.clone()), step.tags, step.fault),
+                None => (msg_name.clone(), state.replies.get(&msg_name).cloned().unwrap_or_default(), None),
+            };
+            (fault.or_else(|| state.faults.pop_front()), state.latency, Frame::new(&answer, reply).encode())
+        };
+        // A scripted reply too long to encode hangs up, as a terminal with a corrupt frame would.
+        let Ok(frame) = frame else {
+            _ = stream.shutdown(Shutdown::Both);
+            return;
+        };
+        thread::sleep(latency);
+        match fault {
+            None => {_ = stream.write_all(&frame);},
+            Some(Fault::Delay(d)) => {
+                thread::sleep(d);
+                _ = stream.write_all(&frame);
+            },
+            Some(Fault::DropFrame) => (),
+            Some(Fault::Truncate(n)) => {_ = stream.write_all(&frame[..n.min(frame.len())]);},
+            Some(Fault::Disconnect) => {
+                _ = stream.write_all(&frame[..frame.len() / 2]);
+                _ = stream.shutdown(Shutdown::Both);
+                return;
+            },
+        }
+    }
+}
@@ -0,0 +1,348 @@
+//! Session recordings and their rendering as sequence diagrams for support tickets.
+//!
+//! A recording holds the raw frames, receipts included, so treat it like a wire dump.
+
+use std::{fmt::Write as _, fs::{File, OpenOptions}, io::{BufRead, Error, ErrorKind, Read, Write}, path::Path, time::{Duration, UNIX_EPOCH}};
+
+use crate::{frame::Frame, rotate::{Rotate, RotatingFile}, schema::Direction, session::be_uint, vtk::TlvKey};
+
+/// One frame as it went over the wire; `ToTerminal` frames were sent by this side.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub at_ms: u64,
+    pub direction: Direction,
+    pub raw: Vec<u8>,
+}
+
+/// Receives every frame the driver sends or reads.
+pub trait Recorder: Send {
+    fn record(&mut self, entry: &TraceEntry) -> Result<(), Error>;
+}
+
+/// Writes one JSON object per line: `{"at_ms":1700000000000,"dir":"sent","frame":"000996FB..."}`.
+pub struct JsonlRecorder<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> JsonlRecorder<W> {
+    pub fn new(out: W) -> Self {
+        Self {out}
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl JsonlRecorder<File> {
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(Self::new(OpenOptions::new().create(true).append(true).open(path)?))
+    }
+}
+
+fn write_jsonl<W: Write>(out: &mut W, entry: &TraceEntry) -> Result<(), Error> {
+    let dir = match entry.direction {
+        Direction::ToTerminal => "sent",
+        Direction::FromTerminal => "received",
+    };
+    let frame: String = entry.raw.iter().map(|b| format!("{:02X}", b)).collect();
+    writeln!(out, "{{\"at_ms\":{},\"dir\":\"{}\",\"frame\":\"{}\"}}", entry.at_ms, dir, frame)?;
+    out.flush()
+}
+
+impl<W: Write + Send> Recorder for JsonlRecorder<W> {
+    fn record(&mut self, entry: &TraceEntry) -> Result<(), Error> {
+        write_jsonl(&mut self.out, entry)
+    }
+}
+
+/// The raw value of `key` in one of our own JSONL lines.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("\"{}\":", key))? + key.len() + 3;
+    let value = line[start..].trim_start();
+    let end = value.find([',', '}']).unwrap_or(value.len());
+    Some(value[..end].trim().trim_matches('"'))
+}
+
+/// Reads a recording written by `JsonlRecorder`, skipping blank lines.
+pub fn read_jsonl<R: BufRead>(input: R) -> Result<Vec<TraceEntry>, Error> {
+    let mut entries = Vec::new();
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {continue;}
+        let bad = || Error::new(ErrorKind::InvalidData, format!("recording line {}: {}", n + 1, line));
+        let at_ms = field(&line, "at_ms").and_then(|v| v.parse().ok()).ok_or_else(bad)?;
+        let direction = match field(&line, "dir") {
+            Some("sent") => Direction::ToTerminal,
+            Some("received") => Direction::FromTerminal,
+            _ => return Err(bad()),
+        };
+        let hex = field(&line, "frame").filter(|h| h.len() % 2 == 0 && h.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(bad)?;
+        let raw = (0..hex.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(bad)?;
+        entries.push(TraceEntry {at_ms, direction, raw});
+    }
+    Ok(entries)
+}
+
+/// Starts a `BinaryRecorder` file; the last byte is the format version.
+pub const BINARY_MAGIC: [u8; 5] = *b"VTKR\x01";
+
+/// Set in an entry's first byte when its time is absolute rather than a delta from the entry before.
+const ABSOLUTE: u8 = 0x80;
+
+/// Compact recordings for long captures on small flash: `BINARY_MAGIC`, then per frame a byte that is 0 for sent
+/// and 1 for received (plus `ABSOLUTE`), the time in LEB128 and the raw length in LEB128 followed by the bytes.
+///
+/// Times are deltas from the previous entry, except for the first one written by each recorder, so appending
+/// after a restart needs no look at what the file already holds.
+pub struct BinaryRecorder<W: Write + Send> {
+    out: W,
+    header: bool,
+    last_ms: Option<u64>,
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// `None` at a clean end of input.
+fn read_leb128<R: Read>(input: &mut R) -> Result<Option<u64>, Error> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        if input.read(&mut byte)? == 0 {
+            return if shift == 0 {Ok(None)} else {Err(Error::from(ErrorKind::UnexpectedEof))};
+        }
+        n |= ((byte[0] & 0x7F) as u64) << shift;
+        if byte[0] & 0x80 == 0 {return Ok(Some(n));}
+    }
+    Err(Error::new(ErrorKind::InvalidData, "recording number longer than 64 bits"))
+}
+
+impl<W: Write + Send> BinaryRecorder<W> {
+    /// Writes `BINARY_MAGIC` before the first entry.
+    pub fn new(out: W) -> Self {
+        Self {out, header: true, last_ms: None}
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl BinaryRecorder<File> {
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let header = file.metadata()?.len() == 0;
+        Ok(Self {header, ..Self::new(file)})
+    }
+}
+
+/// One entry, after the magic with `header`; its time is a delta from `last_ms` when there is one.
+fn write_binary<W: Write>(out: &mut W, entry: &TraceEntry, header: bool, last_ms: Option<u64>) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(entry.raw.len() + 12);
+    if header {
+        buf.extend(BINARY_MAGIC);
+    }
+    let dir = match entry.direction {
+        Direction::ToTerminal => 0,
+        Direction::FromTerminal => 1,
+    };
+    match last_ms {
+        Some(last) if entry.at_ms >= last => {
+            buf.push(dir);
+            write_leb128(&mut buf, entry.at_ms - last);
+        },
+        _ => {
+            buf.push(dir | ABSOLUTE);
+            write_leb128(&mut buf, entry.at_ms);
+        },
+    }
+    write_leb128(&mut buf, entry.raw.len() as u64);
+    buf.extend(&entry.raw);
+    out.write_all(&buf)?;
+    out.flush()
+}
+
+impl<W: Write + Send> Recorder for BinaryRecorder<W> {
+    fn record(&mut self, entry: &TraceEntry) -> Result<(), Error> {
+        write_binary(&mut self.out, entry, self.header, self.last_ms)?;
+        self.header = false;
+        self.last_ms = Some(entry.at_ms);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    Jsonl,
+    /// See `BinaryRecorder`.
+    Binary,
+}
+
+impl RecordingFormat {
+    /// `Binary` for a `.vtkrec` file, `Jsonl` otherwise.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension() {
+            Some(ext) if ext == "vtkrec" => RecordingFormat::Binary,
+            _ => RecordingFormat::Jsonl,
+        }
+    }
+}
+
+/// Records to a file that rotates, and may prune and compress its old parts, as `Rotate` says; every part of a
+/// binary recording starts with the magic and an absolute time, so each reads on its own.
+pub struct RotatingRecorder {
+    file: RotatingFile,
+    format: RecordingFormat,
+    last_ms: Option<u64>,
+}
+
+impl RotatingRecorder {
+    pub fn open<P: AsRef<Path>>(path: P, format: RecordingFormat, rotate: Rotate) -> Result<Self, Error> {
+        Ok(Self {file: RotatingFile::open(path, rotate)?, format, last_ms: None})
+    }
+}
+
+impl Recorder for RotatingRecorder {
+    fn record(&mut self, entry: &TraceEntry) -> Result<(), Error> {
+        self.file.rotate_if_due_at(UNIX_EPOCH + Duration::from_millis(entry.at_ms))?;
+        let fresh = self.file.is_empty();
+        if fresh {
+            self.last_ms = None;
+        }
+        match self.format {
+            RecordingFormat::Jsonl => write_jsonl(&mut self.file, entry)?,
+            RecordingFormat::Binary => write_binary(&mut self.file, entry, fresh, self.last_ms)?,
+        }
+        self.last_ms = Some(entry.at_ms);
+        Ok(())
+    }
+}
+
+/// Reads a recording written by `BinaryRecorder`.
+pub fn read_binary<R: Read>(mut input: R) -> Result<Vec<TraceEntry>, Error> {
+    let mut magic = [0; BINARY_MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != BINARY_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a binary vtk recording"));
+    }
+    let mut entries = Vec::new();
+    let mut last_ms = 0;
+    loop {
+        let mut head = [0];
+        if input.read(&mut head)? == 0 {break;}
+        let bad = || Error::new(ErrorKind::InvalidData, format!("recording entry {}: bad header {:02X}", entries.len() + 1, head[0]));
+        let direction = match head[0] & !ABSOLUTE {
+            0 => Direction::ToTerminal,
+            1 => Direction::FromTerminal,
+            _ => return Err(bad()),
+        };
+        let at = read_leb128(&mut input)?.ok_or(ErrorKind::UnexpectedEof)?;
+        let at_ms = if head[0] & ABSOLUTE != 0 {at} else {last_ms + at};
+        let len = read_leb128(&mut input)?.ok_or(ErrorKind::UnexpectedEof)?;
+        let mut raw = Vec::new();
+        if input.by_ref().take(len).read_to_end(&mut raw)? as u64 != len {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        entries.push(TraceEntry {at_ms, direction, raw});
+        last_ms = at_ms;
+    }
+    Ok(entries)
+}
+
+/// Reads either kind of recording, told apart by `BINARY_MAGIC`.
+pub fn read_recording<R: BufRead>(mut input: R) -> Result<Vec<TraceEntry>, Error> {
+    if input.fill_buf()?.starts_with(&BINARY_MAGIC[..4]) {
+        read_binary(input)
+    } else {
+        read_jsonl(input)
+    }
+}
+
+/// Rewrites `entries` through `recorder`, e.g. to convert a recording from one format to the other.
+pub fn write_recording(recorder: &mut dyn Recorder, entries: &[TraceEntry]) -> Result<(), Error> {
+    entries.iter().try_for_each(|e| recorder.record(e))
+}
+
+/// Tags worth showing on an arrow, with the names support teams know them by.
+const KEY_FIELDS: [(TlvKey, &str); 6] = [
+    (TlvKey::OperationNum, "op"),
+    (TlvKey::AmountInMinorCurrencyUnit, "amount"),
+    (TlvKey::ProductId, "product"),
+    (TlvKey::EventName, "event"),
+    (TlvKey::EventNum, "event_num"),
+    (TlvKey::OperationTimeoutInSecs, "timeout"),
+];
+
+fn label(raw: &[u8]) -> String {
+    let Ok((frame, _)) = Frame::decode(raw) else {
+        return format!("{} undecodable bytes", raw.len());
+    };
+    let mut label = if frame.msg_name.is_empty() {String::from("???")} else {frame.msg_name.clone()};
+    for (key, name) in KEY_FIELDS {
+        let Some(v) = frame.tlv.get_bin(key) else {continue;};
+        let text = match key {
+            TlvKey::EventName => String::from_utf8_lossy(v).chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').collect(),
+            _ => be_uint(v).map(|n| n.to_string()).unwrap_or_else(|| String::from("?")),
+        };
+        _ = write!(label, " {}={}", name, text);
+    }
+    label
+}
+
+fn gap(prev: Option<u64>, at_ms: u64) -> Option<String> {
+    let ms = at_ms.saturating_sub(prev?);
+    (ms >= 1000).then(|| format!("+{}.{} s", ms / 1000, ms % 1000 / 100))
+}
+
+/// Mermaid `sequenceDiagram` text, with a note wherever the line went quiet for a second or more.
+pub fn mermaid(entries: &[TraceEntry]) -> String {
+    let mut out = String::from("sequenceDiagram\n    participant POS\n    participant Terminal\n");
+    let mut prev = None;
+    for entry in entries {
+        if let Some(gap) = gap(prev, entry.at_ms) {
+            _ = writeln!(out, "    Note over POS,Terminal: {}", gap);
+        }
+        let arrow = match entry.direction {
+            Direction::ToTerminal => "POS->>Terminal",
+            Direction::FromTerminal => "Terminal-->>POS",
+        };
+        _ = writeln!(out, "    {}: {}", arrow, label(&entry.raw));
+        prev = Some(entry.at_ms);
+    }
+    out
+}
+
+/// The same diagram in PlantUML.
+pub fn plantuml(entries: &[TraceEntry]) -> String {
+    let mut out = String::from("@startuml\nparticipant POS\nparticipant Terminal\n");
+    let mut prev = None;
+    for entry in entries {
+        if let Some(gap) = gap(prev, entry.at_ms) {
+            _ = writeln!(out, "... {} ...", gap);
+        }
+        let arrow = match entry.direction {
+            Direction::ToTerminal => "POS -> Terminal",
+            Direction::FromTerminal => "Terminal --> POS",
+        };
+        _ = writeln!(out, "{}: {}", arrow, label(&entry.raw));
+        prev = Some(entry.at_ms);
+    }
+    out.push_str("@enduml\n");
+    out
+}
@@ -0,0 +1,83 @@
+//! What the driver moves frames over: TCP by default, or anything implementing `Transport`.
+
+use std::{io::Error, time::Duration};
+
+use crate::config::Config;
+
+/// A byte stream to the terminal.
+pub trait Transport: Send {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), Error>;
+    /// Reads what has arrived, waiting up to `timeout` for the first byte; `Ok(0)` means the peer hung up.
+    fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+    /// Whether the peer is known to be gone, checked without blocking or consuming anything; `false` when unsure.
+    fn is_stale(&mut self) -> bool {
+        false
+    }
+    fn close(&mut self) {}
+}
+
+/// Opens a `Transport` each time the driver connects, which it does again after every `disconnect`.
+pub trait Connector: Send {
+    fn connect(&mut self, config: &Config) -> Result<Box<dyn Transport>, Error>;
+}
+
+/// Connected endpoints in memory, for tests that should not bind sockets.
+pub mod mem {
+    use std::{collections::VecDeque, io::{Error, ErrorKind}, sync::{Arc, Condvar, Mutex, PoisonError}, time::Duration};
+
+    use super::{Connector, Transport};
+    use crate::config::Config;
+
+    #[derive(Default)]
+    struct Pipe {
+        bytes: Mutex<VecDeque<u8>>,
+        ready: Condvar,
+    }
+
+    /// One end of a `pair`. Clones share the end, and connecting through it hands out a clone, so the pair
+    /// outlasts the driver's reconnects like a serial line would.
+    #[derive(Clone)]
+    pub struct Endpoint {
+        rx: Arc<Pipe>,
+        tx: Arc<Pipe>,
+    }
+
+    pub fn pair() -> (Endpoint, Endpoint) {
+        let (a, b) = (Arc::new(Pipe::default()), Arc::new(Pipe::default()));
+        (Endpoint {rx: a.clone(), tx: b.clone()}, Endpoint {rx: b, tx: a})
+    }
+
+    impl Endpoint {
+        /// Bytes written by the other end and not read yet.
+        pub fn pending(&self) -> usize {
+            self.rx.bytes.lock().unwrap_or_else(PoisonError::into_inner).len()
+        }
+    }
+
+    impl Transport for Endpoint {
+        fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+            self.tx.bytes.lock().unwrap_or_else(PoisonError::into_inner).extend(buf);
+            self.tx.ready.notify_all();
+            Ok(())
+        }
+
+        fn read(&mut self, buf: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+            let bytes = self.rx.bytes.lock().unwrap_or_else(PoisonError::into_inner);
+            let (mut bytes, _) = self.rx.ready.wait_timeout_while(bytes, timeout, |b| b.is_empty()).unwrap_or_else(PoisonError::into_inner);
+            if bytes.is_empty() {
+                return Err(Error::new(ErrorKind::TimedOut, "nothing to read"));
+            }
+            let n = buf.len().min(bytes.len());
+            for (dst, src) in buf.iter_mut().zip(bytes.drain(..n)) {
+                *dst = src;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Connector for Endpoint {
+        fn connect(&mut self, _config: &Config) -> Result<Box<dyn Transport>, Error> {
+            Ok(Box::new(self.clone()))
+        }
+    }
+}
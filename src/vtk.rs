@@ -1,38 +1,18 @@
 use core::str;
-use std::{io::{Error, Write, Read}, net::TcpStream, collections::HashMap, time::{Duration, Instant}};
-
-use ignore_result::Ignore;
-use num_derive::FromPrimitive;
-
-const VTK_WRITE_TIMEOUT: Duration = Duration::from_millis(250);
-
-#[derive(PartialEq, Hash, Eq, FromPrimitive, Debug, Clone, Copy)]
-#[repr(u8)]
-pub enum TlvKey {
-    MsgName = 0x01,
-    OperationNum = 0x03,
-    AmountInMinorCurrencyUnit = 0x04,
-    KeepaliveIntervalInSecs = 0x05,
-    OperationTimeoutInSecs = 0x06,
-    EventName = 0x07,
-    EventNum = 0x08,
-    ProductId = 0x09,
-    QrCodeData = 0x0A,
-    TcpIpDestantion = 0x0B,
-    OutgoingByteCounter = 0x0C,
-    SimpleDataBlock = 0x0D,
-    ConfirmableDataBlock = 0x0E,
-    ProductName = 0x0F,
-    PosManagementData = 0x10,
-    LocalTime = 0x11,
-    SysInfo = 0x12,
-    BankingReceipt = 0x13,
-    DisplayTimeInMs = 0x14,
-}
-
-#[derive(Clone)]
+use std::{borrow::Cow, fmt, io::{Error, ErrorKind}, collections::{BTreeMap, HashMap, VecDeque}, net::{IpAddr, SocketAddr}, sync::mpsc::{self, Receiver, Sender}, time::{Duration, Instant}};
+
+use smallvec::SmallVec;
+
+pub use crate::tag::{TlvKey, ValueType};
+
+use crate::{audit::{AuditEntry, AuditLog}, capability::Capabilities, clock::{Clock, SystemClock}, charset::Charset, codec::{push_len, split_tag_as, TooLong}, clock::parse_local_time, config::{AmountLimits, CircuitBreaker, ClockSkew, Compat, Config, QrLimit, RateLimit, RetryPolicy, Role}, connection::{Connection, Listener}, error::{ErrorReport, ErrorSink, NoErrorSink, VtkError}, event::Event, frame::{Anomaly, LengthEncoding, ParseMode}, journal::Journal, logging::{redact_wire, write_masked, FrameDump, Masked}, machine::{Action, Machine}, outbox::Outbox, receipt::ReceiptSink, record::unix_ms, rng::{Rng, SeededRng}, schema::Direction, session::{be_uint, deadline_after, remaining, Payment}, trace::{Recorder, TraceEntry}, transport::{Connector, Transport}};
+
+/// A tag value; values up to 16 bytes, which is nearly all of them, live inline without an allocation.
+pub type TlvValue = SmallVec<[u8; 16]>;
+
+#[derive(Clone, Default)]
 pub struct Tlv {
-    data: HashMap<TlvKey, Vec<u8>>,
+    data: HashMap<TlvKey, TlvValue>,
 }
 
 impl Tlv {
@@ -40,155 +20,1328 @@ impl Tlv {
         Self {data: HashMap::new()}
     }
 
-    fn deser_one(raw: &Vec<u8>, begin: usize) -> Option<(u8, Vec<u8>, usize)> {
-        if raw.len() - begin < 2 {return None;}
-        let k = raw[begin];
-        let len = raw[begin+1] as usize;
-        if (begin + len + 2) > raw.len() {return None;}
-        let v = raw[begin+2..begin+len+2].to_vec();
-        Some((k, v, len + 2))
+    /// Lenient parse that drops whatever it cannot make sense of.
+    pub fn deserialize(raw: &[u8]) -> Self {
+        Self::parse(raw, ParseMode::Lenient, &mut Vec::new()).unwrap_or_default()
     }
 
-    pub fn deserialize(raw: &Vec<u8>) -> Self {
+    /// Parses a TLV body; anomalies fail in strict mode and are pushed to `warnings` in lenient mode.
+    pub fn parse(raw: &[u8], mode: ParseMode, warnings: &mut Vec<Anomaly>) -> Result<Self, Error> {
+        Self::parse_as(raw, mode, LengthEncoding::Short, warnings)
+    }
+
+    pub fn parse_as(raw: &[u8], mode: ParseMode, lengths: LengthEncoding, warnings: &mut Vec<Anomaly>) -> Result<Self, Error> {
         let mut data = HashMap::new();
-        let mut i = 0;
-        loop {
-            match Self::deser_one(raw, i) {
-                Some((k, v, len)) => {
-                    match num::FromPrimitive::from_u8(k) {
-                        Some(k) => {data.insert(k, v);},
-                        None => (),
-                    }
-                    i += len;
+        let mut rest = raw;
+        while let Some(next) = split_tag_as(rest, lengths == LengthEncoding::Ber) {
+            let (tag, v, tail) = match next {
+                Ok(next) => next,
+                Err(broken) => {
+                    mode.tolerate(warnings, broken.into())?;
+                    break;
                 },
-                None => break,
+            };
+            rest = tail;
+            let key = TlvKey::from_u8(tag);
+            if !key.is_known() {
+                warnings.push(Anomaly::UnknownTag(tag));
+            }
+            if data.insert(key, TlvValue::from_slice(v)).is_some() {
+                mode.tolerate(warnings, Anomaly::DuplicateTag(tag))?;
             }
         }
-        Self {data: data}
+        Ok(Self {data})
+    }
+
+    pub fn serialize(self) -> Result<Vec<u8>, Error> {
+        self.serialize_as(LengthEncoding::Short)
     }
 
-    pub fn serialize(self) -> Vec<u8> {
-        let mut output = Vec::new();
+    /// Fails with `InvalidInput` on a value longer than `lengths` can declare.
+    pub fn serialize_as(self, lengths: LengthEncoding) -> Result<Vec<u8>, Error> {
+        let mut output = Vec::with_capacity(self.data.values().map(|v| v.len() + 2).sum());
         for (k, v) in self.data {
-            output.push(k as u8);
-            let len = v.len() as u8;
-            output.push(len);
-            for b in v {
-                output.push(b);
-            }
+            output.push(k.as_u8());
+            push_len(&mut output, v.len(), lengths == LengthEncoding::Ber).map_err(|TooLong(n)| {
+                Error::new(ErrorKind::InvalidInput, format!("{:?} value of {} bytes is too long for {:?} lengths", k, n, lengths))
+            })?;
+            output.extend_from_slice(&v);
         }
-        output
+        Ok(output)
     }
 
-    pub fn data<'a>(&'a self) -> &'a HashMap<TlvKey, Vec<u8>> {
+    pub fn data(&self) -> &HashMap<TlvKey, TlvValue> {
         &self.data
     }
 
-    pub fn get_bin(&self, key: TlvKey) -> Option<&Vec<u8>> {
-        self.data.get(&key)
+    pub fn get_bin(&self, key: TlvKey) -> Option<&[u8]> {
+        self.data.get(&key).map(|v| &v[..])
+    }
+
+    pub fn get_str(&self, key: TlvKey) -> Option<&str> {
+        self.data.get(&key).and_then(|v| str::from_utf8(v).ok())
+    }
+
+    /// The value decoded from `charset`, with invalid sequences replaced.
+    pub fn get_text(&self, key: TlvKey, charset: Charset) -> Option<Cow<'_, str>> {
+        self.data.get(&key).map(|v| charset.decode(v))
+    }
+
+    pub fn set_text(&mut self, key: TlvKey, text: &str, charset: Charset) {
+        self.data.insert(key, TlvValue::from_vec(charset.encode(text)));
     }
 
     pub fn set_bin(&mut self, key: TlvKey, data: &[u8]) {
-        self.data.insert(key, data.to_vec());
+        self.data.insert(key, TlvValue::from_slice(data));
     }
 
     pub fn set_str(&mut self, key: TlvKey, data: &str) {
-        self.data.insert(key, data.as_bytes().to_vec());
+        self.data.insert(key, TlvValue::from_slice(data.as_bytes()));
+    }
+
+    /// Numbers go out big-endian in as few bytes as they need, zero as a single `00`.
+    pub fn set_u8(&mut self, key: TlvKey, n: u8) {
+        self.set_u64(key, n.into());
+    }
+
+    pub fn set_u16(&mut self, key: TlvKey, n: u16) {
+        self.set_u64(key, n.into());
+    }
+
+    pub fn set_u32(&mut self, key: TlvKey, n: u32) {
+        self.set_u64(key, n.into());
+    }
+
+    pub fn set_u64(&mut self, key: TlvKey, n: u64) {
+        self.set_bin(key, &be_bytes(n));
+    }
+
+    /// A big-endian number of one to eight bytes, whatever length the terminal chose.
+    pub fn get_uint(&self, key: TlvKey) -> Option<u64> {
+        self.get_bin(key).and_then(be_uint)
+    }
+
+    /// Sets a `...InSecs` or `...InMs` tag in its own unit, truncating; other keys carry no duration.
+    pub fn set_duration(&mut self, key: TlvKey, d: Duration) -> Result<(), Error> {
+        let value = match key.value_type() {
+            ValueType::Seconds => d.as_secs(),
+            ValueType::Millis if key == TlvKey::DisplayTimeInMs => return self.set_display_time(d),
+            ValueType::Millis => u64::try_from(d.as_millis()).unwrap_or(u64::MAX),
+            _ => return Err(Error::new(ErrorKind::InvalidInput, format!("{:?} is not a duration", key))),
+        };
+        self.set_u64(key, value);
+        Ok(())
+    }
+
+    /// DisplayTimeInMs, rounded up to a whole millisecond; zero and anything past `MAX_DISPLAY_TIME` are refused.
+    pub fn set_display_time(&mut self, d: Duration) -> Result<(), Error> {
+        if d.is_zero() || d > MAX_DISPLAY_TIME {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("display time {:?} is outside 1ms..={:?}", d, MAX_DISPLAY_TIME)));
+        }
+        let ms = d.as_millis() as u64 + u64::from(!d.subsec_nanos().is_multiple_of(1_000_000));
+        self.set_u64(TlvKey::DisplayTimeInMs, ms);
+        Ok(())
+    }
+
+    pub fn get_duration(&self, key: TlvKey) -> Option<Duration> {
+        let value = self.get_uint(key)?;
+        match key.value_type() {
+            ValueType::Seconds => Some(Duration::from_secs(value)),
+            ValueType::Millis => Some(Duration::from_millis(value)),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&mut self, key: TlvKey) -> Option<Vec<u8>> {
+        self.data.remove(&key).map(TlvValue::into_vec)
+    }
+
+    /// What it takes to turn `self` into `other`, by tag number; empty when they carry the same tags and values.
+    pub fn diff(&self, other: &Tlv) -> Vec<TagDiff> {
+        let mut keys: Vec<TlvKey> = self.data.keys().chain(other.data.keys()).copied().collect();
+        keys.sort_by_key(|k| k.as_u8());
+        keys.dedup();
+        keys.into_iter().filter_map(|key| match (self.get_bin(key), other.get_bin(key)) {
+            (None, Some(to)) => Some(TagDiff::Added(key, to.to_vec())),
+            (Some(from), None) => Some(TagDiff::Removed(key, from.to_vec())),
+            (Some(from), Some(to)) if from != to => Some(TagDiff::Changed {key, from: from.to_vec(), to: to.to_vec()}),
+            _ => None,
+        }).collect()
+    }
+}
+
+#[cfg(feature = "json")]
+impl Tlv {
+    /// One object per tag, by tag number: `tag`, `name`, `category`, spaced upper-case `hex` and `value`, which is
+    /// a number for numeric tags, text for printable UTF-8 and `null` otherwise.
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::{json, Value};
+
+        let mut keys: Vec<&TlvKey> = self.data.keys().collect();
+        keys.sort_by_key(|k| k.as_u8());
+        keys.into_iter().map(|key| {
+            let v = self.data[key].as_slice();
+            let value = match key.value_type() {
+                t if t.is_numeric() => be_uint(v).map_or(Value::Null, Value::from),
+                _ => str::from_utf8(v).ok().filter(|s| !s.chars().any(char::is_control)).map_or(Value::Null, Value::from),
+            };
+            let hex = v.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
+            json!({"tag": key.as_u8(), "name": format!("{:?}", key), "category": key.category().map(|c| c.name()), "hex": hex, "value": value})
+        }).collect()
+    }
+}
+
+/// Tags in order, with `logging::is_sensitive` values masked as in the debug log.
+impl fmt::Debug for Tlv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut keys: Vec<&TlvKey> = self.data.keys().collect();
+        keys.sort_by_key(|k| k.as_u8());
+        f.debug_map().entries(keys.into_iter().map(|k| (k, Masked(*k, &self.data[k])))).finish()
+    }
+}
+
+/// One entry of `Tlv::diff`; displayed as `+ Key value`, `- Key value` or `~ Key old -> new`, with sensitive values
+/// masked, also in `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum TagDiff {
+    Added(TlvKey, Vec<u8>),
+    Removed(TlvKey, Vec<u8>),
+    Changed {key: TlvKey, from: Vec<u8>, to: Vec<u8>},
+}
+
+impl fmt::Display for TagDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagDiff::Added(key, v) => {
+                write!(f, "+ {:?} ", key)?;
+                write_masked(f, *key, v)
+            },
+            TagDiff::Removed(key, v) => {
+                write!(f, "- {:?} ", key)?;
+                write_masked(f, *key, v)
+            },
+            TagDiff::Changed {key, from, to} => {
+                write!(f, "~ {:?} ", key)?;
+                write_masked(f, *key, from)?;
+                write!(f, " -> ")?;
+                write_masked(f, *key, to)
+            },
+        }
+    }
+}
+
+impl fmt::Debug for TagDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// The longest DisplayTimeInMs the terminal takes: it reads the tag as a 32-bit count.
+pub const MAX_DISPLAY_TIME: Duration = Duration::from_millis(u32::MAX as u64);
+
+pub(crate) fn be_bytes(n: u64) -> Vec<u8> {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    bytes[skip..].to_vec()
+}
+
+const MAX_WARNINGS: usize = 64;
+
+pub struct VtkBuilder {
+    config: Config,
+    journal: Option<Box<dyn Journal>>,
+    receipts: Option<Box<dyn ReceiptSink>>,
+    outbox: Option<Box<dyn Outbox>>,
+    recorder: Option<Box<dyn Recorder>>,
+    audit: Option<Box<dyn AuditLog>>,
+    connector: Option<Box<dyn Connector>>,
+    clock: Option<Box<dyn Clock>>,
+    rng: Option<Box<dyn Rng>>,
+    errors: Option<Box<dyn ErrorSink>>,
+}
+
+impl VtkBuilder {
+    /// Connects from this local address; see `Config::bind_addr`.
+    pub fn bind_addr(mut self, ip: IpAddr) -> Self {
+        self.config.bind_addr = Some(ip);
+        self
+    }
+
+    /// Connects through this network interface; see `Config::bind_device`.
+    pub fn bind_device(mut self, device: &str) -> Self {
+        self.config.bind_device = Some(String::from(device));
+        self
+    }
+
+    /// Reaches the terminal through a SOCKS5 or HTTP CONNECT proxy; see `Config::proxy`.
+    pub fn proxy(mut self, url: &str) -> Self {
+        self.config.proxy = Some(String::from(url));
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    /// Allows `burst` frames back to back, then one every `interval`.
+    pub fn rate_limit(mut self, interval: Duration, burst: u32) -> Self {
+        self.config.rate_limit = Some(RateLimit {interval, burst});
+        self
+    }
+
+    /// See `Vtk::set_watchdog`.
+    pub fn watchdog(mut self, timeout: Duration) -> Self {
+        self.config.watchdog = Some(timeout);
+        self
     }
+
+    pub fn keepalive(mut self, interval: Duration) -> Self {
+        self.config.keepalive = Some(interval);
+        self
+    }
+
+    pub fn nodelay(mut self, on: bool) -> Self {
+        self.config.nodelay = on;
+        self
+    }
+
+    /// Drops events repeated within `window`; see `Config::event_dedup`.
+    pub fn event_dedup(mut self, window: Duration) -> Self {
+        self.config.event_dedup = Some(window);
+        self
+    }
+
+    /// OS-level keepalive, so half-open connections are noticed; `interval` is ignored where the OS has no such knob.
+    pub fn tcp_keepalive(mut self, idle: Duration, interval: Option<Duration>) -> Self {
+        self.config.tcp_keepalive = Some(idle);
+        self.config.tcp_keepalive_interval = interval;
+        self
+    }
+
+    /// See `Config::stale_after`.
+    pub fn stale_after(mut self, unused: Duration) -> Self {
+        self.config.stale_after = Some(unused);
+        self
+    }
+
+    /// See `Config::circuit_breaker`.
+    pub fn circuit_breaker(mut self, failures: u32, cool_down: Duration) -> Self {
+        self.config.circuit_breaker = Some(CircuitBreaker {failures, cool_down});
+        self
+    }
+
+    /// See `Config::queue_sends`.
+    pub fn queue_sends(mut self, on: bool) -> Self {
+        self.config.queue_sends = on;
+        self
+    }
+
+    /// See `Config::disable_on_drop`.
+    pub fn disable_on_drop(mut self, on: bool) -> Self {
+        self.config.disable_on_drop = on;
+        self
+    }
+
+    pub fn amount_limits(mut self, limits: AmountLimits) -> Self {
+        self.config.amount_limits = limits;
+        self
+    }
+
+    /// See `Config::clock_skew`.
+    pub fn clock_skew(mut self, threshold: Duration, utc_offset_mins: i32) -> Self {
+        self.config.clock_skew = Some(ClockSkew {threshold, utc_offset_mins});
+        self
+    }
+
+    /// See `Config::qr_limit`.
+    pub fn qr_limit(mut self, max_len: usize, fallback: Option<&str>) -> Self {
+        self.config.qr_limit = Some(QrLimit {max_len, fallback: fallback.map(String::from)});
+        self
+    }
+
+    /// See `Config::qr_template`.
+    pub fn qr_template(mut self, template: &str) -> Self {
+        self.config.qr_template = Some(String::from(template));
+        self
+    }
+
+    /// Logs sensitive tags in clear; only for controlled environments.
+    pub fn unredacted_logging(mut self, on: bool) -> Self {
+        self.config.redact_logs = !on;
+        self
+    }
+
+    /// Lets `VTK_*` environment variables override what was set so far.
+    pub fn env_overrides(mut self) -> Result<Self, Error> {
+        self.config.apply_env()?;
+        Ok(self)
+    }
+
+    /// Records every payment request until it is finalized, aborted or declined.
+    pub fn journal<J: Journal + 'static>(mut self, journal: J) -> Self {
+        self.journal = Some(Box::new(journal));
+        self
+    }
+
+    /// Hands the bank slip of every sale settled with `finalize_sale` to `sink`.
+    pub fn receipt_sink<R: ReceiptSink + 'static>(mut self, sink: R) -> Self {
+        self.receipts = Some(Box::new(sink));
+        self
+    }
+
+    /// Queues the outcome of every sale in `outbox` until a downstream system acknowledges it.
+    pub fn outbox<O: Outbox + 'static>(mut self, outbox: O) -> Self {
+        self.outbox = Some(Box::new(outbox));
+        self
+    }
+
+    /// Hands every frame sent or read to `recorder`, e.g. a `trace::JsonlRecorder`.
+    pub fn recorder<R: Recorder + 'static>(mut self, recorder: R) -> Self {
+        self.recorder = Some(Box::new(recorder));
+        self
+    }
+
+    /// Takes time from `clock`, e.g. a `clock::ManualClock` in tests, instead of the OS.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    /// Draws `RetryPolicy::jitter` from `rng`, e.g. a `rng::SeededRng` with a fixed seed in tests.
+    pub fn rng<R: Rng + 'static>(mut self, rng: R) -> Self {
+        self.rng = Some(Box::new(rng));
+        self
+    }
+
+    /// Reports every failed send or read to `sink`; see `ErrorSink`.
+    pub fn error_sink<S: ErrorSink + 'static>(mut self, sink: S) -> Self {
+        self.errors = Some(Box::new(sink));
+        self
+    }
+
+    /// Wire quirks for the terminal's firmware; see `Config::compat`.
+    pub fn compat(mut self, compat: Compat) -> Self {
+        self.config.compat = compat;
+        self
+    }
+
+    /// Whether the driver connects to the terminal or waits for it; see `Config::role`.
+    pub fn role(mut self, role: Role) -> Self {
+        self.config.role = role;
+        self
+    }
+
+    /// Connects through `connector` instead of TCP, e.g. a `transport::mem` endpoint in tests.
+    pub fn connector<C: Connector + 'static>(mut self, connector: C) -> Self {
+        self.connector = Some(Box::new(connector));
+        self
+    }
+
+    /// Writes every exchange and sale outcome to `log`, e.g. an `audit::FileAuditLog`.
+    pub fn audit_log<A: AuditLog + 'static>(mut self, log: A) -> Self {
+        self.audit = Some(Box::new(log));
+        self
+    }
+
+    pub fn build(self) -> Result<Vtk, Error> {
+        let mut vtk = Vtk::from_config(self.config);
+        // Keep operation numbers the journal or the outbox has seen, resolved or not, from being reused.
+        let mut last = self.outbox.as_ref().and_then(|o| o.last_operation()).unwrap_or(0);
+        if let Some(journal) = &self.journal {
+            let unresolved = journal.list_unresolved()?.iter().map(|e| e.operation).max();
+            last = last.max(unresolved.max(journal.last_operation()).unwrap_or(0));
+        }
+        if last > 0 {
+            vtk.next_operation = last.checked_add(1).unwrap_or(1);
+        }
+        vtk.journal = self.journal;
+        vtk.receipts = self.receipts;
+        vtk.outbox = self.outbox;
+        vtk.recorder = self.recorder;
+        vtk.audit = self.audit;
+        vtk.connector = self.connector;
+        if let Some(errors) = self.errors {
+            vtk.errors = errors;
+        }
+        if let Some(clock) = self.clock {
+            vtk.last_exchange = clock.now();
+            vtk.clock = clock;
+        }
+        if let Some(rng) = self.rng {
+            vtk.rng = rng;
+        }
+        Ok(vtk)
+    }
+}
+
+impl From<Config> for VtkBuilder {
+    fn from(config: Config) -> Self {
+        Self {config, journal: None, receipts: None, outbox: None, recorder: None, audit: None, connector: None, clock: None, rng: None, errors: None}
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub errors: u64,
+}
+
+/// Raw bytes of the latest frames on the wire, kept even when decoding fails. Not redacted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LastFrames {
+    pub sent: Vec<u8>,
+    /// Everything the last read returned, which may be more or less than one frame, or nothing after a timeout.
+    pub received: Vec<u8>,
+}
+
+impl LastFrames {
+    /// Both frames with the values of sensitive tags, and anything that does not parse, overwritten by `*`.
+    pub(crate) fn redacted(&self, lengths: LengthEncoding) -> Self {
+        let ber = lengths == LengthEncoding::Ber;
+        Self {sent: redact_wire(&self.sent, ber), received: redact_wire(&self.received, ber)}
+    }
+}
+
+/// Decides whether a service call, by action name (`disable`), may go ahead with the operator's credential, e.g. a
+/// PIN; for front ends such as the bridge to check before calling `Vtk::disable` and friends.
+pub type Authorize = Box<dyn Fn(&str, Option<&str>) -> bool + Send + Sync>;
+
+/// An `Authorize` that lets every action through with `token`, compared in constant time over all of `token`.
+pub fn operator_token(token: String) -> Authorize {
+    Box::new(move |_, given| given.is_some_and(|given| {
+        let given = given.as_bytes();
+        let diff = token.bytes().enumerate()
+            .fold(u8::from(given.len() != token.len()), |diff, (i, b)| diff | (b ^ given.get(i).copied().unwrap_or(0)));
+        diff == 0
+    }))
 }
 
 pub struct Vtk {
-    ip: String,
-    port: u16,
-    tcp: Option<TcpStream>,
+    config: Config,
+    conn: Option<Box<dyn Transport>>,
+    /// Opens `conn`; TCP to `config` when unset.
+    connector: Option<Box<dyn Connector>>,
+    /// Bound on the first connect as `Role::Server`.
+    listener: Option<Listener>,
+    /// Last time `conn` was opened or carried bytes, for `Config::stale_after`.
+    conn_used: Instant,
+    /// Keepalive interval the terminal last agreed to in an IDL answer.
+    heartbeat: Option<Duration>,
+    /// Keepalives stopped arriving on `conn`.
+    suspect: bool,
+    last_exchange: Instant,
+    degraded: bool,
+    subscribers: Vec<Sender<Event>>,
+    counters: Counters,
+    next_operation: u32,
+    pub(crate) journal: Option<Box<dyn Journal>>,
+    pub(crate) receipts: Option<Box<dyn ReceiptSink>>,
+    pub(crate) outbox: Option<Box<dyn Outbox>>,
+    /// Payments awaiting FIN or ABR, for their outbox records; only kept with an outbox.
+    pub(crate) open_sales: BTreeMap<u32, Payment>,
+    /// The approved `preauthorize` awaiting `capture` or `void`.
+    pub(crate) hold: Option<Payment>,
+    /// The `sell_once` key for the journal entry of the VRP about to go out.
+    pub(crate) sale_key: Option<String>,
+    recorder: Option<Box<dyn Recorder>>,
+    audit: Option<Box<dyn AuditLog>>,
+    errors: Box<dyn ErrorSink>,
+    warnings: Vec<Anomaly>,
+    /// Theoretical arrival time of the next frame for the rate limiter (GCRA).
+    send_tat: Option<Instant>,
+    /// When the latest frame started going out, after any rate-limit wait and connect.
+    sent_at: Instant,
+    round_trip: Option<Duration>,
+    /// Failed exchanges in a row, for `Config::circuit_breaker`.
+    failures: u32,
+    /// Set while the circuit is open; after that the next exchange is a trial.
+    open_until: Option<Instant>,
+    /// Framing and the request whose answer has not been read yet, for this connection.
+    machine: Machine,
+    /// Tags of the request in flight that skip its schema; see `request_payment_with`.
+    pub(crate) passthrough: Vec<TlvKey>,
+    /// Frames decoded from an earlier read, e.g. two that arrived together.
+    inbound: VecDeque<Tlv>,
+    /// EventNum, OperationNum and arrival of the events seen within `Config::event_dedup`, oldest first.
+    seen_events: VecDeque<(u64, Option<u64>, Instant)>,
+    /// Answers read ahead by `Config::queue_sends`, for `receive`.
+    parked: VecDeque<Tlv>,
+    last_frames: LastFrames,
+    /// Nothing was sent since the last `shutdown`, so dropping has nothing left to do.
+    shut_down: bool,
+    /// What the last `idle` was asked to show, for `resume`; `None` for the default or rotating screen.
+    idle_screen: Option<Tlv>,
+    paused: bool,
+    rotation: Option<Rotation>,
+    /// Terminal LocalTime minus host time at the last answer carrying it.
+    clock_skew_ms: Option<i64>,
+    skewed: bool,
+    capabilities: Capabilities,
+    pub(crate) clock: Box<dyn Clock>,
+    rng: Box<dyn Rng>,
+}
+
+/// Idle screens taking turns, each for `interval`.
+struct Rotation {
+    screens: Vec<Tlv>,
+    interval: Duration,
+    since: Instant,
 }
 
 impl Vtk {
     pub fn new(ip: &str, port: u16) -> Result<Self, Error> {
-        let s = Self {
-            ip: String::from(ip),
-            port: port,
-            tcp: None,
-        };
-        Ok(s)
+        Self::builder(ip, port).build()
+    }
+
+    pub fn builder(ip: &str, port: u16) -> VtkBuilder {
+        VtkBuilder::from(Config::new(ip, port))
+    }
+
+    pub fn from_config(config: Config) -> Self {
+        let machine = Machine::new(config.parse_mode, config.compat.lengths);
+        Self {
+            config,
+            conn: None,
+            connector: None,
+            listener: None,
+            conn_used: Instant::now(),
+            last_exchange: Instant::now(),
+            clock: Box::new(SystemClock),
+            rng: Box::new(SeededRng::default()),
+            errors: Box::new(NoErrorSink),
+            degraded: false,
+            subscribers: Vec::new(),
+            counters: Counters::default(),
+            next_operation: 1,
+            journal: None,
+            receipts: None,
+            outbox: None,
+            open_sales: BTreeMap::new(),
+            hold: None,
+            sale_key: None,
+            recorder: None,
+            audit: None,
+            warnings: Vec::new(),
+            send_tat: None,
+            sent_at: Instant::now(),
+            round_trip: None,
+            heartbeat: None,
+            suspect: false,
+            failures: 0,
+            open_until: None,
+            machine,
+            passthrough: Vec::new(),
+            inbound: VecDeque::new(),
+            seen_events: VecDeque::new(),
+            parked: VecDeque::new(),
+            last_frames: LastFrames::default(),
+            shut_down: false,
+            idle_screen: None,
+            paused: false,
+            rotation: None,
+            clock_skew_ms: None,
+            skewed: false,
+            capabilities: Capabilities::default(),
+        }
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The address a `Role::Server` driver listens on, once it has started to.
+    pub fn listen_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().and_then(|l| l.local_addr().ok())
     }
 
     pub fn is_connected(&self) -> bool {
-        self.tcp.is_some()
+        self.conn.is_some()
+    }
+
+    /// Swaps in `config` between exchanges; the connection is only dropped when the terminal's address or the
+    /// route to it changed. Returns whether it was.
+    pub fn reconfigure(&mut self, config: Config) -> bool {
+        let route = |c: &Config| (c.host.clone(), c.port, c.role, c.bind_addr, c.bind_device.clone(), c.proxy.clone());
+        let moved = route(&config) != route(&self.config);
+        if moved {
+            self.listener = None;
+        }
+        if config.watchdog != self.config.watchdog {
+            self.last_exchange = self.now();
+        }
+        self.machine.mode = config.parse_mode;
+        self.machine.lengths = config.compat.lengths;
+        self.config = config;
+        if moved {
+            self.disconnect();
+        }
+        moved
+    }
+
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    /// Marks the driver degraded when no exchange succeeds within `timeout`, and recovers on the next one that does.
+    ///
+    /// Failed sends and reads check the watchdog, but nothing polls the terminal on its own: a machine that stops
+    /// calling the driver once degraded must call `check_watchdog` meanwhile, and try e.g. `idle` now and then, to
+    /// see it recover.
+    pub fn set_watchdog(&mut self, timeout: Option<Duration>) {
+        self.config.watchdog = timeout;
+        self.last_exchange = self.now();
+    }
+
+    pub fn journal(&self) -> Option<&dyn Journal> {
+        self.journal.as_deref()
+    }
+
+    /// For the consumer that delivers and acknowledges sale outcomes; see `Outbox::deliver`.
+    pub fn outbox(&mut self) -> Option<&mut (dyn Outbox + 'static)> {
+        self.outbox.as_deref_mut()
+    }
+
+    /// Anomalies seen so far, oldest first; only the latest 64 are kept. Each is also sent to subscribers.
+    pub fn warnings(&self) -> &[Anomaly] {
+        &self.warnings
+    }
+
+    /// From writing the request to decoding its answer, for the latest exchange that got one; connecting,
+    /// rate-limit waits and retries are not included.
+    pub fn last_round_trip(&self) -> Option<Duration> {
+        self.round_trip
+    }
+
+    pub fn last_frames(&self) -> &LastFrames {
+        &self.last_frames
+    }
+
+    /// Starts or stops recording frames; see `VtkBuilder::recorder`.
+    pub fn set_recorder(&mut self, recorder: Option<Box<dyn Recorder>>) {
+        self.recorder = recorder;
+    }
+
+    fn trace(&mut self, direction: Direction, raw: &[u8]) {
+        let Some(recorder) = self.recorder.as_mut() else {return;};
+        let entry = TraceEntry {at_ms: unix_ms(self.clock.system_now()), direction, raw: raw.to_vec()};
+        if let Err(e) = recorder.record(&entry) {
+            log::warn!("recorder: {}", e);
+        }
+    }
+
+    /// The exchange already happened, so a failing audit log is only logged.
+    pub(crate) fn audit(&mut self, entry: AuditEntry) {
+        let Some(audit) = self.audit.as_mut() else {return;};
+        if let Err(e) = audit.append(unix_ms(self.clock.system_now()), &entry) {
+            log::warn!("audit log: {}", e);
+        }
+    }
+
+    pub fn clear_warnings(&mut self) {
+        self.warnings.clear();
+    }
+
+    fn warn(&mut self, anomalies: Vec<Anomaly>) {
+        for anomaly in anomalies {
+            log::warn!("{}:{}: {}", self.config.host, self.config.port, anomaly);
+            if self.warnings.len() == MAX_WARNINGS {
+                self.warnings.remove(0);
+            }
+            self.warnings.push(anomaly.clone());
+            self.emit(Event::Anomaly(anomaly));
+        }
+    }
+
+    /// How far the terminal's clock was off at its last LocalTime, in milliseconds; positive when it is ahead.
+    /// Only measured with `Config::clock_skew` set.
+    pub fn clock_skew(&self) -> Option<i64> {
+        self.clock_skew_ms
+    }
+
+    /// What the terminal has reported about itself so far; see `probe`.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Asks the terminal for its status without changing what it shows: IDL with the current idle screen,
+    /// or DIS while paused. Both answers carry SysInfo.
+    pub fn probe(&mut self) -> Result<&Capabilities, Error> {
+        if self.paused {
+            self.disable()?;
+        } else {
+            self.idle(self.idle_screen.clone())?;
+        }
+        if self.config.clock_skew.is_some() && !self.capabilities.reports(TlvKey::LocalTime) {
+            log::warn!("terminal does not report LocalTime, clock skew goes unchecked");
+        }
+        Ok(&self.capabilities)
+    }
+
+    /// Fails with `ErrorKind::Unsupported` when the terminal's firmware is older than `min`, probing first if its
+    /// version is not known yet; a terminal that reports no version passes.
+    pub fn require_firmware(&mut self, min: &[u32]) -> Result<(), Error> {
+        if self.capabilities.firmware.is_none() {
+            self.probe()?;
+        }
+        match self.capabilities.firmware_at_least(min) {
+            Some(false) => Err(Error::new(ErrorKind::Unsupported, format!("terminal firmware {:?} is older than {:?}",
+                self.capabilities.sys_info.as_deref().unwrap_or_default(), min))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Emits `Event::ClockSkew` once per excursion past the threshold.
+    fn check_clock(&mut self, tlv: &Tlv) {
+        let Some(ClockSkew {threshold, utc_offset_mins}) = self.config.clock_skew else {return;};
+        let Some(local) = tlv.get_bin(TlvKey::LocalTime).and_then(parse_local_time) else {return;};
+        let skew_ms = local - utc_offset_mins as i64 * 60_000 - unix_ms(self.clock.system_now()) as i64;
+        self.clock_skew_ms = Some(skew_ms);
+        let skewed = skew_ms.unsigned_abs() > threshold.as_millis() as u64;
+        if skewed && !self.skewed {
+            log::warn!("terminal clock is {} ms off the host clock", skew_ms);
+            self.emit(Event::ClockSkew {skew_ms});
+        }
+        self.skewed = skewed;
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// How long calls keep failing with `VtkError::CircuitOpen`; `None` while the circuit is closed or a trial is due.
+    pub fn circuit_open(&self) -> Option<Duration> {
+        self.open_until.and_then(|until| until.checked_duration_since(self.now())).filter(|d| !d.is_zero())
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Call periodically while not exchanging, so a silent terminal is noticed; also runs `check_heartbeat`.
+    pub fn check_watchdog(&mut self) {
+        self.check_heartbeat();
+        let Some(timeout) = self.config.watchdog else {return;};
+        let silent_for = self.now().saturating_duration_since(self.last_exchange);
+        if !self.degraded && silent_for >= timeout {
+            self.degraded = true;
+            self.emit(Event::Degraded {silent_for});
+        }
+    }
+
+    /// Marks the connection suspect and emits `Event::TerminalSilent` once nothing has arrived on it for 1.5 times
+    /// the keepalive interval agreed in the last IDL; the next VRP, FIN or ABR then goes over a fresh connection.
+    pub fn check_heartbeat(&mut self) {
+        let Some(interval) = self.heartbeat else {return;};
+        if self.conn.is_none() || self.suspect {return;}
+        let silent_for = self.now().saturating_duration_since(self.conn_used);
+        if silent_for >= interval * 3 / 2 {
+            log::warn!("{}: no keepalive for {:?}, expected every {:?}", self.config.host, silent_for, interval);
+            self.suspect = true;
+            self.emit(Event::TerminalSilent {interval, silent_for});
+        }
+    }
+
+    /// Keepalives stopped arriving on the open connection; see `check_heartbeat`.
+    pub fn is_suspect(&self) -> bool {
+        self.suspect
+    }
+
+    fn exchange_ok(&mut self) {
+        self.last_exchange = self.now();
+        if self.degraded {
+            self.degraded = false;
+            self.emit(Event::Recovered);
+        }
+    }
+
+    pub(crate) fn emit(&mut self, event: Event) {
+        self.subscribers.retain(|s| s.send(event.clone()).is_ok());
     }
 
     pub fn connect(&mut self) -> Result<(), Error> {
-        if self.tcp.is_none() {
-            self.tcp = Some(TcpStream::connect(format!("{}:{}", self.ip, self.port))?);
-            self.tcp.as_mut().unwrap().set_write_timeout(Some(VTK_WRITE_TIMEOUT))?;
+        self.transport().map(|_| ())
+    }
+
+    /// The open transport, connecting first if there is none; the only way `send` and `read_tlv` reach it.
+    fn transport(&mut self) -> Result<&mut dyn Transport, Error> {
+        let conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => {
+                let conn: Box<dyn Transport> = match (self.connector.as_mut(), self.config.role) {
+                    (Some(connector), _) => connector.connect(&self.config)?,
+                    (None, Role::Client) => Box::new(Connection::open(&self.config)?),
+                    (None, Role::Server) => {
+                        let listener = match self.listener.take() {
+                            Some(listener) => listener,
+                            None => Listener::bind(&self.config)?,
+                        };
+                        Box::new(self.listener.insert(listener).accept(&self.config, self.clock.as_ref())?)
+                    },
+                };
+                self.conn_used = self.now();
+                conn
+            },
+        };
+        Ok(self.conn.insert(conn).as_mut())
+    }
+
+    /// Drops a connection the terminal has closed, e.g. by rebooting, or one unused past `Config::stale_after`,
+    /// so a payment-critical frame is not written into a socket nobody reads.
+    fn revalidate(&mut self) {
+        let aged = self.config.stale_after.is_some_and(|d| self.now().saturating_duration_since(self.conn_used) >= d);
+        let suspect = self.suspect;
+        let Some(conn) = self.conn.as_mut() else {return;};
+        if aged || suspect || conn.is_stale() {
+            log::info!("reopening the {} connection before sending", if aged {"idle"} else if suspect {"silent"} else {"closed"});
+            self.disconnect();
         }
-        Ok(())
     }
 
     pub fn disconnect(&mut self) {
-        match self.tcp.take() {
-            Some(tcp) => {
-                tcp.shutdown(std::net::Shutdown::Both).ignore();
-            },
-            None => ()
+        self.machine.reset();
+        self.inbound.clear();
+        self.suspect = false;
+        if let Some(mut conn) = self.conn.take() {
+            conn.close();
         }
     }
 
-    pub fn idle(&mut self, add: Option<Tlv>) -> Result<(), Error> {
+    pub fn idle(&mut self, add: Option<Tlv>) -> Result<Tlv, Error> {
+        self.idle_until(add, None)
+    }
+
+    pub(crate) fn idle_until(&mut self, add: Option<Tlv>, deadline: Option<Instant>) -> Result<Tlv, Error> {
         self.disconnect();
-        let tlv = match add {
-            Some(tlv) => tlv,
-            None => Tlv::new(),
-        };
-        self.send("IDL", tlv)?;
-        _ = self.receive(2000)?;
+        self.idle_screen.clone_from(&add);
+        let mut tlv = add.or_else(|| self.rotation.as_ref().map(|r| r.current(self.now()))).unwrap_or_default();
+        if let Some(keepalive) = self.config.keepalive.or(self.config.compat.idl_keepalive.then_some(Duration::ZERO)) {
+            tlv.set_duration(TlvKey::KeepaliveIntervalInSecs, keepalive)?;
+        }
+        let resp = self.exchange_until("IDL", tlv, self.config.read_timeout, deadline)?;
+        self.heartbeat = resp.get_duration(TlvKey::KeepaliveIntervalInSecs).or(self.config.keepalive).filter(|d| !d.is_zero());
         self.disconnect();
-        Ok(())
+        self.paused = false;
+        Ok(resp)
+    }
+
+    /// Stops card acceptance until `resume`, e.g. while the machine is restocked.
+    pub fn pause(&mut self) -> Result<Tlv, Error> {
+        let resp = self.disable()?;
+        self.paused = true;
+        Ok(resp)
+    }
+
+    /// Returns to the idle screen shown before `pause`, QR code and all.
+    pub fn resume(&mut self) -> Result<Tlv, Error> {
+        let screen = self.idle_screen.clone();
+        self.idle(screen)
     }
 
-    pub fn disable(&mut self) -> Result<(), Error> {
+    /// Makes `idle(None)` show `screens` in turn, each for `interval`, starting now; an empty list stops the rotation.
+    ///
+    /// The screen changes with the next IDL after its turn comes, so the keepalive loop that already calls `idle`
+    /// drives the rotation; keep its period well under `interval`.
+    pub fn set_idle_rotation(&mut self, screens: Vec<Tlv>, interval: Duration) {
+        self.rotation = (!screens.is_empty()).then(|| Rotation {screens, interval, since: self.now()});
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn disable(&mut self) -> Result<Tlv, Error> {
         self.disconnect();
-        self.send("DIS", Tlv::new())?;
-        _ = self.receive(2000)?;
-        Ok(())
+        self.exchange("DIS", Tlv::new(), self.config.read_timeout)
     }
 
-    pub fn show_qr(&mut self, qr: &str) -> Result<(), Error> {
+    /// Sends DIS so the terminal stops accepting cards, flushes the journal and closes the socket, all within `timeout`.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<(), Error> {
+        let deadline = deadline_after(self.now(), timeout);
+        self.disconnect();
+        let saved = (self.config.connect_timeout, self.config.write_timeout);
+        self.config.connect_timeout = saved.0.min(timeout);
+        self.config.write_timeout = saved.1.min(timeout);
+        let res = remaining(deadline, self.now()).and_then(|left| self.transact("DIS", Tlv::new(), left));
+        (self.config.connect_timeout, self.config.write_timeout) = saved;
+        let flushed = self.journal.as_mut().map_or(Ok(()), |j| j.flush());
+        self.disconnect();
+        self.shut_down = true;
+        res.and(flushed)
+    }
+
+    /// `qr`, or `Config::qr_limit`'s fallback, filled in by `fill`, when it is too long for the display.
+    pub(crate) fn fit_qr(&self, qr: String, fill: impl Fn(&str) -> String) -> Result<String, Error> {
+        let Some(QrLimit {max_len, fallback}) = &self.config.qr_limit else {return Ok(qr);};
+        if qr.len() <= *max_len {return Ok(qr);}
+        match fallback {
+            Some(fallback) => {
+                log::info!("QR payload of {} bytes exceeds {}, showing the fallback", qr.len(), max_len);
+                Ok(fill(fallback))
+            },
+            None => Err(Error::new(ErrorKind::InvalidInput, format!("QR payload of {} bytes exceeds the limit of {}", qr.len(), max_len))),
+        }
+    }
+
+    pub fn show_qr(&mut self, qr: &str) -> Result<Tlv, Error> {
         let mut tlv = Tlv::new();
-        tlv.set_str(TlvKey::QrCodeData, qr);
+        tlv.set_str(TlvKey::QrCodeData, &self.fit_qr(String::from(qr), str::to_owned)?);
         self.idle(Some(tlv))
     }
 
-    pub fn send(&mut self, msg_name: &str, mut tlv: Tlv) -> Result<(), Error> {
-        tlv.set_str(TlvKey::MsgName, msg_name);
-        let mut tlv = tlv.serialize();
-        let mut buf = Vec::new();
-        let len = (tlv.len() + 2) as u16;
-        let len_buf: [u8;2] = len.to_be_bytes();
-        buf.push(len_buf[0]);
-        buf.push(len_buf[1]);
-        buf.push(0x96);
-        buf.push(0xFB);
-        buf.append(&mut tlv);
-        self.connect()?;
-        self.tcp.as_mut().unwrap().write_all(&buf)
+    /// `show_qr` that asks the terminal to keep the code up for `display`; see `Tlv::set_display_time`.
+    pub fn show_qr_for(&mut self, qr: &str, display: Duration) -> Result<Tlv, Error> {
+        let mut tlv = Tlv::new();
+        tlv.set_str(TlvKey::QrCodeData, &self.fit_qr(String::from(qr), str::to_owned)?);
+        tlv.set_display_time(display)?;
+        self.idle(Some(tlv))
+    }
+
+    pub(crate) fn next_operation(&mut self) -> u32 {
+        let op = self.next_operation;
+        self.next_operation = op.checked_add(1).unwrap_or(1);
+        op
+    }
+
+    /// One send and one read, never retried; for frames that must not be repeated.
+    pub(crate) fn transact(&mut self, msg_name: &str, tlv: Tlv, timeout: Duration) -> Result<Tlv, Error> {
+        let audited = self.audit.is_some().then(|| {
+            let num = |key| tlv.get_uint(key);
+            (num(TlvKey::OperationNum).and_then(|n| u32::try_from(n).ok()), num(TlvKey::AmountInMinorCurrencyUnit))
+        });
+        let operation = tlv.get_uint(TlvKey::OperationNum);
+        let res = self.send(msg_name, tlv)
+            .map_err(|e| if matches!(VtkError::of(&e), Some(VtkError::Busy {..} | VtkError::CircuitOpen {..})) {e} else {VtkError::exchange(Some(msg_name), None, e)})
+            .and_then(|()| self.answer(msg_name, operation, timeout));
+        self.count_outcome(&res);
+        if res.is_ok() {
+            self.round_trip = Some(self.now().saturating_duration_since(self.sent_at));
+        } else if self.machine.awaiting() == Some(msg_name) && !self.config.queue_sends {
+            // A late answer would be taken for the next request's; a new connection cannot carry it.
+            self.disconnect();
+        }
+        if let Some((operation, amount)) = audited {
+            let answer = res.as_ref().map(|t| String::from(t.get_str(TlvKey::MsgName).unwrap_or_default())).map_err(Error::kind);
+            self.audit(AuditEntry::Exchange {request: String::from(msg_name), operation, amount, answer});
+        }
+        res
+    }
+
+    /// Opens the circuit on the `failures`th failed exchange in a row, or again when the trial after a cool-down
+    /// fails; refusals that never reached the terminal do not count.
+    fn count_outcome(&mut self, res: &Result<Tlv, Error>) {
+        let Some(CircuitBreaker {failures, cool_down}) = self.config.circuit_breaker else {return;};
+        match res {
+            Ok(_) => {
+                self.failures = 0;
+                if self.open_until.take().is_some() {
+                    log::info!("{}: circuit closed", self.config.host);
+                    self.emit(Event::CircuitClosed);
+                }
+            },
+            Err(e) if matches!(e.kind(), ErrorKind::InvalidInput | ErrorKind::ResourceBusy) => (),
+            Err(e) if matches!(VtkError::of(e), Some(VtkError::CircuitOpen {..})) => (),
+            Err(_) => {
+                self.failures += 1;
+                if self.failures >= failures || self.open_until.is_some() {
+                    log::warn!("{}: circuit open for {:?} after {} failures", self.config.host, cool_down, self.failures);
+                    self.open_until = Some(deadline_after(self.now(), cool_down));
+                    self.disconnect();
+                    self.emit(Event::CircuitOpen {failures: self.failures, cool_down});
+                }
+            },
+        }
+    }
+
+    fn read_error(&self, request: Option<&str>, e: Error) -> Error {
+        VtkError::exchange(request, Some(&self.last_frames.received), e)
+    }
+
+    /// Reads until the answer to `operation`, skipping answers left over from earlier operations.
+    fn answer(&mut self, request: &str, operation: Option<u64>, timeout: Duration) -> Result<Tlv, Error> {
+        let deadline = deadline_after(self.now(), timeout);
+        loop {
+            let tlv = remaining(deadline, self.now()).and_then(|left| self.read_frame(left))
+                .map_err(|e| self.read_error(Some(request), e))?;
+            match (operation, tlv.get_uint(TlvKey::OperationNum)) {
+                (Some(ours), Some(theirs)) if theirs != ours => self.warn(vec![Anomaly::StaleAnswer {operation: theirs}]),
+                _ => return Ok(tlv),
+            }
+        }
+    }
+
+    /// Sends `msg_name` and waits up to `timeout` for its answer, skipping answers to other operations.
+    ///
+    /// A failed attempt is repeated as `Config::retry` says, with the same frame and so the same operation
+    /// number; an answer under another MsgName is returned and reported as `Anomaly::UnexpectedMessage`.
+    pub fn exchange(&mut self, msg_name: &str, tlv: Tlv, timeout: Duration) -> Result<Tlv, Error> {
+        self.exchange_until(msg_name, tlv, timeout, None)
+    }
+
+    /// `exchange` with no attempt waiting past `deadline`, and no retry whose backoff would reach it.
+    fn exchange_until(&mut self, msg_name: &str, tlv: Tlv, timeout: Duration, deadline: Option<Instant>) -> Result<Tlv, Error> {
+        let mut attempt = 1;
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => remaining(deadline, self.now())?.min(timeout),
+                None => timeout,
+            };
+            let res = self.transact(msg_name, tlv.clone(), timeout);
+            if let Some(got) = res.as_ref().ok().and_then(|t| t.get_str(TlvKey::MsgName)).filter(|got| *got != msg_name) {
+                self.warn(vec![Anomaly::UnexpectedMessage {expected: String::from(msg_name), got: String::from(got)}]);
+            }
+            match res {
+                Err(_) if attempt < self.config.retry.attempts && self.open_until.is_none() => {
+                    let backoff = self.backoff();
+                    if deadline.is_some_and(|d| deadline_after(self.now(), backoff) >= d) {return res;}
+                    self.disconnect();
+                    self.clock.sleep(backoff);
+                    attempt += 1;
+                },
+                res => return res,
+            }
+        }
+    }
+
+    /// `RetryPolicy::backoff` plus a random part of `RetryPolicy::jitter`.
+    fn backoff(&mut self) -> Duration {
+        let RetryPolicy {backoff, jitter, ..} = self.config.retry;
+        if jitter.is_zero() {return backoff;}
+        let nanos = jitter.as_nanos() * u128::from(self.rng.next_u32()) / u128::from(u32::MAX);
+        backoff.saturating_add(Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX)))
     }
 
-    pub fn receive(&mut self, timeout_ms: u64) -> Result<Tlv, Error> {
-        let mut buf: [u8;512] = [0;512];
-        self.connect()?;
-        self.tcp.as_mut().unwrap().set_read_timeout(Some(Duration::from_millis(timeout_ms)))?;
-        let size = self.tcp.as_mut().unwrap().read(&mut buf)?;
-        if size < 9 {
-            return Err(Error::new(std::io::ErrorKind::Other, "too few bytes received"));
+    fn throttle(&mut self) {
+        let Some(RateLimit {interval, burst}) = self.config.rate_limit else {return;};
+        let now = self.now();
+        let tat = self.send_tat.unwrap_or(now).max(now);
+        let allowed_at = tat.checked_sub(interval * burst.saturating_sub(1)).unwrap_or(now);
+        if allowed_at > now {
+            log::debug!("rate limit: holding {} for {:?}", self.config.host, allowed_at - now);
+            self.clock.sleep(allowed_at - now);
         }
-        Ok(Tlv::deserialize(&buf[4..].to_vec()))
+        self.send_tat = Some(tat.max(self.now()) + interval);
+    }
+
+    pub fn send(&mut self, msg_name: &str, tlv: Tlv) -> Result<(), Error> {
+        if let Some(retry_in) = self.circuit_open() {
+            return Err(VtkError::circuit_open(retry_in));
+        }
+        if let Some(pending) = self.machine.awaiting().map(String::from) {
+            if !self.config.queue_sends {
+                return Err(VtkError::busy(&pending, msg_name));
+            }
+            self.read_ahead(&pending, msg_name);
+        }
+        self.shut_down = false;
+        self.throttle();
+        log::debug!("-> {}", FrameDump {msg_name, tlv: &tlv, redact: self.config.redact_logs});
+        let buf = self.machine.encode_allowing(msg_name, tlv, &self.passthrough)?;
+        self.last_frames.sent.clone_from(&buf);
+        if matches!(msg_name, "VRP" | "FIN" | "ABR") {
+            self.revalidate();
+        }
+        let res = self.connect().and_then(|()| {
+            self.sent_at = self.now();
+            self.transport()?.write_all(&buf)
+        });
+        match res {
+            Ok(_) => {
+                self.machine.sent(msg_name);
+                self.conn_used = self.now();
+                self.counters.frames_sent += 1;
+                self.trace(Direction::ToTerminal, &buf);
+            },
+            Err(ref e) => {
+                self.counters.errors += 1;
+                self.report_error(Some(msg_name), e);
+                self.check_watchdog();
+            },
+        }
+        res
+    }
+
+    /// Hands a failure to the `ErrorSink`; `sending` is the message that could not go out, if any.
+    fn report_error(&mut self, sending: Option<&str>, error: &Error) {
+        let request = sending.or(self.machine.awaiting());
+        if request.is_none() && matches!(error.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) {return;}
+        let last_frames = if self.config.redact_logs {self.last_frames.redacted(self.config.compat.lengths)} else {self.last_frames.clone()};
+        let report = ErrorReport {
+            host: &self.config.host,
+            port: self.config.port,
+            request,
+            code: VtkError::of(error).map(VtkError::code),
+            error,
+            last_frames: &last_frames,
+        };
+        self.errors.report(&report);
+    }
+
+    /// Waits for the answer to `pending` and parks it, so `request` does not go out while it is on the way.
+    fn read_ahead(&mut self, pending: &str, request: &str) {
+        match self.read_frame(self.config.read_timeout) {
+            Ok(tlv) => self.parked.push_back(tlv),
+            Err(e) => {
+                log::warn!("sending {} without the answer to {}: {}", request, pending, e);
+                self.machine.abandon();
+            },
+        }
+    }
+
+    /// The next frame, answers parked by `Config::queue_sends` first.
+    pub fn receive(&mut self, timeout: Duration) -> Result<Tlv, Error> {
+        if let Some(tlv) = self.parked.pop_front() {return Ok(tlv);}
+        self.read_frame(timeout).map_err(|e| self.read_error(None, e))
+    }
+
+    #[deprecated(note = "use `receive` with a `Duration`")]
+    pub fn receive_ms(&mut self, timeout_ms: u64) -> Result<Tlv, Error> {
+        self.receive(Duration::from_millis(timeout_ms))
+    }
+
+    /// Waits for a frame until `deadline`, so one budget can span several calls or devices.
+    pub fn receive_deadline(&mut self, deadline: Instant) -> Result<Tlv, Error> {
+        if let Some(tlv) = self.parked.pop_front() {return Ok(tlv);}
+        let timeout = remaining(deadline, self.now())?;
+        self.read_frame(timeout).map_err(|e| self.read_error(None, e))
+    }
+
+    /// The next frame within `timeout`, reading past replayed events.
+    fn read_frame(&mut self, timeout: Duration) -> Result<Tlv, Error> {
+        let deadline = deadline_after(self.now(), timeout);
+        let mut timeout = timeout;
+        loop {
+            let res = self.read_tlv(timeout);
+            match res {
+                Ok(_) => {
+                    self.counters.frames_received += 1;
+                    self.exchange_ok();
+                },
+                Err(ref e) => {
+                    self.counters.errors += 1;
+                    self.report_error(None, e);
+                    self.check_watchdog();
+                },
+            }
+            match res {
+                Ok(tlv) if self.replayed(&tlv) => timeout = remaining(deadline, self.now())?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Whether `tlv` repeats an event seen within `Config::event_dedup`; remembers it otherwise.
+    fn replayed(&mut self, tlv: &Tlv) -> bool {
+        let Some(window) = self.config.event_dedup else {return false;};
+        let Some(event) = tlv.get_uint(TlvKey::EventNum) else {return false;};
+        let operation = tlv.get_uint(TlvKey::OperationNum);
+        let now = self.now();
+        while self.seen_events.front().is_some_and(|(_, _, at)| now.saturating_duration_since(*at) >= window) {
+            self.seen_events.pop_front();
+        }
+        if self.seen_events.iter().any(|(e, op, _)| (*e, *op) == (event, operation)) {
+            self.warn(vec![Anomaly::ReplayedEvent {event, operation}]);
+            return true;
+        }
+        self.seen_events.push_back((event, operation, now));
+        false
+    }
+
+    /// The next frame, reading until one is complete; frames that arrived with it wait in `inbound`.
+    fn read_tlv(&mut self, timeout: Duration) -> Result<Tlv, Error> {
+        let deadline = deadline_after(self.now(), timeout);
+        let mut left = timeout;
+        while self.inbound.is_empty() {
+            let mut buf: [u8;512] = [0;512];
+            self.last_frames.received.clear();
+            let size = self.transport()?.read(&mut buf, left)?;
+            self.conn_used = self.now();
+            self.last_frames.received = buf[..size].to_vec();
+            if size == 0 {
+                return Err(Error::new(ErrorKind::ConnectionAborted, "the terminal closed the connection"));
+            }
+            self.suspect = false;
+            self.trace(Direction::FromTerminal, &buf[..size]);
+            let (mut warnings, mut rejected) = (Vec::new(), None);
+            for action in self.machine.handle_input(&buf[..size]) {
+                match action {
+                    Action::Received(tlv) => self.inbound.push_back(tlv),
+                    Action::Anomaly(anomaly) => warnings.push(anomaly),
+                    Action::Rejected(reason) => rejected = rejected.or(Some(reason)),
+                }
+            }
+            self.warn(warnings);
+            if let Some(reason) = rejected {
+                return Err(Error::new(ErrorKind::InvalidData, reason));
+            }
+            if self.inbound.is_empty() {
+                left = remaining(deadline, self.now())?;
+            }
+        }
+        let Some(tlv) = self.inbound.pop_front() else {return Err(Error::other("no frame decoded"));};
+        self.capabilities.learn(&tlv);
+        self.check_clock(&tlv);
+        log::debug!("<- {}", FrameDump {msg_name: tlv.get_str(TlvKey::MsgName).unwrap_or("???"), tlv: &tlv, redact: self.config.redact_logs});
+        Ok(tlv)
     }
 
 }
 
+impl fmt::Debug for Vtk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vtk")
+            .field("host", &self.config.host)
+            .field("port", &self.config.port)
+            .field("connected", &self.is_connected())
+            .field("degraded", &self.degraded)
+            .field("counters", &self.counters)
+            .finish()
+    }
+}
+
+impl Rotation {
+    fn current(&self, now: Instant) -> Tlv {
+        let turn = now.saturating_duration_since(self.since).as_millis() / self.interval.as_millis().max(1);
+        self.screens[(turn % self.screens.len() as u128) as usize].clone()
+    }
+}
+
+/// How long dropping the driver may take with `Config::disable_on_drop` set.
+const DROP_SHUTDOWN: Duration = Duration::from_secs(1);
+
 impl Drop for Vtk {
     fn drop(&mut self) {
+        if self.config.disable_on_drop && !self.shut_down {
+            if let Err(e) = self.shutdown(DROP_SHUTDOWN) {
+                log::warn!("{}:{}: shutdown on drop: {}", self.config.host, self.config.port, e);
+            }
+            return;
+        }
+        if let Some(Err(e)) = self.journal.as_mut().map(|j| j.flush()) {
+            log::warn!("journal: {}", e);
+        }
         self.disconnect();
     }
 }
@@ -0,0 +1,38 @@
+use vtk::AmountFormat;
+
+#[test]
+fn minor_units_read_as_major_ones() {
+    let plain = AmountFormat::default();
+    assert_eq!(plain.format(12550), "125.50");
+    assert_eq!(plain.format(5), "0.05");
+    assert_eq!(plain.format(0), "0.00");
+    assert_eq!(AmountFormat::new(0).format(1250), "1250");
+    assert_eq!(AmountFormat::new(3).format(7), "0.007");
+    let grouped = AmountFormat {exponent: 2, decimal: ',', grouping: Some(' ')};
+    assert_eq!(grouped.format(125050), "1 250,50");
+    assert_eq!(grouped.format(123456789), "1 234 567,89");
+    assert_eq!(grouped.format(99999), "999,99");
+}
+
+#[cfg(feature = "decimal")]
+#[test]
+fn decimals_convert_to_minor_units_exactly() {
+    use std::{io::ErrorKind, str::FromStr};
+
+    use rust_decimal::Decimal;
+    use vtk::{AmountLimits, VtkError};
+
+    let cents = AmountFormat::default();
+    let limits = AmountLimits {min: 1, max: Some(100_000), exponent: 2};
+    let d = |s| Decimal::from_str(s).unwrap();
+    assert_eq!(cents.to_decimal(12550), d("125.50"));
+    assert_eq!(cents.from_decimal(d("125.5"), &limits).unwrap(), 12550);
+    assert_eq!(cents.from_decimal(d("0.01") * d("3"), &limits).unwrap(), 3);
+    assert_eq!(cents.from_decimal(d("2.500"), &limits).unwrap(), 250);
+    for bad in ["-1", "1.005"] {
+        assert_eq!(cents.from_decimal(d(bad), &limits).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", bad);
+    }
+    let e = cents.from_decimal(d("1000.01"), &limits).unwrap_err();
+    assert!(matches!(VtkError::of(&e), Some(VtkError::AmountOutOfRange {amount: 100_001, ..})));
+    assert_eq!(AmountFormat::new(0).from_decimal(d("7"), &AmountLimits::default()).unwrap(), 7);
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::{fs, io::Write, path::PathBuf};
+
+use common::scratch;
+use vtk::{Rotate, RotatingFile};
+
+#[test]
+fn rotates_by_size_between_records() {
+    let dir = scratch("audit-size");
+    let path = dir.join("audit.log");
+    let mut file = RotatingFile::open(&path, Rotate {max_bytes: Some(20), ..Rotate::default()}).unwrap();
+    for n in 0..5 {
+        // Two writes per record, as `writeln!` would do.
+        write!(file, "record {} ", n).unwrap();
+        writeln!(file, "done").unwrap();
+        file.flush().unwrap();
+    }
+    let mut rotated: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).filter(|p| *p != path).collect();
+    rotated.sort();
+    assert_eq!(rotated.len(), 2);
+    assert!(rotated[0].to_str().unwrap().ends_with(".1"));
+    let mut lines: Vec<String> = rotated.iter().chain([&path]).flat_map(|p| fs::read_to_string(p).unwrap().lines().map(String::from).collect::<Vec<_>>()).collect();
+    lines.sort();
+    assert_eq!(lines, (0..5).map(|n| format!("record {} done", n)).collect::<Vec<_>>());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn audit_rotates_daily_by_the_entry_time() {
+    use vtk::{audit::{AuditEntry, AuditLog, FileAuditLog}, SaleOutcome};
+
+    let dir = scratch("audit-daily");
+    let path = dir.join("audit.log");
+    let mut log = FileAuditLog::open(&path, Rotate {daily: true, ..Rotate::default()}).unwrap();
+    let entry = AuditEntry::Outcome {operation: 1, outcome: SaleOutcome::Finalized};
+    // 2001-01-01 and 2001-01-02 at noon UTC, whatever the OS clock says.
+    log.append(978_350_400_000, &entry).unwrap();
+    log.append(978_436_800_000, &entry).unwrap();
+    let rotated: Vec<PathBuf> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).filter(|p| *p != path).collect();
+    assert_eq!(rotated.len(), 1);
+    assert!(rotated[0].to_str().unwrap().ends_with("audit.log.2001-01-01.1"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "978436800000 outcome op=1 finalized\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn sale_leaves_a_trail() {
+    use std::time::Duration;
+
+    use vtk::{audit::FileAuditLog, testing::MockTerminal, Vtk};
+
+    let dir = scratch("audit-sale");
+    let path = dir.join("audit.log");
+    let mock = MockTerminal::start().unwrap();
+    let mut vtk = Vtk::builder("127.0.0.1", mock.port())
+        .audit_log(FileAuditLog::open(&path, Rotate::default()).unwrap())
+        .build().unwrap();
+    let payment = vtk.request_payment(150, None, Duration::from_secs(30)).unwrap();
+    vtk.finalize_sale(&payment, 150).unwrap();
+    let text = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().map(|l| l.split_once(' ').unwrap().1).collect();
+    let op = payment.operation;
+    assert_eq!(lines, [
+        format!("exchange VRP op={} amount=150 -> VRP", op),
+        format!("exchange FIN op={} amount=150 -> FIN", op),
+        format!("outcome op={} finalized", op),
+    ]);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,8 @@
+#[test]
+fn operator_tokens_must_match() {
+    let authorize = vtk::operator_token(String::from("4711"));
+    assert!(authorize("disable", Some("4711")));
+    for given in [None, Some(""), Some("471"), Some("4712"), Some("47111"), Some("4711\u{0}")] {
+        assert!(!authorize("disable", given), "{:?}", given);
+    }
+}
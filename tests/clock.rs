@@ -0,0 +1,207 @@
+use std::{thread, time::Duration};
+
+use vtk::{clock::ManualClock, rng::SeededRng, transport::{mem, Transport}, Event, Frame, RetryPolicy, Role, Tlv, TlvKey, Vtk};
+
+/// Answers every frame after the first `ignore` ones with a frame of the same name.
+fn terminal(mut end: mem::Endpoint, ignore: usize, answer: usize) {
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        for n in 0..ignore + answer {
+            let Ok(len) = end.read(&mut buf, Duration::from_secs(5)) else {return;};
+            let (frame, _) = Frame::decode(&buf[..len]).unwrap();
+            if n >= ignore {
+                end.write_all(&Frame::new(&frame.msg_name, frame.tlv).encode().unwrap()).unwrap();
+            }
+        }
+    });
+}
+
+#[test]
+fn watchdog_fires_on_manual_time() {
+    let clock = ManualClock::new();
+    let (client, end) = mem::pair();
+    terminal(end, 0, 1);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).clock(clock.clone()).watchdog(Duration::from_secs(60)).build().unwrap();
+    let events = vtk.subscribe();
+    vtk.idle(None).unwrap();
+    clock.advance(Duration::from_secs(59));
+    vtk.check_watchdog();
+    assert!(!vtk.is_degraded());
+    clock.advance(Duration::from_secs(2));
+    vtk.check_watchdog();
+    assert!(vtk.is_degraded());
+    assert!(events.try_iter().any(|e| matches!(e, Event::Degraded {silent_for} if silent_for >= Duration::from_secs(61))));
+}
+
+#[test]
+fn backoff_and_rate_limit_do_not_wait() {
+    let clock = ManualClock::new();
+    let (client, end) = mem::pair();
+    terminal(end, 1, 2);
+    let mut vtk = Vtk::builder("unused", 0)
+        .connector(client)
+        .clock(clock.clone())
+        .read_timeout(Duration::from_millis(50))
+        .retry(RetryPolicy {attempts: 2, backoff: Duration::from_secs(600), ..RetryPolicy::default()})
+        .rate_limit(Duration::from_secs(3600), 1)
+        .build().unwrap();
+    let start = std::time::Instant::now();
+    vtk.idle(None).unwrap();
+    vtk.disable().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    // Three frames one hour apart; the retry's backoff is spent inside the first of those hours.
+    assert_eq!(clock.elapsed(), Duration::from_secs(2 * 3600));
+}
+
+#[test]
+fn jitter_comes_from_the_injected_rng() {
+    let backoff_after_one_retry = |seed| {
+        let clock = ManualClock::new();
+        let (client, end) = mem::pair();
+        terminal(end, 1, 1);
+        let mut vtk = Vtk::builder("unused", 0)
+            .connector(client)
+            .clock(clock.clone())
+            .rng(SeededRng::new(seed))
+            .read_timeout(Duration::from_millis(50))
+            .retry(RetryPolicy {attempts: 2, backoff: Duration::from_secs(600), jitter: Duration::from_secs(60)})
+            .build().unwrap();
+        vtk.idle(None).unwrap();
+        clock.elapsed()
+    };
+    let waited = backoff_after_one_retry(7);
+    assert!(waited > Duration::from_secs(600) && waited <= Duration::from_secs(660), "{:?}", waited);
+    assert_eq!(backoff_after_one_retry(7), waited);
+    assert_ne!(backoff_after_one_retry(8), waited);
+}
+
+#[test]
+fn round_trip_is_the_terminal_s_answer_time() {
+    let clock = ManualClock::new();
+    let (client, mut end) = mem::pair();
+    {
+        let clock = clock.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let len = end.read(&mut buf, Duration::from_secs(5)).unwrap();
+            let (frame, _) = Frame::decode(&buf[..len]).unwrap();
+            clock.advance(Duration::from_millis(250));
+            end.write_all(&Frame::new(&frame.msg_name, frame.tlv).encode().unwrap()).unwrap();
+        });
+    }
+    let mut vtk = Vtk::builder("unused", 0).connector(client).clock(clock).build().unwrap();
+    assert_eq!(vtk.last_round_trip(), None);
+    vtk.idle(None).unwrap();
+    assert_eq!(vtk.last_round_trip(), Some(Duration::from_millis(250)));
+}
+
+#[test]
+fn circuit_opens_fails_fast_and_closes_after_the_cool_down() {
+    use vtk::VtkError;
+
+    let clock = ManualClock::new();
+    let (client, end) = mem::pair();
+    terminal(end, 2, 1);
+    let mut vtk = Vtk::builder("unused", 0).connector(client.clone()).clock(clock.clone())
+        .read_timeout(Duration::from_millis(20))
+        .circuit_breaker(2, Duration::from_secs(30))
+        .build().unwrap();
+    let events = vtk.subscribe();
+    assert!(vtk.idle(None).is_err());
+    assert!(vtk.idle(None).is_err());
+    let err = vtk.idle(None).unwrap_err();
+    assert!(matches!(VtkError::of(&err), Some(VtkError::CircuitOpen {retry_in}) if *retry_in == Duration::from_secs(30)));
+    assert_eq!(VtkError::of(&err).and_then(VtkError::retry_after), Some(Duration::from_secs(30)));
+    assert_eq!(client.pending(), 0);
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(vtk.circuit_open(), None);
+    vtk.idle(None).unwrap();
+    let events: Vec<Event> = events.try_iter().collect();
+    assert_eq!(events, [Event::CircuitOpen {failures: 2, cool_down: Duration::from_secs(30)}, Event::CircuitClosed]);
+}
+
+#[test]
+fn missing_keepalives_make_the_connection_suspect() {
+    let clock = ManualClock::new();
+    let (client, mut end) = mem::pair();
+    let mut keepalive = Tlv::new();
+    keepalive.set_u8(TlvKey::KeepaliveIntervalInSecs, 10);
+    end.write_all(&Frame::new("IDL", keepalive.clone()).encode().unwrap()).unwrap();
+    let mut vtk = Vtk::builder("unused", 0).connector(client).clock(clock.clone()).build().unwrap();
+    let events = vtk.subscribe();
+    vtk.idle(None).unwrap();
+    vtk.connect().unwrap();
+    clock.advance(Duration::from_secs(14));
+    vtk.check_watchdog();
+    assert!(!vtk.is_suspect());
+    clock.advance(Duration::from_secs(2));
+    vtk.check_watchdog();
+    vtk.check_watchdog();
+    assert!(vtk.is_suspect());
+    assert_eq!(events.try_iter().collect::<Vec<_>>(),
+        [Event::TerminalSilent {interval: Duration::from_secs(10), silent_for: Duration::from_secs(16)}]);
+    end.write_all(&Frame::new("IDL", keepalive).encode().unwrap()).unwrap();
+    vtk.receive(Duration::from_secs(1)).unwrap();
+    assert!(!vtk.is_suspect());
+}
+
+#[test]
+fn a_sale_shares_one_budget_across_its_legs() {
+    use std::{io::ErrorKind, sync::mpsc};
+
+    use vtk::SaleOutcome;
+
+    let clock = ManualClock::new();
+    let (client, mut end) = mem::pair();
+    let (seen, frames) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        while let Ok(len) = end.read(&mut buf, Duration::from_secs(5)) {
+            let Ok((frame, _)) = Frame::decode(&buf[..len]) else {return;};
+            _ = seen.send((frame.msg_name.clone(), frame.tlv.get_duration(TlvKey::OperationTimeoutInSecs)));
+            end.write_all(&Frame::new(&frame.msg_name, frame.tlv).encode().unwrap()).unwrap();
+        }
+    });
+    let mut vtk = Vtk::builder("unused", 0).connector(client).clock(clock.clone()).read_timeout(Duration::from_secs(5)).build().unwrap();
+    let record = vtk.sell(100, None, Duration::from_secs(60), |_| {
+        clock.advance(Duration::from_secs(10));
+        Ok(80)
+    }).unwrap();
+    assert_eq!((record.outcome, record.amount), (SaleOutcome::Finalized, 80));
+    // Both stamps come from the manual clock, ten seconds of dispensing apart.
+    assert_eq!(record.settled_at_ms - record.requested_at_ms, 10_000);
+    let e = vtk.sell(100, None, Duration::from_secs(60), |_| {
+        clock.advance(Duration::from_secs(120));
+        Ok(100)
+    }).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    let names: Vec<(String, Option<Duration>)> = frames.try_iter().collect();
+    // The customer gets the budget less the settlement held back and the read timeout VRP answers within; the
+    // overrun sale is never settled.
+    assert_eq!(names, [
+        (String::from("IDL"), None),
+        (String::from("VRP"), Some(Duration::from_secs(50))),
+        (String::from("FIN"), None),
+        (String::from("IDL"), None),
+        (String::from("VRP"), Some(Duration::from_secs(50))),
+    ]);
+}
+
+#[test]
+fn unbounded_timeouts_do_not_overflow() {
+    let (client, end) = mem::pair();
+    terminal(end, 0, 2);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    assert!(vtk.request_payment(100, None, Duration::MAX).unwrap().approved);
+    vtk.send("IDL", Tlv::new()).unwrap();
+    assert_eq!(vtk.receive(Duration::MAX).unwrap().get_str(TlvKey::MsgName), Some("IDL"));
+}
+
+#[test]
+fn waiting_for_the_terminal_to_connect_runs_on_manual_time() {
+    let clock = ManualClock::new();
+    let mut vtk = Vtk::builder("127.0.0.1", 0).role(Role::Server).bind_addr([127, 0, 0, 1].into())
+        .connect_timeout(Duration::from_secs(30)).clock(clock.clone()).build().unwrap();
+    assert_eq!(vtk.connect().unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    assert!(clock.elapsed() >= Duration::from_secs(30));
+}
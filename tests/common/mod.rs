@@ -0,0 +1,29 @@
+//! Helpers shared by the integration tests; each test file uses only some of them.
+#![allow(dead_code)]
+
+use std::{fs, path::PathBuf, thread, time::Duration};
+
+use vtk::{transport::{mem, Transport}, Frame};
+
+/// An empty directory under the system temp dir, unique to `name` and this test process.
+pub fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vtk-{}-{}", name, std::process::id()));
+    _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Approves everything by echoing `frames` frames back; yields the MsgNames it saw.
+pub fn echo_terminal(mut end: mem::Endpoint, frames: usize) -> thread::JoinHandle<Vec<String>> {
+    thread::spawn(move || {
+        let mut seen = Vec::new();
+        let mut buf = [0; 512];
+        while seen.len() < frames {
+            let n = end.read(&mut buf, Duration::from_secs(5)).unwrap();
+            let (frame, _) = Frame::decode(&buf[..n]).unwrap();
+            end.write_all(&Frame::new(&frame.msg_name, frame.tlv.clone()).encode().unwrap()).unwrap();
+            seen.push(frame.msg_name);
+        }
+        seen
+    })
+}
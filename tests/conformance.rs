@@ -0,0 +1,17 @@
+mod common;
+
+use std::time::Duration;
+
+use common::echo_terminal;
+use vtk::{conformance, transport::mem, Vtk};
+
+#[test]
+fn the_sale_battery_runs_the_money_path() {
+    let checks = conformance::battery_with_sale(7, 100);
+    let (client, terminal) = mem::pair();
+    let terminal = echo_terminal(terminal, checks.len());
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    let report = conformance::run(&mut vtk, &checks, Duration::from_secs(5));
+    assert!(report.passed(), "{}", report);
+    assert_eq!(terminal.join().unwrap(), ["IDL", "IDL", "DIS", "IDL", "VRP", "FIN", "VRP", "ABR"]);
+}
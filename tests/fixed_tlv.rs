@@ -0,0 +1,46 @@
+use vtk::{FixedError, FixedTlv, Frame, Tlv, TlvKey};
+
+type Small = FixedTlv<4, 32>;
+
+#[test]
+fn frames_match_the_heap_codec() {
+    let mut fixed = Small::new();
+    fixed.set_str(TlvKey::MsgName, "VRP").unwrap();
+    fixed.set_bin(TlvKey::OperationNum, &[0x01, 0x02]).unwrap();
+    fixed.set_str(TlvKey::ProductName, "Tea").unwrap();
+    let mut out = [0u8; 64];
+    let n = fixed.encode_frame(&mut out).unwrap();
+
+    let (frame, used) = Frame::decode(&out[..n]).unwrap();
+    assert_eq!((frame.msg_name.as_str(), used), ("VRP", n));
+    assert_eq!(frame.tlv.get_bin(TlvKey::OperationNum), Some(&[0x01, 0x02][..]));
+
+    let raw = Frame::new("VRP", Tlv::from(&fixed)).encode().unwrap();
+    let (back, used) = Small::decode_frame(&raw).unwrap();
+    assert_eq!((used, back.len()), (raw.len(), 3));
+    assert_eq!(back.get_str(TlvKey::ProductName), Some("Tea"));
+}
+
+#[test]
+fn capacity_errors_leave_the_tlv_unchanged() {
+    let mut fixed = FixedTlv::<2, 10>::new();
+    fixed.set_bin(TlvKey::OperationNum, &[1, 2, 3]).unwrap();
+    assert_eq!(fixed.set_bin(TlvKey::ProductId, &[0; 4]), Err(FixedError::BufferFull));
+    fixed.set_bin(TlvKey::ProductId, &[7]).unwrap();
+    assert_eq!(fixed.set_bin(TlvKey::EventNum, &[]), Err(FixedError::TooManyTags));
+    assert_eq!(fixed.set_bin(TlvKey::OperationNum, &[0; 300]), Err(FixedError::ValueTooLong(300)));
+    // Replacing a value reuses its room.
+    fixed.set_bin(TlvKey::OperationNum, &[9, 9, 9, 9]).unwrap();
+    assert!(fixed.remove(TlvKey::ProductId));
+    assert_eq!((fixed.len(), fixed.as_bytes()), (1, &[0x03, 0x04, 9, 9, 9, 9][..]));
+}
+
+#[test]
+fn parsing_is_strict() {
+    assert_eq!(Small::parse(&[0x01, 0x01, b'A', 0x01, 0x01, b'B']).err(), Some(FixedError::DuplicateTag(0x01)));
+    assert_eq!(Small::parse(&[0x01, 0x05, b'A']).err(), Some(FixedError::TruncatedTag(0x01)));
+    assert_eq!(Small::parse(&[0x01, 0x01, b'A', 0x09]).err(), Some(FixedError::TrailingBytes(1)));
+    assert_eq!(Small::decode_frame(&[0x00, 0x02, 0x00, 0x00]).err(), Some(FixedError::BadHeader));
+    let tags: Vec<u8> = Small::parse(&[0xEE, 0x00, 0x01, 0x01, b'A']).unwrap().iter().map(|(k, _)| k.as_u8()).collect();
+    assert_eq!(tags, [0xEE, 0x01]);
+}
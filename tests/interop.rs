@@ -0,0 +1,336 @@
+//! End-to-end sale, abort and reconnect scenarios.
+//!
+//! Opt-in: set `VTK_INTEROP_ENDPOINT=host:port` to run them against a simulator or terminal, e.g. one
+//! started with `docker run -p 62801:62801 <simulator image>`, or build with `--features testing` to run
+//! them against the bundled `MockTerminal`. Without either they are skipped.
+
+use std::time::Duration;
+
+use vtk::{Config, Product, SaleOutcome, Vtk};
+
+enum Endpoint {
+    Remote(String, u16),
+    #[cfg(feature = "testing")]
+    Mock(vtk::testing::MockTerminal),
+}
+
+impl Endpoint {
+    fn config(&self) -> Config {
+        let mut config = match self {
+            Endpoint::Remote(host, port) => Config::new(host, *port),
+            #[cfg(feature = "testing")]
+            Endpoint::Mock(mock) => Config::new("127.0.0.1", mock.port()),
+        };
+        config.read_timeout = Duration::from_secs(5);
+        config
+    }
+
+    /// The mock checks every request against the schema registry; a real endpoint has no such hook.
+    fn assert_well_formed(&self) {
+        #[cfg(feature = "testing")]
+        if let Endpoint::Mock(mock) = self {
+            assert_eq!(mock.violations(), []);
+        }
+    }
+}
+
+fn endpoint() -> Option<Endpoint> {
+    if let Ok(addr) = std::env::var("VTK_INTEROP_ENDPOINT") {
+        let (host, port) = addr.rsplit_once(':').expect("VTK_INTEROP_ENDPOINT is host:port");
+        return Some(Endpoint::Remote(String::from(host), port.parse().expect("VTK_INTEROP_ENDPOINT port")));
+    }
+    #[cfg(feature = "testing")]
+    return Some(Endpoint::Mock(vtk::testing::MockTerminal::start().unwrap()));
+    #[cfg(not(feature = "testing"))]
+    None
+}
+
+macro_rules! endpoint_or_skip {
+    () => {
+        match endpoint() {
+            Some(endpoint) => endpoint,
+            None => {
+                eprintln!("skipped: set VTK_INTEROP_ENDPOINT or enable the testing feature");
+                return;
+            },
+        }
+    };
+}
+
+fn product() -> Product {
+    Product {id: Some(7), name: Some(String::from("Interop tea"))}
+}
+
+#[test]
+fn sale_is_approved_and_finalized() {
+    let endpoint = endpoint_or_skip!();
+    let mut vtk = Vtk::from_config(endpoint.config());
+    vtk.idle(None).unwrap();
+    let payment = vtk.request_payment(150, Some(&product()), Duration::from_secs(30)).unwrap();
+    assert!(payment.approved, "payment declined: {:?}", payment.response);
+    let record = vtk.finalize_sale(&payment, 150).unwrap();
+    assert_eq!((record.outcome, record.amount), (SaleOutcome::Finalized, 150));
+    vtk.idle(None).unwrap();
+    endpoint.assert_well_formed();
+}
+
+#[test]
+fn sale_is_aborted() {
+    let endpoint = endpoint_or_skip!();
+    let mut vtk = Vtk::from_config(endpoint.config());
+    vtk.idle(None).unwrap();
+    let payment = vtk.request_payment(90, Some(&product()), Duration::from_secs(30)).unwrap();
+    let record = vtk.abort_sale(&payment).unwrap();
+    assert_eq!(record.outcome, SaleOutcome::Aborted);
+    vtk.idle(None).unwrap();
+    endpoint.assert_well_formed();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn payment_carries_the_filled_in_qr() {
+    use vtk::{testing::MockTerminal, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut config = Config::new("127.0.0.1", mock.port());
+    config.qr_template = Some(String::from("https://pay.example/{op}/{amount}?p={product}"));
+    let mut vtk = Vtk::from_config(config);
+    let payment = vtk.request_payment(250, Some(&product()), Duration::from_secs(30)).unwrap();
+    vtk.abort_sale(&payment).unwrap();
+    let vrp = mock.received().into_iter().find(|t| t.get_str(TlvKey::MsgName) == Some("VRP")).unwrap();
+    let expected = format!("https://pay.example/{}/250?p=7", payment.operation);
+    assert_eq!(vrp.get_str(TlvKey::QrCodeData), Some(expected.as_str()));
+    assert_eq!(mock.violations(), []);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn out_of_range_amounts_never_reach_the_terminal() {
+    use std::io::ErrorKind;
+
+    use vtk::{testing::MockTerminal, AmountLimits, VtkError};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut vtk = Vtk::builder("127.0.0.1", mock.port())
+        .amount_limits(AmountLimits {min: 100, max: Some(50_000), exponent: 2})
+        .build().unwrap();
+    for amount in [5, 50_001] {
+        let err = vtk.request_payment(amount, None, Duration::from_secs(30)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(matches!(VtkError::of(&err), Some(VtkError::AmountOutOfRange {..})));
+    }
+    let err = vtk.request_payment(5, None, Duration::from_secs(30)).unwrap_err();
+    assert_eq!(err.to_string(), "amount 0.05 is outside the allowed range 1.00..=500.00");
+    assert!(mock.received().is_empty());
+    let payment = vtk.request_payment(100, None, Duration::from_secs(30)).unwrap();
+    vtk.abort_sale(&payment).unwrap();
+}
+
+#[test]
+fn reconnects_after_disconnect() {
+    let endpoint = endpoint_or_skip!();
+    let mut vtk = Vtk::from_config(endpoint.config());
+    vtk.disable().unwrap();
+    assert!(vtk.is_connected());
+    vtk.disconnect();
+    assert!(!vtk.is_connected());
+    let payment = vtk.request_payment(50, None, Duration::from_secs(30)).unwrap();
+    assert!(vtk.is_connected());
+    vtk.abort_sale(&payment).unwrap();
+    vtk.idle(None).unwrap();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn retries_through_a_dropped_connection() {
+    use vtk::{testing::{Fault, MockTerminal}, RetryPolicy, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut config = Config::new("127.0.0.1", mock.port());
+    config.retry = RetryPolicy {attempts: 2, backoff: Duration::from_millis(50), ..RetryPolicy::default()};
+    let mut vtk = Vtk::from_config(config);
+    mock.inject(Fault::Disconnect);
+    assert_eq!(vtk.disable().unwrap().get_str(TlvKey::MsgName), Some("DIS"));
+    let names: Vec<String> = mock.received().iter().filter_map(|t| t.get_str(TlvKey::MsgName).map(String::from)).collect();
+    assert_eq!(names, ["DIS", "DIS"]);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn declined_sale_follows_the_script() {
+    use vtk::{testing::{ExpectScript, MockTerminal}, Tlv};
+
+    let mock = MockTerminal::start().unwrap();
+    mock.script(ExpectScript::new()
+        .expect("IDL")
+        .expect("VRP").respond_as("ABR", Tlv::new())
+        .expect("IDL"));
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    vtk.idle(None).unwrap();
+    let payment = vtk.request_payment(100, None, Duration::from_secs(30)).unwrap();
+    assert!(!payment.approved);
+    vtk.idle(None).unwrap();
+    mock.assert_script_done();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+#[should_panic(expected = "expected VRP, got FIN; never received IDL")]
+fn broken_script_is_reported() {
+    use vtk::testing::{ExpectScript, MockTerminal};
+
+    let mock = MockTerminal::start().unwrap();
+    mock.script(ExpectScript::new().expect("VRP").expect("IDL"));
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    vtk.finalize(1, 100).unwrap();
+    mock.assert_script_done();
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn shutdown_disables_the_terminal() {
+    use vtk::{testing::{Fault, MockTerminal}, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    vtk.idle(None).unwrap();
+    vtk.shutdown(Duration::from_secs(1)).unwrap();
+    assert!(!vtk.is_connected());
+    drop(vtk);
+    let mut config = Config::new("127.0.0.1", mock.port());
+    config.disable_on_drop = true;
+    drop(Vtk::from_config(config.clone()));
+    let names: Vec<String> = mock.received().iter().filter_map(|t| t.get_str(TlvKey::MsgName).map(String::from)).collect();
+    assert_eq!(names, ["IDL", "DIS", "DIS"]);
+
+    mock.inject(Fault::DropFrame);
+    let start = std::time::Instant::now();
+    assert!(Vtk::from_config(config).shutdown(Duration::from_millis(300)).is_err());
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn resume_restores_the_idle_screen() {
+    use vtk::{testing::MockTerminal, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    vtk.show_qr("https://example.com/loyalty").unwrap();
+    vtk.pause().unwrap();
+    assert!(vtk.is_paused());
+    vtk.resume().unwrap();
+    assert!(!vtk.is_paused());
+    let sent = mock.received();
+    assert_eq!(sent.iter().map(|t| t.get_str(TlvKey::MsgName).unwrap()).collect::<Vec<_>>(), ["IDL", "DIS", "IDL"]);
+    assert_eq!(sent[2].get_str(TlvKey::QrCodeData), Some("https://example.com/loyalty"));
+}
+
+#[cfg(all(feature = "testing", feature = "schedule"))]
+#[test]
+fn scheduler_pauses_and_resumes_on_transitions() {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use vtk::{schedule::{Schedule, Scheduler}, testing::MockTerminal, Event};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    let events = vtk.subscribe();
+    let mut scheduler = Scheduler::new(Schedule::parse("Mon 08:00-20:00", "UTC").unwrap());
+    // 2024-01-01 was a Monday.
+    let monday = |hour: u64| -> SystemTime {UNIX_EPOCH + Duration::from_secs(1_704_067_200 + hour * 3600)};
+    assert_eq!(scheduler.tick_at(&mut vtk, monday(7)).unwrap(), Some(false));
+    assert_eq!(scheduler.tick_at(&mut vtk, monday(7)).unwrap(), None);
+    assert_eq!(scheduler.tick_at(&mut vtk, monday(9)).unwrap(), Some(true));
+    assert!(!vtk.is_paused());
+    let transitions: Vec<Event> = events.try_iter().collect();
+    assert_eq!(transitions, [Event::OperatingHours {open: false}, Event::OperatingHours {open: true}]);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn idle_rotates_through_screens() {
+    use vtk::{testing::MockTerminal, Tlv, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    let screens: Vec<Tlv> = ["loyalty", "promo"].iter().map(|qr| {
+        let mut tlv = Tlv::new();
+        tlv.set_str(TlvKey::QrCodeData, qr);
+        tlv
+    }).collect();
+    vtk.set_idle_rotation(screens, Duration::from_millis(150));
+    vtk.idle(None).unwrap();
+    std::thread::sleep(Duration::from_millis(200));
+    vtk.idle(None).unwrap();
+    vtk.pause().unwrap();
+    vtk.resume().unwrap();
+    vtk.set_idle_rotation(Vec::new(), Duration::ZERO);
+    vtk.idle(None).unwrap();
+    let shown: Vec<Option<String>> = mock.received().iter().filter(|t| t.get_str(TlvKey::MsgName) == Some("IDL"))
+        .map(|t| t.get_str(TlvKey::QrCodeData).map(String::from)).collect();
+    assert_eq!(shown[..2], [Some(String::from("loyalty")), Some(String::from("promo"))]);
+    // Resuming rejoins the rotation rather than pinning the screen shown at pause time.
+    assert!(shown[2].is_some());
+    assert_eq!(shown[3], None);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn clock_skew_is_reported_once() {
+    use vtk::{testing::MockTerminal, Event, Tlv, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut status = Tlv::new();
+    status.set_str(TlvKey::LocalTime, "20000101120000");
+    mock.reply("IDL", status);
+    let mut vtk = Vtk::builder("127.0.0.1", mock.port()).clock_skew(Duration::from_secs(60), 180).build().unwrap();
+    let events = vtk.subscribe();
+    vtk.idle(None).unwrap();
+    vtk.idle(None).unwrap();
+    // 2000-01-01 12:00 at UTC+3 is 09:00 UTC.
+    let expected = 946_717_200_000 - std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis() as i64;
+    let skew = vtk.clock_skew().unwrap();
+    assert!((skew - expected).abs() < 5_000, "{} vs {}", skew, expected);
+    let skews: Vec<Event> = events.try_iter().filter(|e| matches!(e, Event::ClockSkew {..})).collect();
+    assert_eq!(skews.len(), 1);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn probe_learns_the_firmware() {
+    use std::io::ErrorKind;
+
+    use vtk::{testing::MockTerminal, Tlv, TlvKey};
+
+    let mock = MockTerminal::start().unwrap();
+    let mut status = Tlv::new();
+    status.set_str(TlvKey::SysInfo, "VTK-Pro fw 2.14.1 (build 7)");
+    mock.reply("IDL", status);
+    let mut vtk = Vtk::from_config(Config::new("127.0.0.1", mock.port()));
+    vtk.require_firmware(&[2, 10]).unwrap();
+    let caps = vtk.capabilities();
+    assert_eq!(caps.firmware, Some(vec![2, 14, 1]));
+    assert!(caps.reports(TlvKey::SysInfo));
+    assert_eq!(vtk.require_firmware(&[3]).unwrap_err().kind(), ErrorKind::Unsupported);
+    // One probe was enough.
+    assert_eq!(mock.received().len(), 1);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn reconfigure_reconnects_only_when_the_address_changes() {
+    use vtk::testing::MockTerminal;
+
+    let mock = MockTerminal::start().unwrap();
+    let mut config = Config::new("127.0.0.1", mock.port());
+    let mut vtk = Vtk::from_config(config.clone());
+    vtk.connect().unwrap();
+    config.read_timeout = Duration::from_secs(9);
+    assert!(!vtk.reconfigure(config.clone()));
+    assert!(vtk.is_connected());
+    assert_eq!(vtk.config().read_timeout, Duration::from_secs(9));
+    config.host = String::from("localhost");
+    assert!(vtk.reconfigure(config));
+    assert!(!vtk.is_connected());
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use std::{fs, io::ErrorKind, time::Duration};
+
+use common::{echo_terminal, scratch};
+use vtk::{prelude::*, transport::mem, FileJournal, Journal, VtkError};
+
+#[test]
+fn a_key_that_charged_is_not_sold_again() {
+    let dir = scratch("journal-keys");
+    let path = dir.join("journal.log");
+    let (client, terminal) = mem::pair();
+    echo_terminal(terminal, 3);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).journal(FileJournal::open(&path).unwrap()).build().unwrap();
+    let record = vtk.sell_once("basket 1", 100, None, Duration::from_secs(60), |_| Ok(100)).unwrap();
+    assert_eq!(record.outcome, SaleOutcome::Finalized);
+    drop(vtk);
+
+    // A sale from before keys, and one that crashed mid-way.
+    fs::write(&path, fs::read_to_string(&path).unwrap() + "P 900 50 0 - -\nP 901 50 0 - - 6261736b65742032\n").unwrap();
+    let journal = FileJournal::open(&path).unwrap();
+    assert_eq!(journal.find_key("basket 1").unwrap(), Some((record.operation_num, Some(SaleOutcome::Finalized))));
+    assert_eq!(journal.list_unresolved().unwrap().len(), 2);
+    let (client, _terminal) = mem::pair();
+    let mut vtk = Vtk::builder("unused", 0).connector(client).journal(journal).build().unwrap();
+    for (key, operation, outcome) in [("basket 1", record.operation_num, Some(SaleOutcome::Finalized)), ("basket 2", 901, None)] {
+        let e = vtk.sell_once(key, 100, None, Duration::from_secs(60), |_| Ok(100)).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+        assert!(matches!(VtkError::of(&e), Some(VtkError::Duplicate {operation: op, outcome: o, ..}) if (*op, *o) == (operation, outcome)));
+    }
+    assert_eq!(vtk.counters().frames_sent, 0);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn operation_numbers_go_on_after_a_restart() {
+    let dir = scratch("journal-restart");
+    let path = dir.join("journal.log");
+    let (client, terminal) = mem::pair();
+    echo_terminal(terminal, 2);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).journal(FileJournal::open(&path).unwrap()).build().unwrap();
+    let payment = vtk.request_payment(100, None, Duration::from_secs(30)).unwrap();
+    vtk.finalize(payment.operation, 100).unwrap();
+    drop(vtk);
+
+    let journal = FileJournal::open(&path).unwrap();
+    assert!(journal.list_unresolved().unwrap().is_empty());
+    assert_eq!(journal.last_operation(), Some(payment.operation));
+    let (client, terminal) = mem::pair();
+    echo_terminal(terminal, 1);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).journal(journal).build().unwrap();
+    assert_eq!(vtk.request_payment(100, None, Duration::from_secs(30)).unwrap().operation, payment.operation + 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_journal_torn_mid_line_still_opens() {
+    let dir = scratch("journal-torn");
+    let path = dir.join("journal.log");
+    fs::write(&path, "P 1 100 0 - -\nP 2 10").unwrap();
+    let mut journal = FileJournal::open(&path).unwrap();
+    assert_eq!(journal.list_unresolved().unwrap().iter().map(|e| e.operation).collect::<Vec<_>>(), [1]);
+    journal.mark_resolved(1, SaleOutcome::Aborted).unwrap();
+    drop(journal);
+    let journal = FileJournal::open(&path).unwrap();
+    assert!(journal.list_unresolved().unwrap().is_empty());
+    assert_eq!(journal.last_operation(), Some(1));
+}
@@ -0,0 +1,65 @@
+use vtk::{machine::{Action, Machine}, Anomaly, LengthEncoding, ParseMode, Tlv, TlvKey};
+
+fn operation(op: u32) -> Tlv {
+    let mut tlv = Tlv::new();
+    tlv.set_u32(TlvKey::OperationNum, op);
+    tlv
+}
+
+fn received(actions: &[Action]) -> Vec<String> {
+    actions.iter().filter_map(|a| match a {
+        Action::Received(tlv) => Some(format!("{}:{}", tlv.get_str(TlvKey::MsgName).unwrap_or("?"), tlv.get_uint(TlvKey::OperationNum).unwrap_or(0))),
+        _ => None,
+    }).collect()
+}
+
+#[test]
+fn frames_come_out_however_the_input_is_split() {
+    let mut machine = Machine::default();
+    let mut wire = machine.encode("IDL", operation(1)).unwrap();
+    wire.extend(machine.encode("VRP", operation(2)).unwrap());
+    for chunk in [1, 3, 7, wire.len()] {
+        let mut machine = Machine::default();
+        let actions: Vec<Action> = wire.chunks(chunk).flat_map(|c| machine.handle_input(c)).collect();
+        assert_eq!(received(&actions), ["IDL:1", "VRP:2"], "chunks of {}", chunk);
+        assert_eq!(machine.buffered(), 0);
+    }
+    let half = machine.handle_input(&wire[..5]);
+    assert!(half.is_empty());
+    assert_eq!(machine.buffered(), 5);
+    machine.reset();
+    assert_eq!(received(&machine.handle_input(&wire)), ["IDL:1", "VRP:2"]);
+}
+
+#[test]
+fn an_answer_ends_the_wait_for_it() {
+    let mut machine = Machine::new(ParseMode::Lenient, LengthEncoding::Ber);
+    let answer = machine.encode("VRP", operation(7)).unwrap();
+    machine.sent("VRP");
+    assert_eq!(machine.awaiting(), Some("VRP"));
+    machine.handle_input(&answer[..answer.len() - 1]);
+    assert_eq!(machine.awaiting(), Some("VRP"));
+    machine.handle_input(&answer[answer.len() - 1..]);
+    assert_eq!(machine.awaiting(), None);
+}
+
+#[test]
+fn bad_headers_drop_what_is_buffered() {
+    let mut machine = Machine::default();
+    let frame = machine.encode("IDL", Tlv::new()).unwrap();
+    let mut trailing = frame.clone();
+    trailing.extend([0x00, 0x04, 0x12, 0x34]);
+    let actions = machine.handle_input(&trailing);
+    assert_eq!(received(&actions), ["IDL:0"]);
+    assert!(matches!(actions.last(), Some(Action::Anomaly(Anomaly::TrailingBytes(4)))));
+    let actions = machine.handle_input(&[0x00, 0x04, 0x12, 0x34]);
+    assert!(matches!(&actions[..], [Action::Rejected(reason)] if reason == "unexpected protocol id 1234"));
+    assert_eq!(machine.buffered(), 0);
+
+    let mut strict = Machine::new(ParseMode::Strict, LengthEncoding::Short);
+    let actions = strict.handle_input(&trailing);
+    assert_eq!(received(&actions), ["IDL:0"]);
+    assert!(matches!(actions.last(), Some(Action::Rejected(_))));
+    assert!(matches!(&strict.handle_input(&[0x00, 0x01, 0x96, 0xFB])[..], [Action::Rejected(_)]));
+    assert!(strict.encode("VRP", Tlv::new()).is_err());
+}
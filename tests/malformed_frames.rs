@@ -0,0 +1,116 @@
+use std::io::ErrorKind;
+
+use vtk::{Anomaly, Frame, ParseMode, Tlv, TlvKey};
+
+fn frame(body: &[u8]) -> Vec<u8> {
+    let mut raw = ((body.len() + 2) as u16).to_be_bytes().to_vec();
+    raw.extend_from_slice(&[0x96, 0xFB]);
+    raw.extend_from_slice(body);
+    raw
+}
+
+fn valid() -> Vec<u8> {
+    let mut tlv = Tlv::new();
+    tlv.set_bin(TlvKey::OperationNum, &[0x01, 0x02]);
+    tlv.set_str(TlvKey::ProductName, "Tea");
+    Frame::new("VRP", tlv).encode().unwrap()
+}
+
+fn decode(raw: &[u8], mode: ParseMode) -> (Result<(Frame, usize), std::io::Error>, Vec<Anomaly>) {
+    let mut warnings = Vec::new();
+    let res = Frame::decode_with(raw, mode, &mut warnings);
+    (res, warnings)
+}
+
+#[test]
+fn header_corpus_is_rejected() {
+    let corpus: &[(&[u8], ErrorKind)] = &[
+        (&[], ErrorKind::UnexpectedEof),
+        (&[0x00], ErrorKind::UnexpectedEof),
+        (&[0x00, 0x05, 0x96], ErrorKind::UnexpectedEof),
+        (&[0x00, 0x02, 0x00, 0x00], ErrorKind::InvalidData),
+        // Declared lengths below the protocol id used to underflow.
+        (&[0x00, 0x00, 0x96, 0xFB], ErrorKind::UnexpectedEof),
+        (&[0x00, 0x01, 0x96, 0xFB], ErrorKind::UnexpectedEof),
+        (&[0xFF, 0xFF, 0x96, 0xFB, 0x01], ErrorKind::UnexpectedEof),
+        (&[0x00, 0x10, 0x96, 0xFB, 0x01, 0x03, b'I'], ErrorKind::UnexpectedEof),
+    ];
+    for (raw, kind) in corpus {
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            let (res, _) = decode(raw, mode);
+            assert_eq!(res.err().map(|e| e.kind()), Some(*kind), "{:02X?} in {:?}", raw, mode);
+        }
+    }
+}
+
+#[test]
+fn empty_body_is_a_frame() {
+    let (res, warnings) = decode(&frame(&[]), ParseMode::Strict);
+    let (frame, used) = res.unwrap();
+    assert_eq!((frame.msg_name.as_str(), used, warnings.len()), ("", 4, 0));
+}
+
+#[test]
+fn body_corpus_is_tolerated_or_rejected() {
+    let corpus: &[(&[u8], Anomaly)] = &[
+        (&[0x01], Anomaly::TrailingBytes(1)),
+        (&[0x01, 0x03, b'I', b'D', b'L', 0x09], Anomaly::TrailingBytes(1)),
+        (&[0x01, 0xFF, b'I'], Anomaly::TruncatedTag {tag: 0x01, declared: 255, available: 1}),
+        (&[0x01, 0x01, b'A', 0x01, 0x01, b'B'], Anomaly::DuplicateTag(0x01)),
+    ];
+    for (body, anomaly) in corpus {
+        let raw = frame(body);
+        let (res, warnings) = decode(&raw, ParseMode::Lenient);
+        assert_eq!(res.unwrap().1, raw.len());
+        assert_eq!(warnings, vec![anomaly.clone()], "{:02X?}", body);
+        let (res, _) = decode(&raw, ParseMode::Strict);
+        assert_eq!(res.err().map(|e| e.kind()), Some(ErrorKind::InvalidData), "{:02X?}", body);
+    }
+}
+
+#[test]
+fn unknown_tags_are_kept_in_both_modes() {
+    let raw = frame(&[0x02, 0x01, 0x07, 0x01, 0x03, b'I', b'D', b'L', 0xFF, 0x00]);
+    for mode in [ParseMode::Strict, ParseMode::Lenient] {
+        let (res, warnings) = decode(&raw, mode);
+        let frame = res.unwrap().0;
+        assert_eq!(frame.msg_name, "IDL");
+        assert_eq!(frame.tlv.get_bin(TlvKey::Unknown(0x02)), Some(&[0x07][..]));
+        assert_eq!(frame.tlv.get_bin(TlvKey::Unknown(0xFF)), Some(&[][..]));
+        assert_eq!(warnings, vec![Anomaly::UnknownTag(0x02), Anomaly::UnknownTag(0xFF)]);
+    }
+}
+
+#[test]
+fn every_truncation_of_a_valid_frame_fails_cleanly() {
+    let raw = valid();
+    assert!(decode(&raw, ParseMode::Strict).0.is_ok());
+    for n in 0..raw.len() {
+        assert!(decode(&raw[..n], ParseMode::Lenient).0.is_err(), "prefix of {} bytes", n);
+    }
+}
+
+#[test]
+fn random_bytes_never_panic() {
+    // Small xorshift so the corpus is the same on every run.
+    let mut state = 0x2545F4914F6CDD1Du64;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    for _ in 0..20_000 {
+        let len = (next() % 64) as usize;
+        let mut raw: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+        if next() % 2 == 0 && raw.len() >= 4 {
+            raw[2..4].copy_from_slice(&[0x96, 0xFB]);
+        }
+        for mode in [ParseMode::Strict, ParseMode::Lenient] {
+            if let (Ok((_, used)), _) = decode(&raw, mode) {
+                assert!(used <= raw.len());
+            }
+        }
+        let _ = Tlv::deserialize(&raw);
+    }
+}
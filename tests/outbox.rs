@@ -0,0 +1,52 @@
+mod common;
+
+use std::{fs, io::Error, time::Duration};
+
+use common::{echo_terminal, scratch};
+use vtk::{prelude::*, transport::mem, FileOutbox, Outbox, SaleRecord};
+
+#[test]
+fn outcomes_survive_a_restart_until_acknowledged() {
+    let dir = scratch("outbox-restart");
+    let path = dir.join("outbox.log");
+    let (client, terminal) = mem::pair();
+    echo_terminal(terminal, 2);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).outbox(FileOutbox::open(&path).unwrap()).build().unwrap();
+    let payment = vtk.request_payment(150, None, Duration::from_secs(30)).unwrap();
+    vtk.finalize(payment.operation, 120).unwrap();
+    drop(vtk);
+
+    let mut outbox = FileOutbox::open(&path).unwrap();
+    let pending = outbox.pending().unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!((pending[0].operation_num, pending[0].amount, pending[0].outcome), (payment.operation, 120, SaleOutcome::Finalized));
+    assert_eq!(pending[0].requested_at_ms, payment.record(SaleOutcome::Finalized, 120, None, payment.requested_at).requested_at_ms);
+    assert!(outbox.deliver(&mut |_| Err(Error::other("ERP down"))).is_err());
+    assert_eq!(outbox.deliver(&mut |_| Ok(())).unwrap(), 1);
+    outbox.push(&pending[0]).unwrap();
+    drop(outbox);
+
+    let outbox = FileOutbox::open(&path).unwrap();
+    assert!(outbox.pending().unwrap().is_empty());
+    assert_eq!(outbox.last_operation(), Some(payment.operation));
+    let (client, terminal) = mem::pair();
+    echo_terminal(terminal, 1);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).outbox(outbox).build().unwrap();
+    assert_eq!(vtk.request_payment(150, None, Duration::from_secs(30)).unwrap().operation, payment.operation + 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn an_outbox_torn_mid_line_still_opens() {
+    let dir = scratch("outbox-torn");
+    let path = dir.join("outbox.log");
+    fs::write(&path, "A 3\nO 4 12").unwrap();
+    let mut outbox = FileOutbox::open(&path).unwrap();
+    assert!(outbox.pending().unwrap().is_empty());
+    let record = SaleRecord {operation_num: 5, amount: 12, product: None, outcome: SaleOutcome::Aborted, requested_at_ms: 1,
+        settled_at_ms: 2, receipt: None};
+    outbox.push(&record).unwrap();
+    drop(outbox);
+    assert_eq!(FileOutbox::open(&path).unwrap().pending().unwrap(), [record]);
+    fs::remove_dir_all(dir).unwrap();
+}
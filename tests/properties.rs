@@ -0,0 +1,21 @@
+#![cfg(feature = "proptest")]
+
+use proptest::prelude::*;
+use vtk::{schema::{self, Direction}, strategy, Frame, ParseMode, Tlv};
+
+proptest! {
+    #[test]
+    fn generated_frames_decode_strictly(raw in strategy::frame_bytes(Direction::FromTerminal)) {
+        let mut warnings = Vec::new();
+        let (frame, used) = Frame::decode_with(&raw, ParseMode::Strict, &mut warnings).unwrap();
+        prop_assert_eq!(used, raw.len());
+        prop_assert!(warnings.is_empty());
+        prop_assert_eq!(schema::check(&frame.msg_name, Direction::FromTerminal, &frame.tlv), []);
+    }
+
+    #[test]
+    fn tlvs_survive_a_round_trip(tlv in strategy::tlv(8)) {
+        let back = Tlv::deserialize(&tlv.clone().serialize().unwrap());
+        prop_assert_eq!(back.data(), tlv.data());
+    }
+}
@@ -0,0 +1,40 @@
+#![cfg(feature = "schedule")]
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use vtk::schedule::Schedule;
+
+/// 2024-01-01 was a Monday.
+fn utc(day: u64, hour: u64, minute: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_704_067_200 + ((day * 24 + hour) * 60 + minute) * 60)
+}
+
+#[test]
+fn weekday_and_overnight_windows() {
+    let s = Schedule::parse("Mon-Fri 08:00-20:00, Sat 22:00-02:00", "UTC").unwrap();
+    assert!(!s.is_open_at(utc(0, 7, 59)));
+    assert!(s.is_open_at(utc(0, 8, 0)));
+    assert!(s.is_open_at(utc(4, 19, 59)));
+    assert!(!s.is_open_at(utc(4, 20, 0)));
+    assert!(s.is_open_at(utc(5, 23, 30)));
+    assert!(s.is_open_at(utc(6, 1, 59)));
+    assert!(!s.is_open_at(utc(6, 2, 0)));
+}
+
+#[test]
+fn hours_are_local_to_the_time_zone() {
+    let s: Schedule = "Europe/Moscow Mon 09:00-10:00".parse().unwrap();
+    assert!(s.is_open_at(utc(0, 6, 30)));
+    assert!(!s.is_open_at(utc(0, 9, 30)));
+    // Fri-Mon wraps around the week.
+    let s = Schedule::parse("Fri-Mon 00:00-24:00", "UTC").unwrap();
+    assert!(s.is_open_at(utc(6, 12, 0)) && s.is_open_at(utc(0, 12, 0)) && !s.is_open_at(utc(1, 12, 0)));
+}
+
+#[test]
+fn bad_specs_are_rejected() {
+    for spec in ["Mon 8-20", "Funday 08:00-20:00", "Mon 08:00-24:30", "Mon08:00-20:00"] {
+        assert!(Schedule::parse(spec, "UTC").is_err(), "{}", spec);
+    }
+    assert!(Schedule::new("Mars/Olympus_Mons").is_err());
+}
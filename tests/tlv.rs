@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use vtk::{schema::{self, Direction, Violation}, TagCategory, TagDiff, Tlv, TlvKey, ValueType, MAX_DISPLAY_TIME};
+
+#[test]
+fn durations_use_the_tag_s_unit() {
+    let mut tlv = Tlv::new();
+    tlv.set_duration(TlvKey::KeepaliveIntervalInSecs, Duration::from_millis(30_900)).unwrap();
+    tlv.set_duration(TlvKey::DisplayTimeInMs, Duration::from_millis(1500)).unwrap();
+    assert_eq!(tlv.get_bin(TlvKey::KeepaliveIntervalInSecs), Some(&[30][..]));
+    assert_eq!(tlv.get_bin(TlvKey::DisplayTimeInMs), Some(&[0x05, 0xDC][..]));
+    assert_eq!(tlv.get_duration(TlvKey::KeepaliveIntervalInSecs), Some(Duration::from_secs(30)));
+    assert_eq!(tlv.get_duration(TlvKey::DisplayTimeInMs), Some(Duration::from_millis(1500)));
+    assert!(tlv.set_duration(TlvKey::ProductId, Duration::from_secs(1)).is_err());
+    assert_eq!(tlv.get_duration(TlvKey::ProductId), None);
+}
+
+#[test]
+fn display_time_rounds_up_and_refuses_what_the_terminal_cannot_take() {
+    let mut tlv = Tlv::new();
+    tlv.set_display_time(Duration::from_micros(1)).unwrap();
+    assert_eq!(tlv.get_duration(TlvKey::DisplayTimeInMs), Some(Duration::from_millis(1)));
+    tlv.set_display_time(MAX_DISPLAY_TIME).unwrap();
+    assert_eq!(tlv.get_bin(TlvKey::DisplayTimeInMs), Some(&[0xFF, 0xFF, 0xFF, 0xFF][..]));
+    for refused in [Duration::ZERO, MAX_DISPLAY_TIME + Duration::from_millis(1)] {
+        assert!(tlv.set_display_time(refused).is_err());
+    }
+    assert_eq!(tlv.get_duration(TlvKey::DisplayTimeInMs), Some(MAX_DISPLAY_TIME));
+}
+
+#[test]
+fn numbers_take_the_fewest_bytes() {
+    let mut tlv = Tlv::new();
+    tlv.set_u8(TlvKey::EventNum, 0);
+    tlv.set_u16(TlvKey::ProductId, 0x00FF);
+    tlv.set_u32(TlvKey::OperationNum, 0x0001_0000);
+    tlv.set_u64(TlvKey::AmountInMinorCurrencyUnit, u64::MAX);
+    assert_eq!(tlv.get_bin(TlvKey::EventNum), Some(&[0][..]));
+    assert_eq!(tlv.get_bin(TlvKey::ProductId), Some(&[0xFF][..]));
+    assert_eq!(tlv.get_bin(TlvKey::OperationNum), Some(&[0x01, 0x00, 0x00][..]));
+    assert_eq!(tlv.get_uint(TlvKey::AmountInMinorCurrencyUnit), Some(u64::MAX));
+    tlv.set_bin(TlvKey::OutgoingByteCounter, &[0; 9]);
+    assert_eq!(tlv.get_uint(TlvKey::OutgoingByteCounter), None);
+}
+
+#[test]
+fn diff_lists_added_removed_and_changed_tags_in_tag_order() {
+    let mut golden = Tlv::new();
+    golden.set_str(TlvKey::MsgName, "VRP");
+    golden.set_u32(TlvKey::OperationNum, 7);
+    golden.set_str(TlvKey::ProductName, "Tea");
+    let mut answer = golden.clone();
+    answer.set_u32(TlvKey::OperationNum, 8);
+    answer.remove(TlvKey::ProductName);
+    answer.set_bin(TlvKey::Unknown(0x7F), &[0x00, 0x01]);
+    let diff = golden.diff(&answer);
+    assert_eq!(diff, [
+        TagDiff::Changed {key: TlvKey::OperationNum, from: vec![7], to: vec![8]},
+        TagDiff::Removed(TlvKey::ProductName, b"Tea".to_vec()),
+        TagDiff::Added(TlvKey::Unknown(0x7F), vec![0x00, 0x01]),
+    ]);
+    assert_eq!(diff.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+        "~ OperationNum 0x07 -> 0x08",
+        "- ProductName \"Tea\"",
+        "+ Unknown(127) <redacted 2 bytes>",
+    ]);
+    assert_eq!(golden.diff(&golden), []);
+}
+
+#[test]
+fn every_named_tag_has_a_category_and_numbers_are_checked() {
+    assert!(TlvKey::ALL.iter().all(|k| k.category().is_some()));
+    assert_eq!(TlvKey::Unknown(0x02).category(), None);
+    assert_eq!(TlvKey::QrCodeData.category(), Some(TagCategory::Display));
+    assert_eq!(TlvKey::OperationTimeoutInSecs.value_type(), ValueType::Seconds);
+    let mut tlv = Tlv::new();
+    tlv.set_bin(TlvKey::OperationNum, &[]);
+    tlv.set_bin(TlvKey::AmountInMinorCurrencyUnit, &[1; 9]);
+    assert_eq!(schema::check("VRP", Direction::ToTerminal, &tlv), [
+        Violation::BadValue(TlvKey::OperationNum),
+        Violation::BadValue(TlvKey::AmountInMinorCurrencyUnit),
+    ]);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn frames_export_as_json() {
+    use vtk::Frame;
+
+    let mut tlv = Tlv::new();
+    tlv.set_u16(TlvKey::AmountInMinorCurrencyUnit, 300);
+    tlv.set_bin(TlvKey::PosManagementData, &[0x00, 0xFF]);
+    let json = Frame::new("VRP", tlv).to_json();
+    assert_eq!(json["msg_name"], "VRP");
+    assert_eq!(json["tags"][0]["name"], "AmountInMinorCurrencyUnit");
+    assert_eq!(json["tags"][0]["value"], 300);
+    assert_eq!(json["tags"][1]["category"], "management");
+    assert_eq!(json["tags"][1]["hex"], "00 FF");
+    assert!(json["tags"][1]["value"].is_null());
+}
+
+#[test]
+fn long_values_use_ber_lengths_when_asked_to() {
+    use vtk::{Frame, LengthEncoding, ParseMode};
+
+    let mut tlv = Tlv::new();
+    tlv.set_bin(TlvKey::BankingReceipt, &[0x41; 200]);
+    let ber = tlv.clone().serialize_as(LengthEncoding::Ber).unwrap();
+    assert_eq!(ber[..3], [TlvKey::BankingReceipt.as_u8(), 0x81, 200]);
+    assert_eq!(tlv.clone().serialize().unwrap()[..2], [TlvKey::BankingReceipt.as_u8(), 200]);
+    let frame = Frame::new("VRP", tlv).encode_as(LengthEncoding::Ber).unwrap();
+    let (back, used) = Frame::decode_as(&frame, ParseMode::Strict, LengthEncoding::Ber, &mut Vec::new()).unwrap();
+    assert_eq!(used, frame.len());
+    assert_eq!(back.tlv.get_bin(TlvKey::BankingReceipt), Some(&[0x41; 200][..]));
+    assert_eq!("ber".parse::<LengthEncoding>().unwrap(), LengthEncoding::Ber);
+}
+
+#[test]
+fn values_too_long_for_their_lengths_are_refused() {
+    use std::io::ErrorKind;
+    use vtk::{Frame, LengthEncoding};
+
+    let mut tlv = Tlv::new();
+    tlv.set_bin(TlvKey::BankingReceipt, &[0x41; 256]);
+    assert_eq!(tlv.clone().serialize().unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(tlv.clone().serialize_as(LengthEncoding::Ber).unwrap()[..4], [TlvKey::BankingReceipt.as_u8(), 0x82, 1, 0]);
+    let mut huge = Tlv::new();
+    huge.set_bin(TlvKey::BankingReceipt, &[0x41; 0xFFFF]);
+    assert_eq!(Frame::new("VRP", huge).encode_as(LengthEncoding::Ber).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn debug_and_diffs_mask_sensitive_tags() {
+    let mut before = Tlv::new();
+    before.set_str(TlvKey::MsgName, "VRP");
+    before.set_str(TlvKey::BankingReceipt, "CARD ****1234");
+    let mut after = before.clone();
+    after.set_str(TlvKey::BankingReceipt, "CARD ****5678");
+    assert_eq!(format!("{:?}", before), r#"{MsgName: "VRP", BankingReceipt: <redacted 13 bytes>}"#);
+    let diff = before.diff(&after);
+    assert_eq!(diff.iter().map(|d| format!("{} {:?}", d, d)).collect::<Vec<_>>(),
+        ["~ BankingReceipt <redacted 13 bytes> -> <redacted 13 bytes> ~ BankingReceipt <redacted 13 bytes> -> <redacted 13 bytes>"]);
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::scratch;
+use vtk::{schema::Direction, trace::{self, BinaryRecorder, JsonlRecorder, Recorder, RecordingFormat, RotatingRecorder, TraceEntry}, Frame, Rotate, Tlv, TlvKey};
+
+fn vrp(op: u8, amount: &[u8]) -> Vec<u8> {
+    let mut tlv = Tlv::new();
+    tlv.set_bin(TlvKey::OperationNum, &[op]);
+    tlv.set_bin(TlvKey::AmountInMinorCurrencyUnit, amount);
+    Frame::new("VRP", tlv).encode().unwrap()
+}
+
+fn session() -> Vec<TraceEntry> {
+    vec![
+        TraceEntry {at_ms: 1_000, direction: Direction::ToTerminal, raw: vrp(7, &[0x01, 0xF4])},
+        TraceEntry {at_ms: 13_250, direction: Direction::FromTerminal, raw: vrp(7, &[0x01, 0xF4])},
+        TraceEntry {at_ms: 13_300, direction: Direction::FromTerminal, raw: vec![0x00, 0x01]},
+    ]
+}
+
+#[test]
+fn jsonl_round_trips() {
+    let session = session();
+    let mut recorder = JsonlRecorder::new(Vec::new());
+    for entry in &session {
+        recorder.record(entry).unwrap();
+    }
+    let out = recorder.into_inner();
+    assert_eq!(trace::read_jsonl(&out[..]).unwrap(), session);
+    assert!(trace::read_jsonl(&b"{\"at_ms\":1,\"dir\":\"sideways\",\"frame\":\"00\"}\n"[..]).is_err());
+    assert!(trace::read_jsonl("{\"at_ms\":1,\"dir\":\"sent\",\"frame\":\"aéb\"}\n".as_bytes()).is_err());
+}
+
+#[test]
+fn diagrams_show_key_fields_and_gaps() {
+    assert_eq!(trace::mermaid(&session()), "sequenceDiagram
+    participant POS
+    participant Terminal
+    POS->>Terminal: VRP op=7 amount=500
+    Note over POS,Terminal: +12.2 s
+    Terminal-->>POS: VRP op=7 amount=500
+    Terminal-->>POS: 2 undecodable bytes
+");
+    assert!(trace::plantuml(&session()).contains("... +12.2 s ...\nTerminal --> POS: VRP op=7 amount=500\n"));
+}
+
+#[test]
+fn binary_round_trips_across_appends_and_converts_to_jsonl() {
+    let session = session();
+    let path = std::env::temp_dir().join(format!("vtk-trace-{}.vtkrec", std::process::id()));
+    _ = std::fs::remove_file(&path);
+    // A restart between the second and third frame: the new recorder appends with an absolute time.
+    for part in [&session[..2], &session[2..]] {
+        trace::write_recording(&mut BinaryRecorder::open(&path).unwrap(), part).unwrap();
+    }
+    let binary = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(trace::read_recording(&binary[..]).unwrap(), session);
+
+    let mut jsonl = JsonlRecorder::new(Vec::new());
+    trace::write_recording(&mut jsonl, &trace::read_binary(&binary[..]).unwrap()).unwrap();
+    let jsonl = jsonl.into_inner();
+    assert!(binary.len() * 2 < jsonl.len());
+    assert_eq!(trace::read_recording(&jsonl[..]).unwrap(), session);
+    assert!(trace::read_binary(&binary[..binary.len() - 1]).is_err());
+}
+
+#[test]
+fn rotated_binary_parts_read_on_their_own_and_old_ones_go() {
+    let dir = scratch("trace-rotate");
+    let path = dir.join("session.vtkrec");
+    let rotate = Rotate {max_bytes: Some(1), keep: Some(2), gzip: cfg!(feature = "gzip"), ..Rotate::default()};
+    let mut recorder = RotatingRecorder::open(&path, RecordingFormat::for_path(&path), rotate).unwrap();
+    let entries: Vec<TraceEntry> = session().into_iter().cycle().take(5).enumerate()
+        .map(|(n, e)| TraceEntry {at_ms: 1_000 * n as u64, ..e}).collect();
+    trace::write_recording(&mut recorder, &entries).unwrap();
+    let mut parts: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().path()).filter(|p| *p != path).collect();
+    parts.sort();
+    assert_eq!(parts.len(), 2);
+    assert!(parts.iter().all(|p| p.extension().is_some_and(|ext| ext == "gz") == cfg!(feature = "gzip")));
+    let read = |p: &std::path::Path| -> Vec<TraceEntry> {
+        let bytes = std::fs::read(p).unwrap();
+        #[cfg(feature = "gzip")]
+        let bytes = match p.extension() {
+            Some(ext) if ext == "gz" => {
+                use std::io::Read;
+                let mut out = Vec::new();
+                flate2::read::GzDecoder::new(&bytes[..]).read_to_end(&mut out).unwrap();
+                out
+            },
+            _ => bytes,
+        };
+        trace::read_binary(&bytes[..]).unwrap()
+    };
+    // One entry per part: the first two parts went past `keep`.
+    assert_eq!([read(&parts[0]), read(&parts[1]), read(&path)].concat(), entries[2..]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
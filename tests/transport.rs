@@ -0,0 +1,400 @@
+mod common;
+
+use std::{io::{Error, ErrorKind, Read, Write}, net::{TcpListener, TcpStream}, sync::{Arc, Mutex}, thread, time::Duration};
+
+use common::echo_terminal;
+use vtk::{transport::{mem, Connector, Transport}, Anomaly, Config, Frame, ParseMode, Role, SaleOutcome, Tlv, TlvKey, Vtk};
+
+#[test]
+fn sale_over_memory() {
+    let (client, terminal) = mem::pair();
+    let terminal = echo_terminal(terminal, 3);
+    let mut vtk = Vtk::builder("unused", 0).connector(client.clone()).build().unwrap();
+    vtk.idle(None).unwrap();
+    let payment = vtk.request_payment(120, None, Duration::from_secs(30)).unwrap();
+    assert!(payment.approved);
+    let answer = vtk.finalize(payment.operation, 120).unwrap();
+    assert_eq!(answer.get_str(TlvKey::MsgName), Some("FIN"));
+    assert_eq!(terminal.join().unwrap(), ["IDL", "VRP", "FIN"]);
+    assert_eq!(client.pending(), 0);
+}
+
+#[test]
+fn silence_times_out() {
+    let (client, _terminal) = mem::pair();
+    let mut vtk = Vtk::builder("unused", 0).read_timeout(Duration::from_millis(20)).connector(client).build().unwrap();
+    let err = vtk.idle(Some(Tlv::new())).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn failures_reach_the_error_sink_but_quiet_receives_do_not() {
+    use vtk::{ErrorReport, ErrorSink};
+
+    struct Collect(Arc<Mutex<Vec<String>>>);
+    impl ErrorSink for Collect {
+        fn report(&mut self, report: &ErrorReport<'_>) {
+            self.0.lock().unwrap().push(format!("{} {:?}", report.request.unwrap_or("-"), report.error.kind()));
+        }
+    }
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let (client, _terminal) = mem::pair();
+    let mut vtk = Vtk::builder("unused", 0).read_timeout(Duration::from_millis(20)).connector(client)
+        .error_sink(Collect(reports.clone())).build().unwrap();
+    vtk.receive(Duration::from_millis(20)).unwrap_err();
+    vtk.idle(Some(Tlv::new())).unwrap_err();
+    assert_eq!(*reports.lock().unwrap(), ["IDL TimedOut"]);
+}
+
+#[test]
+fn long_qr_payloads_fall_back_or_are_refused() {
+    let (client, end) = mem::pair();
+    let terminal = echo_terminal(end, 1);
+    let mut vtk = Vtk::builder("unused", 0).connector(client)
+        .qr_template("https://pay.example/checkout?operation={op}&amount={amount}").qr_limit(32, Some("https://s.example/{op}"))
+        .build().unwrap();
+    let payment = vtk.request_payment(150, None, Duration::from_secs(5)).unwrap();
+    assert_eq!(payment.response.get_str(TlvKey::QrCodeData), Some("https://s.example/1"));
+    assert_eq!(terminal.join().unwrap(), ["VRP"]);
+
+    let (client, _terminal) = mem::pair();
+    let mut vtk = Vtk::builder("unused", 0).connector(client).qr_limit(8, None).build().unwrap();
+    assert_eq!(vtk.show_qr("https://pay.example/1").unwrap_err().kind(), ErrorKind::InvalidInput);
+}
+
+/// Refuses every other connection attempt, like a terminal that keeps dropping off the network.
+struct Flaky {
+    end: mem::Endpoint,
+    attempts: u32,
+}
+
+impl Connector for Flaky {
+    fn connect(&mut self, _config: &Config) -> Result<Box<dyn Transport>, Error> {
+        self.attempts += 1;
+        if self.attempts.is_multiple_of(2) {
+            return Err(Error::new(ErrorKind::ConnectionRefused, "flaky"));
+        }
+        Ok(Box::new(self.end.clone()))
+    }
+}
+
+#[test]
+fn disconnect_racing_send_and_receive_is_an_error_not_a_panic() {
+    let (client, _terminal) = mem::pair();
+    let vtk = Vtk::builder("unused", 0).connector(Flaky {end: client, attempts: 0}).build().unwrap();
+    let vtk = Arc::new(Mutex::new(vtk));
+    let dropper = {
+        let vtk = vtk.clone();
+        thread::spawn(move || for _ in 0..200 {
+            vtk.lock().unwrap().disconnect();
+            thread::yield_now();
+        })
+    };
+    let (mut sent, mut refused) = (0, 0);
+    for _ in 0..200 {
+        let mut vtk = vtk.lock().unwrap();
+        match vtk.send("IDL", Tlv::new()) {
+            Ok(()) => sent += 1,
+            // Busy: the dropper did not get in between, so the last IDL is still unanswered.
+            Err(e) => {
+                assert!(matches!(e.kind(), ErrorKind::ConnectionRefused | ErrorKind::ResourceBusy), "{}", e);
+                refused += 1;
+            },
+        }
+        assert!(vtk.receive(Duration::from_millis(1)).is_err());
+    }
+    dropper.join().unwrap();
+    assert_eq!(sent + refused, 200);
+    assert!(sent > 0);
+}
+
+#[test]
+fn payment_after_a_terminal_reboot_goes_over_a_fresh_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut vtk = Vtk::builder("127.0.0.1", port).build().unwrap();
+    vtk.connect().unwrap();
+    // The terminal reboots: the old socket is closed and a new one is accepted later.
+    drop(listener.accept().unwrap());
+    thread::sleep(Duration::from_millis(50));
+    let terminal = thread::spawn(move || {
+        let (mut tcp, _) = listener.accept().unwrap();
+        let mut buf = [0; 512];
+        let n = tcp.read(&mut buf).unwrap();
+        let (frame, _) = Frame::decode(&buf[..n]).unwrap();
+        tcp.write_all(&Frame::new(&frame.msg_name, frame.tlv.clone()).encode().unwrap()).unwrap();
+        frame.msg_name
+    });
+    let payment = vtk.request_payment(120, None, Duration::from_secs(5)).unwrap();
+    assert!(payment.approved);
+    assert_eq!(terminal.join().unwrap(), "VRP");
+}
+
+#[test]
+fn a_reboot_during_the_payment_leaves_it_unresolved() {
+    use vtk::{FileJournal, VtkError};
+
+    let path = std::env::temp_dir().join(format!("vtk-transport-reboot-{}.log", std::process::id()));
+    _ = std::fs::remove_file(&path);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let terminal = thread::spawn(move || {
+        let (mut tcp, _) = listener.accept().unwrap();
+        let n = tcp.read(&mut [0; 512]).unwrap();
+        assert!(n > 0);
+        listener
+    });
+    let mut vtk = Vtk::builder("127.0.0.1", port).journal(FileJournal::open(&path).unwrap()).build().unwrap();
+    let e = vtk.request_payment(120, None, Duration::from_secs(5)).unwrap_err();
+    assert!(matches!(VtkError::of(&e), Some(VtkError::Interrupted {operation: 1, ..})), "{}", e);
+    let listener = terminal.join().unwrap();
+    // Nothing was sent after the connection dropped: no ABR guessing at the outcome.
+    listener.set_nonblocking(true).unwrap();
+    assert_eq!(listener.accept().unwrap_err().kind(), ErrorKind::WouldBlock);
+    let unresolved = vtk.journal().unwrap().list_unresolved().unwrap();
+    assert_eq!(unresolved.iter().map(|e| (e.operation, e.amount)).collect::<Vec<_>>(), [(1, 120)]);
+    drop(vtk);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn a_terminal_gone_after_a_reboot_leaves_the_payment_unknown() {
+    use vtk::{RetryPolicy, VtkError};
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let terminal = thread::spawn(move || {
+        let (mut tcp, _) = listener.accept().unwrap();
+        assert!(tcp.read(&mut [0; 512]).unwrap() > 0);
+    });
+    let mut vtk = Vtk::builder("127.0.0.1", port).retry(RetryPolicy {attempts: 1, backoff: Duration::ZERO, ..RetryPolicy::default()}).build().unwrap();
+    let e = vtk.request_payment(120, None, Duration::from_secs(5)).unwrap_err();
+    terminal.join().unwrap();
+    assert!(matches!(VtkError::of(&e), Some(VtkError::Interrupted {operation: 1, ..})), "{}", e);
+    assert_eq!(VtkError::of(&e).map(VtkError::code), Some(5));
+}
+
+#[test]
+fn answers_to_other_operations_are_skipped() {
+    let (client, mut terminal) = mem::pair();
+    let inbox = client.clone();
+    let terminal = thread::spawn(move || {
+        let mut buf = [0; 512];
+        let n = terminal.read(&mut buf, Duration::from_secs(5)).unwrap();
+        let (frame, _) = Frame::decode(&buf[..n]).unwrap();
+        let mut stale = frame.tlv.clone();
+        stale.set_bin(TlvKey::OperationNum, &[0x0F, 0x42, 0x3F]);
+        terminal.write_all(&Frame::new("VRP", stale).encode().unwrap()).unwrap();
+        while inbox.pending() > 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        terminal.write_all(&Frame::new("VRP", frame.tlv).encode().unwrap()).unwrap();
+    });
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    let payment = vtk.request_payment(120, None, Duration::from_secs(5)).unwrap();
+    terminal.join().unwrap();
+    assert!(payment.approved);
+    assert!(vtk.warnings().contains(&Anomaly::StaleAnswer {operation: 999_999}));
+}
+
+#[test]
+fn second_send_before_the_answer_is_busy_unless_queued() {
+    use vtk::VtkError;
+
+    let (client, _terminal) = mem::pair();
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    vtk.send("IDL", Tlv::new()).unwrap();
+    let err = vtk.send("DIS", Tlv::new()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ResourceBusy);
+    assert!(matches!(VtkError::of(&err), Some(VtkError::Busy {pending, request}) if pending == "IDL" && request == "DIS"));
+    assert_eq!(VtkError::of(&err).and_then(VtkError::retry_after), Some(Duration::ZERO));
+
+    let (client, terminal) = mem::pair();
+    let terminal = echo_terminal(terminal, 2);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).queue_sends(true).build().unwrap();
+    vtk.send("IDL", Tlv::new()).unwrap();
+    vtk.send("DIS", Tlv::new()).unwrap();
+    assert_eq!(vtk.receive(Duration::from_secs(5)).unwrap().get_str(TlvKey::MsgName), Some("IDL"));
+    assert_eq!(vtk.receive(Duration::from_secs(5)).unwrap().get_str(TlvKey::MsgName), Some("DIS"));
+    assert_eq!(terminal.join().unwrap(), ["IDL", "DIS"]);
+}
+
+#[test]
+fn sale_with_the_terminal_dialling_in() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let terminal = thread::spawn(move || {
+        let mut seen = Vec::new();
+        while seen.len() < 3 {
+            // Terminals set up this way dial back in whenever the POS hangs up.
+            let Ok(mut tcp) = TcpStream::connect(("127.0.0.1", port)) else {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            };
+            let mut buf = [0; 512];
+            while let Ok(n @ 1..) = tcp.read(&mut buf) {
+                let (frame, _) = Frame::decode(&buf[..n]).unwrap();
+                tcp.write_all(&Frame::new(&frame.msg_name, frame.tlv).encode().unwrap()).unwrap();
+                seen.push(frame.msg_name);
+            }
+        }
+        seen
+    });
+    let mut vtk = Vtk::builder("127.0.0.1", port).role(Role::Server).bind_addr([127, 0, 0, 1].into()).build().unwrap();
+    vtk.idle(None).unwrap();
+    assert_eq!(vtk.listen_addr().map(|a| a.port()), Some(port));
+    let payment = vtk.request_payment(120, None, Duration::from_secs(30)).unwrap();
+    vtk.finalize(payment.operation, 120).unwrap();
+    vtk.disconnect();
+    assert_eq!(terminal.join().unwrap(), ["IDL", "VRP", "FIN"]);
+}
+
+#[test]
+fn replayed_events_are_dropped() {
+    let (client, mut terminal) = mem::pair();
+    let mut vtk = Vtk::builder("unused", 0).connector(client).event_dedup(Duration::from_secs(60)).build().unwrap();
+    let event = |num| {
+        let mut tlv = Tlv::new();
+        tlv.set_str(TlvKey::EventName, "PAID");
+        tlv.set_u8(TlvKey::EventNum, num);
+        tlv.set_u8(TlvKey::OperationNum, 1);
+        Frame::new("IDL", tlv).encode().unwrap()
+    };
+    terminal.write_all(&event(7)).unwrap();
+    assert_eq!(vtk.receive(Duration::from_secs(1)).unwrap().get_uint(TlvKey::EventNum), Some(7));
+    terminal.write_all(&event(7)).unwrap();
+    assert_eq!(vtk.receive(Duration::from_millis(20)).unwrap_err().kind(), ErrorKind::TimedOut);
+    assert!(vtk.warnings().contains(&Anomaly::ReplayedEvent {event: 7, operation: Some(1)}));
+    terminal.write_all(&event(8)).unwrap();
+    assert_eq!(vtk.receive(Duration::from_secs(1)).unwrap().get_uint(TlvKey::EventNum), Some(8));
+}
+
+#[test]
+fn extra_tags_ride_in_the_sale_request() {
+    let (client, mut terminal) = mem::pair();
+    let terminal = thread::spawn(move || {
+        let mut buf = [0; 512];
+        let n = terminal.read(&mut buf, Duration::from_secs(5)).unwrap();
+        let (frame, _) = Frame::decode(&buf[..n]).unwrap();
+        let mut answer = Tlv::new();
+        answer.set_u32(TlvKey::OperationNum, frame.tlv.get_uint(TlvKey::OperationNum).unwrap() as u32);
+        terminal.write_all(&Frame::new("VRP", answer).encode().unwrap()).unwrap();
+        frame.tlv
+    });
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    let mut config = vtk.config().clone();
+    config.parse_mode = ParseMode::Strict;
+    vtk.reconfigure(config);
+    let mut extras = Tlv::new();
+    extras.set_bin(TlvKey::PosManagementData, b"LOYALTY:42");
+    extras.set_bin(TlvKey::Unknown(0x7A), &[0x05]);
+    let payment = vtk.request_payment_with(120, None, &extras, Duration::from_secs(30)).unwrap();
+    assert!(payment.approved);
+    let sent = terminal.join().unwrap();
+    assert_eq!(sent.get_bin(TlvKey::PosManagementData), Some(&b"LOYALTY:42"[..]));
+    assert_eq!(sent.get_bin(TlvKey::Unknown(0x7A)), Some(&[0x05][..]));
+
+    let mut clash = Tlv::new();
+    clash.set_u32(TlvKey::AmountInMinorCurrencyUnit, 1);
+    let err = vtk.request_payment_with(120, None, &clash, Duration::from_secs(30)).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn a_hold_is_captured_or_voided() {
+    let (client, terminal) = mem::pair();
+    let terminal = echo_terminal(terminal, 4);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    let held = vtk.preauthorize(500, None, Duration::from_secs(30)).unwrap();
+    assert!(held.approved);
+    assert_eq!(vtk.preauthorize(500, None, Duration::from_secs(30)).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(vtk.capture(501).unwrap_err().kind(), ErrorKind::InvalidInput);
+    let record = vtk.capture(320).unwrap();
+    assert_eq!((record.operation_num, record.amount), (held.operation, 320));
+    assert!(vtk.hold().is_none());
+    assert_eq!(vtk.capture(320).unwrap_err().kind(), ErrorKind::NotFound);
+
+    let held = vtk.preauthorize(500, None, Duration::from_secs(30)).unwrap();
+    assert_eq!(vtk.hold().map(|h| h.operation), Some(held.operation));
+    assert_eq!(vtk.void().unwrap().operation_num, held.operation);
+    assert!(vtk.hold().is_none());
+    assert_eq!(terminal.join().unwrap(), ["VRP", "FIN", "VRP", "ABR"]);
+}
+
+#[test]
+fn adjusted_fin_reports_the_accepted_amount() {
+    let (client, mut terminal) = mem::pair();
+    let terminal = thread::spawn(move || {
+        let mut buf = [0; 512];
+        let mut fins = Vec::new();
+        while fins.len() < 2 {
+            let n = terminal.read(&mut buf, Duration::from_secs(5)).unwrap();
+            let (mut frame, _) = Frame::decode(&buf[..n]).unwrap();
+            if frame.msg_name == "FIN" {
+                fins.push(frame.tlv.get_uint(TlvKey::AmountInMinorCurrencyUnit).unwrap());
+                // Rounds to whole units, as some firmware does.
+                frame.tlv.set_u64(TlvKey::AmountInMinorCurrencyUnit, fins.last().unwrap() / 100 * 100);
+            }
+            terminal.write_all(&Frame::new(&frame.msg_name, frame.tlv).encode().unwrap()).unwrap();
+        }
+        fins
+    });
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    let payment = vtk.request_payment(1000, None, Duration::from_secs(30)).unwrap();
+    assert_eq!(vtk.finalize_adjusted(&payment, 50).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(vtk.finalize_adjusted(&payment, -1001).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(vtk.finalize_adjusted(&payment, -250).unwrap().amount, 700);
+
+    let mut config = vtk.config().clone();
+    config.compat.fin_above_authorized = true;
+    vtk.reconfigure(config);
+    let payment = vtk.request_payment(1000, None, Duration::from_secs(30)).unwrap();
+    assert_eq!(vtk.finalize_adjusted(&payment, 150).unwrap().amount, 1100);
+    assert_eq!(terminal.join().unwrap(), [750, 1150]);
+}
+
+#[test]
+fn partial_dispenses_charge_what_came_out() {
+    let (client, terminal) = mem::pair();
+    let terminal = echo_terminal(terminal, 4);
+    let mut vtk = Vtk::builder("unused", 0).connector(client).build().unwrap();
+    let payment = vtk.request_payment(300, None, Duration::from_secs(30)).unwrap();
+    assert_eq!(vtk.settle_partial(&payment, 301).unwrap_err().kind(), ErrorKind::InvalidInput);
+    assert_eq!(vtk.settle_partial(&payment, 200).unwrap().amount, 200);
+    let payment = vtk.request_payment(300, None, Duration::from_secs(30)).unwrap();
+    assert_eq!(vtk.settle_partial(&payment, 0).unwrap().outcome, SaleOutcome::Aborted);
+    assert_eq!(terminal.join().unwrap(), ["VRP", "FIN", "VRP", "ABR"]);
+}
+
+#[test]
+fn errors_do_not_show_what_follows_the_message_name() {
+    let (client, mut terminal) = mem::pair();
+    let terminal = thread::spawn(move || {
+        let mut buf = [0; 512];
+        let n = terminal.read(&mut buf, Duration::from_secs(5)).unwrap();
+        let (frame, _) = Frame::decode(&buf[..n]).unwrap();
+        let mut answer = frame.tlv.clone();
+        answer.set_str(TlvKey::BankingReceipt, "CARD ****1234");
+        terminal.write_all(&Frame::new("VRP", answer).encode().unwrap()).unwrap();
+    });
+    struct Received(Arc<Mutex<Vec<u8>>>);
+    impl vtk::ErrorSink for Received {
+        fn report(&mut self, report: &vtk::ErrorReport<'_>) {
+            self.0.lock().unwrap().clone_from(&report.last_frames.received);
+        }
+    }
+    let reported = Arc::new(Mutex::new(Vec::new()));
+    let mut vtk = Vtk::builder("unused", 0).connector(client).error_sink(Received(reported.clone())).build().unwrap();
+    let mut config = vtk.config().clone();
+    config.parse_mode = ParseMode::Strict;
+    vtk.reconfigure(config);
+    // Strict mode refuses the OperationTimeoutInSecs echoed back.
+    let e = vtk.request_payment(120, None, Duration::from_secs(30)).unwrap_err();
+    terminal.join().unwrap();
+    let shown = format!("{} {:?}", e, e);
+    assert!(shown.contains("56 52 50"), "{}", shown);
+    assert!(!shown.contains("43 41 52 44") && !shown.contains("67, 65, 82, 68"), "{}", shown);
+    let reported = reported.lock().unwrap();
+    assert!(reported.windows(3).any(|w| w == b"VRP") && !reported.windows(4).any(|w| w == b"CARD"), "{:?}", reported);
+    assert!(vtk::logging::is_sensitive(TlvKey::Unknown(0x7A)));
+}